@@ -0,0 +1,249 @@
+//! Cost of importing the shm buffer of a 4K terminal that redraws a single line,
+//! with a full upload into a new texture versus a damage-limited upload into the texture of the surface.
+//!
+//! Needs a GPU: `cargo bench --bench shm_upload`.
+//! The render node defaults to `/dev/dri/renderD128` and can be changed with `VESHELL_BENCH_RENDER_NODE`.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use smithay::backend::allocator::gbm::GbmDevice;
+use smithay::backend::egl::{EGLContext, EGLDisplay};
+use smithay::backend::renderer::gles::GlesRenderer;
+use smithay::backend::renderer::ImportAll;
+use smithay::reexports::wayland_server::backend::ClientData;
+use smithay::reexports::wayland_server::protocol::wl_buffer::WlBuffer;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::reexports::wayland_server::{Client, Display};
+use smithay::utils::{Buffer as BufferCoords, Rectangle};
+use smithay::wayland::buffer::BufferHandler;
+use smithay::wayland::compositor::{
+    with_states, CompositorClientState, CompositorHandler, CompositorState,
+};
+use smithay::wayland::shm::{ShmHandler, ShmState};
+use smithay::{delegate_compositor, delegate_shm};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{
+    wl_buffer, wl_compositor, wl_registry, wl_shm, wl_shm_pool, wl_surface,
+};
+use wayland_client::{delegate_noop, Connection, Dispatch, Proxy, QueueHandle};
+
+const WIDTH: i32 = 3840;
+const HEIGHT: i32 = 2160;
+/// A line of a terminal with a 12pt font at 2x scale.
+const LINE_HEIGHT: i32 = 40;
+
+fn renderer() -> GlesRenderer {
+    let path = std::env::var("VESHELL_BENCH_RENDER_NODE")
+        .unwrap_or_else(|_| "/dev/dri/renderD128".to_string());
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap_or_else(|err| panic!("Failed to open {}: {}", path, err));
+    let gbm_device = GbmDevice::new(file).expect("Failed to create the gbm device");
+    let egl_display = unsafe { EGLDisplay::new(gbm_device) }.expect("Failed to create EGLDisplay");
+    let egl_context = EGLContext::new(&egl_display).expect("Failed to create EGLContext");
+    unsafe { GlesRenderer::new(egl_context) }.expect("Failed to create the renderer")
+}
+
+/// Waits for the upload to land, the compositor fences it but the GPU still does the work.
+fn finish(renderer: &mut GlesRenderer) {
+    renderer.with_context(|gl| unsafe { gl.Finish() }).unwrap();
+}
+
+struct BenchState {
+    compositor_state: CompositorState,
+    shm_state: ShmState,
+}
+
+#[derive(Default)]
+struct BenchClientData {
+    compositor_state: CompositorClientState,
+}
+
+impl ClientData for BenchClientData {}
+
+impl CompositorHandler for BenchState {
+    fn compositor_state(&mut self) -> &mut CompositorState {
+        &mut self.compositor_state
+    }
+
+    fn client_compositor_state<'a>(&self, client: &'a Client) -> &'a CompositorClientState {
+        &client
+            .get_data::<BenchClientData>()
+            .unwrap()
+            .compositor_state
+    }
+
+    fn commit(&mut self, _surface: &WlSurface) {}
+}
+
+impl BufferHandler for BenchState {
+    fn buffer_destroyed(&mut self, _buffer: &WlBuffer) {}
+}
+
+impl ShmHandler for BenchState {
+    fn shm_state(&self) -> &ShmState {
+        &self.shm_state
+    }
+}
+
+delegate_compositor!(BenchState);
+delegate_shm!(BenchState);
+
+struct BenchClient;
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for BenchClient {
+    fn event(
+        _state: &mut Self,
+        _registry: &wl_registry::WlRegistry,
+        _event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _connection: &Connection,
+        _queue_handle: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(BenchClient: wl_compositor::WlCompositor);
+delegate_noop!(BenchClient: ignore wl_surface::WlSurface);
+delegate_noop!(BenchClient: ignore wl_shm::WlShm);
+delegate_noop!(BenchClient: wl_shm_pool::WlShmPool);
+delegate_noop!(BenchClient: ignore wl_buffer::WlBuffer);
+
+/// Runs a client that creates a surface and a 4K shm buffer, and keeps them alive until `quit`.
+/// Returns the protocol ids of the surface and of the buffer.
+fn spawn_client(stream: UnixStream, quit: mpsc::Receiver<()>) -> mpsc::Receiver<(u32, u32)> {
+    let (ids_sender, ids) = mpsc::channel();
+    std::thread::spawn(move || {
+        let connection = Connection::from_socket(stream).unwrap();
+        let (globals, mut queue) = registry_queue_init::<BenchClient>(&connection).unwrap();
+        let queue_handle = queue.handle();
+        let compositor: wl_compositor::WlCompositor =
+            globals.bind(&queue_handle, 1..=1, ()).unwrap();
+        let shm: wl_shm::WlShm = globals.bind(&queue_handle, 1..=1, ()).unwrap();
+
+        let len = (WIDTH * HEIGHT * 4) as usize;
+        let file = shm_file(len);
+        let pool = shm.create_pool(file.as_fd(), len as i32, &queue_handle, ());
+        let buffer = pool.create_buffer(
+            0,
+            WIDTH,
+            HEIGHT,
+            WIDTH * 4,
+            wl_shm::Format::Argb8888,
+            &queue_handle,
+            (),
+        );
+        let surface = compositor.create_surface(&queue_handle, ());
+        queue.roundtrip(&mut BenchClient).unwrap();
+
+        ids_sender
+            .send((surface.id().protocol_id(), buffer.id().protocol_id()))
+            .unwrap();
+        let _ = quit.recv();
+    });
+    ids
+}
+
+fn shm_file(len: usize) -> File {
+    let path = std::env::temp_dir().join(format!("veshell-shm-upload-{}", std::process::id()));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    file.write_all(&vec![0x80u8; len]).unwrap();
+    file
+}
+
+fn shm_upload(c: &mut Criterion) {
+    let mut renderer = renderer();
+
+    let mut display = Display::<BenchState>::new().unwrap();
+    let display_handle = display.handle();
+    let mut state = BenchState {
+        compositor_state: CompositorState::new::<BenchState>(&display_handle),
+        shm_state: ShmState::new::<BenchState>(&display_handle, vec![]),
+    };
+
+    let (server_stream, client_stream) = UnixStream::pair().unwrap();
+    let client = display_handle
+        .insert_client(server_stream, Arc::new(BenchClientData::default()))
+        .unwrap();
+    let (quit, quit_receiver) = mpsc::channel();
+    let ids = spawn_client(client_stream, quit_receiver);
+    let (surface_id, buffer_id) = loop {
+        display.dispatch_clients(&mut state).unwrap();
+        display.flush_clients().unwrap();
+        if let Ok(ids) = ids.recv_timeout(Duration::from_millis(1)) {
+            break ids;
+        }
+    };
+    let surface: WlSurface = client
+        .object_from_protocol_id(&display_handle, surface_id)
+        .unwrap();
+    let buffer: WlBuffer = client
+        .object_from_protocol_id(&display_handle, buffer_id)
+        .unwrap();
+
+    let full = [Rectangle::<i32, BufferCoords>::from_loc_and_size(
+        (0, 0),
+        (WIDTH, HEIGHT),
+    )];
+    let line = [Rectangle::<i32, BufferCoords>::from_loc_and_size(
+        (0, HEIGHT / 2),
+        (WIDTH, LINE_HEIGHT),
+    )];
+
+    let mut group = c.benchmark_group("shm_upload_4k");
+
+    group.bench_function("full_upload_new_texture", |b| {
+        b.iter(|| {
+            // Without the surface, the renderer has no texture to reuse.
+            let texture = renderer
+                .import_buffer(&buffer, None, &full)
+                .unwrap()
+                .unwrap();
+            finish(&mut renderer);
+            // Deleted by the renderer on the next import, like the textures the compositor retires.
+            drop(texture);
+        })
+    });
+
+    // The first import creates the texture of the surface, the next ones only upload the damage.
+    with_states(&surface, |surface_data| {
+        renderer
+            .import_buffer(&buffer, Some(surface_data), &full)
+            .unwrap()
+            .unwrap()
+    });
+    group.bench_function("damaged_line_upload", |b| {
+        b.iter(|| {
+            let texture = with_states(&surface, |surface_data| {
+                renderer
+                    .import_buffer(&buffer, Some(surface_data), &line)
+                    .unwrap()
+                    .unwrap()
+            });
+            finish(&mut renderer);
+            drop(texture);
+        })
+    });
+
+    group.finish();
+
+    let _ = quit.send(());
+}
+
+criterion_group!(benches, shm_upload);
+criterion_main!(benches);
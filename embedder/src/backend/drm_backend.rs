@@ -1,44 +1,67 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::os::fd::AsFd;
 use std::path::Path;
 use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 
 use rustix::fs::OFlags;
+use serde_json::json;
 use smithay::backend::allocator::dmabuf::{AnyError, AsDmabuf, Dmabuf, DmabufAllocator};
 use smithay::backend::allocator::gbm::GbmDevice;
 use smithay::backend::allocator::gbm::{GbmAllocator, GbmBufferFlags};
-use smithay::backend::allocator::{Allocator, Fourcc, Slot, Swapchain};
+use smithay::backend::allocator::{Allocator, Format, Fourcc, Slot, Swapchain};
 use smithay::backend::drm::compositor::DrmCompositor;
 use smithay::backend::drm::{
-    CreateDrmNodeError, DrmDevice, DrmDeviceFd, DrmError, DrmEvent, DrmNode, NodeType,
+    CreateDrmNodeError, DrmDevice, DrmDeviceFd, DrmError, DrmEvent, DrmEventMetadata, DrmEventTime,
+    DrmNode, NodeType,
 };
 use smithay::backend::egl;
 use smithay::backend::egl::{EGLContext, EGLDevice, EGLDisplay};
 use smithay::backend::libinput::{LibinputInputBackend, LibinputSessionInterface};
+use smithay::backend::renderer::element::surface::{
+    render_elements_from_surface_tree, WaylandSurfaceRenderElement,
+};
 use smithay::backend::renderer::element::texture::{TextureBuffer, TextureRenderElement};
 use smithay::backend::renderer::element::Kind;
 use smithay::backend::renderer::gles::ffi::Gles2;
 use smithay::backend::renderer::gles::{GlesRenderer, GlesTexture};
 use smithay::backend::renderer::{ImportDma, ImportEgl};
 use smithay::backend::session::libseat::LibSeatSession;
-use smithay::backend::session::{libseat, Session};
+use smithay::backend::session::{libseat, Session, SessionEvent};
 use smithay::backend::udev::{all_gpus, primary_gpu, UdevBackend, UdevEvent};
-use smithay::desktop::utils::OutputPresentationFeedback;
+use smithay::desktop::utils::{
+    surface_presentation_feedback_flags_from_states, take_presentation_feedback_surface_tree,
+    OutputPresentationFeedback,
+};
 use smithay::desktop::{Space, Window};
 use smithay::output::Mode;
 use smithay::output::{Output, PhysicalProperties, Subpixel};
 use smithay::reexports::calloop::channel::Event;
 use smithay::reexports::calloop::EventLoop;
 use smithay::reexports::calloop::RegistrationToken;
-use smithay::reexports::drm::control::{connector, crtc, Device, ModeTypeFlags};
+use smithay::reexports::drm::control::atomic::AtomicModeReq;
+use smithay::reexports::drm::control::{
+    connector, crtc, property, AtomicCommitFlags, Device, Mode as DrmMode, ModeTypeFlags,
+    ResourceHandle,
+};
 use smithay::reexports::drm::Device as _;
 use smithay::reexports::input::Libinput;
+use smithay::reexports::wayland_protocols::wp::linux_dmabuf::zv1::server::zwp_linux_dmabuf_feedback_v1;
+use smithay::reexports::wayland_protocols::wp::presentation_time::server::wp_presentation_feedback;
 use smithay::reexports::wayland_server::backend::GlobalId;
-use smithay::reexports::wayland_server::protocol::wl_shm;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
 use smithay::reexports::wayland_server::Display;
 use smithay::reexports::wayland_server::DisplayHandle;
-use smithay::utils::{Coordinate, DeviceFd, Point, Rectangle, Transform};
-use smithay::wayland::dmabuf::{DmabufFeedbackBuilder, DmabufState};
-use smithay::wayland::drm_lease::DrmLease;
+use smithay::utils::{
+    Coordinate, DeviceFd, Logical, Monotonic, Physical, Point, Rectangle, Scale, Size, Time,
+    Transform,
+};
+use smithay::wayland::dmabuf::{DmabufFeedback, DmabufFeedbackBuilder, DmabufState};
+use smithay::wayland::drm_lease::{
+    DrmLease, DrmLeaseBuilder, DrmLeaseHandler, DrmLeaseRequest, DrmLeaseState, LeaseRejected,
+};
+use smithay::wayland::drm_syncobj::{supports_syncobj_eventfd, DrmSyncPoint, DrmSyncobjState};
+use smithay::{delegate_drm_lease, render_elements};
 use tracing::{error, info, warn};
 
 use smithay_drm_extras::drm_scanner::{DrmScanEvent, DrmScanner};
@@ -47,9 +70,18 @@ use smithay_drm_extras::edid::EdidInfo;
 use crate::flutter_engine::platform_channels::binary_messenger::BinaryMessenger;
 use crate::flutter_engine::FlutterEngine;
 use crate::state;
-use crate::{flutter_engine::EmbedderChannels, send_frames_surface_tree, State};
+use crate::wayland::color_management::color_management::OutputColorCapabilities;
+use crate::wayland::security_context::security_context::{
+    can_view_privileged_global, PrivilegedProtocol,
+};
+use crate::{
+    flutter_engine::EmbedderChannels, output_canvas_geometry, set_dmabuf_feedback_surface_tree,
+    State,
+};
 
-use super::Backend;
+use super::mirroring::{mirror_destination, output_mirror};
+use super::monitor_profiles::{find_mode, MonitorIdentity};
+use super::{Backend, VrrMode};
 
 pub struct DrmBackend {
     pub session: LibSeatSession,
@@ -58,6 +90,10 @@ pub struct DrmBackend {
     pointer_images: Vec<(xcursor::parser::Image, TextureBuffer<GlesTexture>)>,
     pointer_image: crate::cursor::Cursor,
     highest_hz_crtc: Option<(i32, crtc::Handle)>,
+    /// Monitors turned back on, which need a frame to get VBlanks again.
+    crtcs_to_wake: Vec<crtc::Handle>,
+    /// Release points of the buffers that were on the planes of monitors turned off.
+    release_points_off_planes: Vec<DrmSyncPoint>,
 }
 
 impl Backend for DrmBackend {
@@ -70,6 +106,90 @@ impl Backend for DrmBackend {
     fn get_session(&self) -> LibSeatSession {
         self.session.clone()
     }
+
+    fn gamma_size(&self, output: &Output) -> Option<usize> {
+        let id = output.user_data().get::<UdevOutputId>()?;
+        let gpu_data = self.gpus.get(&id.device_id)?;
+        get_gamma_size(&gpu_data.drm_device, id.crtc)
+    }
+
+    fn set_gamma(&mut self, output: &Output, ramp: Option<Vec<u16>>) -> std::io::Result<()> {
+        let not_found = || std::io::Error::from(std::io::ErrorKind::NotFound);
+        let id = output
+            .user_data()
+            .get::<UdevOutputId>()
+            .ok_or_else(not_found)?;
+        let gpu_data = self.gpus.get_mut(&id.device_id).ok_or_else(not_found)?;
+        let surface = gpu_data.surfaces.get_mut(&id.crtc).ok_or_else(not_found)?;
+
+        set_crtc_gamma(&gpu_data.drm_device, id.crtc, ramp.as_deref())?;
+        // Kept to restore it when we come back from another VT.
+        surface.gamma_ramp = ramp;
+        Ok(())
+    }
+
+    fn set_output_mode(&mut self, output: &Output, mode: Mode) -> std::io::Result<()> {
+        let not_found = || std::io::Error::from(std::io::ErrorKind::NotFound);
+        let id = output
+            .user_data()
+            .get::<UdevOutputId>()
+            .ok_or_else(not_found)?;
+        let gpu_data = self.gpus.get_mut(&id.device_id).ok_or_else(not_found)?;
+        let surface = gpu_data.surfaces.get_mut(&id.crtc).ok_or_else(not_found)?;
+        let drm_mode = surface
+            .modes
+            .iter()
+            .find(|drm_mode| Mode::from(**drm_mode) == mode)
+            .copied()
+            .ok_or_else(not_found)?;
+
+        surface
+            .compositor
+            .use_mode(drm_mode)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))
+    }
+
+    fn set_output_power(&mut self, output: &Output, on: bool) -> std::io::Result<()> {
+        let not_found = || std::io::Error::from(std::io::ErrorKind::NotFound);
+        let id = output
+            .user_data()
+            .get::<UdevOutputId>()
+            .ok_or_else(not_found)?;
+        let gpu_data = self.gpus.get_mut(&id.device_id).ok_or_else(not_found)?;
+        let surface = gpu_data.surfaces.get_mut(&id.crtc).ok_or_else(not_found)?;
+
+        if on {
+            surface
+                .compositor
+                .reset_state()
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+            // There's no VBlank to render the next frame on until one is queued.
+            self.crtcs_to_wake.push(id.crtc);
+        } else {
+            // Disables the CRTC, which turns the monitor off.
+            surface
+                .compositor
+                .clear()
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+            self.release_points_off_planes
+                .extend(surface.take_plane_release_points());
+            surface.last_vblank_time = None;
+        }
+        surface.powered_off = !on;
+
+        self.update_highest_hz_crtc();
+        Ok(())
+    }
+
+    fn monitor_layout_changed(&mut self, _outputs: &[Output], canvas_size: Size<i32, Logical>) {
+        for gpu_data in self.gpus.values_mut() {
+            gpu_data
+                .swapchain
+                .resize(canvas_size.w as u32, canvas_size.h as u32);
+        }
+
+        self.update_highest_hz_crtc();
+    }
 }
 
 impl DrmBackend {
@@ -80,6 +200,68 @@ impl DrmBackend {
     fn get_gpu_data_mut(&mut self) -> &mut GpuData {
         self.gpus.get_mut(&self.primary_gpu).unwrap()
     }
+
+    /// Flutter is paced by the VBlanks of the fastest monitor that's on.
+    fn update_highest_hz_crtc(&mut self) {
+        self.highest_hz_crtc = self
+            .gpus
+            .get(&self.primary_gpu)
+            .into_iter()
+            .flat_map(|gpu_data| gpu_data.surfaces.values())
+            .filter(|surface| !surface.powered_off)
+            .map(|surface| {
+                let mode = Mode::from(surface.compositor.pending_mode());
+                (mode.refresh, surface.crtc)
+            })
+            .max_by_key(|(refresh, _)| *refresh);
+    }
+
+    /// Gives up DRM master so that the next session can take it.
+    fn pause_devices(&mut self) {
+        for gpu_data in self.gpus.values_mut() {
+            gpu_data.drm_device.pause();
+            if let Some(leasing_global) = gpu_data.leasing_global.as_mut() {
+                leasing_global.suspend();
+            }
+        }
+    }
+
+    fn activate_devices(&mut self) {
+        for (node, gpu_data) in self.gpus.iter_mut() {
+            if let Err(err) = gpu_data.drm_device.activate(false) {
+                error!(?node, "Failed to activate the DRM device: {}", err);
+            }
+            if let Some(leasing_global) = gpu_data.leasing_global.as_mut() {
+                leasing_global.resume::<State<DrmBackend>>();
+            }
+        }
+    }
+
+    /// Leaves linear gamma ramps behind for the session we're switching to.
+    fn reset_gamma(&self) {
+        for gpu_data in self.gpus.values() {
+            for surface in gpu_data.surfaces.values() {
+                if surface.gamma_ramp.is_some() {
+                    if let Err(err) = set_crtc_gamma(&gpu_data.drm_device, surface.crtc, None) {
+                        warn!(?surface.crtc, "Failed to reset gamma: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn reapply_gamma(&self) {
+        for gpu_data in self.gpus.values() {
+            for surface in gpu_data.surfaces.values() {
+                if let Some(ref ramp) = surface.gamma_ramp {
+                    if let Err(err) = set_crtc_gamma(&gpu_data.drm_device, surface.crtc, Some(ramp))
+                    {
+                        warn!(?surface.crtc, "Failed to restore gamma: {}", err);
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -107,7 +289,7 @@ pub fn run_drm_backend() {
     let display: Display<State<DrmBackend>> = Display::new().unwrap();
     let mut display_handle = display.handle();
 
-    let (session, _notifier) = match LibSeatSession::new() {
+    let (session, notifier) = match LibSeatSession::new() {
         Ok(ret) => ret,
         Err(err) => {
             error!("Could not initialize a session: {}", err);
@@ -159,6 +341,8 @@ pub fn run_drm_backend() {
             pointer_images: vec![],
             pointer_image: crate::cursor::Cursor::load(),
             highest_hz_crtc: None,
+            crtcs_to_wake: Vec::new(),
+            release_points_off_planes: Vec::new(),
         },
         None,
     );
@@ -187,6 +371,18 @@ pub fn run_drm_backend() {
 
     state.dmabuf_state = Some(dmabuf_state);
 
+    // Explicit sync needs the kernel to signal syncobj points through an eventfd.
+    let drm_device_fd = state.backend_data.gpus[&primary_gpu]
+        .drm_device
+        .device_fd()
+        .clone();
+    if supports_syncobj_eventfd(&drm_device_fd) {
+        state.drm_syncobj_state = Some(DrmSyncobjState::new::<State<DrmBackend>>(
+            &display_handle,
+            drm_device_fd,
+        ));
+    }
+
     // Start the Flutter engine.
     let (
         flutter_engine,
@@ -204,11 +400,7 @@ pub fn run_drm_backend() {
     // Initialize already present connectors.
     state.device_changed(primary_gpu);
 
-    // Mandatory formats by the Wayland spec.
-    // TODO: Add more formats based on the GLES version.
-    state
-        .shm_state
-        .update_formats([wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888]);
+    state.update_shm_formats();
 
     event_loop
         .handle()
@@ -229,11 +421,28 @@ pub fn run_drm_backend() {
         })
         .unwrap();
 
-    // Mandatory formats by the Wayland spec.
-    // TODO: Add more formats based on the GLES version.
-    state
-        .shm_state
-        .update_formats([wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888]);
+    // Another session takes over the seat when switching VT, or when the system suspends.
+    event_loop
+        .handle()
+        .insert_source(notifier, move |event, _, data| match event {
+            SessionEvent::PauseSession => {
+                info!("Session paused");
+                libinput_context.suspend();
+                // Needs DRM master, so before pausing the devices.
+                data.backend_data.reset_gamma();
+                data.backend_data.pause_devices();
+            }
+            SessionEvent::ActivateSession => {
+                info!("Session activated");
+                if libinput_context.resume().is_err() {
+                    error!("Failed to resume libinput");
+                }
+                data.session_activated();
+            }
+        })
+        .unwrap();
+
+    state.init_logind();
 
     event_loop
         .handle()
@@ -265,6 +474,10 @@ pub fn run_drm_backend() {
             if let Some(ref slot) = gpu_data.last_rendered_slot {
                 gpu_data.swapchain.submitted(slot);
             }
+            gpu_data
+                .surface_ids_in_last_rendered_slot
+                .extend(data.surface_ids_sampled_by_flutter.drain());
+            data.signal_release_points();
         })
         .unwrap();
 
@@ -275,6 +488,9 @@ pub fn run_drm_backend() {
         if result.is_err() {
             state.running.store(false, Ordering::SeqCst);
         } else {
+            state.present_tearing_surfaces();
+            state.wake_crtcs();
+            state.release_buffers_off_planes();
             display_handle.flush_clients().unwrap();
         }
     }
@@ -300,6 +516,10 @@ impl State<DrmBackend> {
             Some(surface) => surface,
             None => return,
         };
+        // Page flips fail without DRM master.
+        if surface.powered_off || !self.backend_data.session.is_active() {
+            return;
+        }
 
         let gles_renderer = self.gles_renderer.as_mut().unwrap();
         let last_rendered_slot = gpu_data.last_rendered_slot.as_mut();
@@ -321,60 +541,69 @@ impl State<DrmBackend> {
             return;
         };
 
-        let output = self.space.outputs().find(|output| {
-            output
-                .user_data()
-                .get::<UdevOutputId>()
-                .map(|id| id.device_id == surface.device_id && id.crtc == surface.crtc)
-                .unwrap_or(false)
-        });
+        let output = self
+            .space
+            .outputs()
+            .chain(self.mirrored_outputs.iter())
+            .find(|output| {
+                output
+                    .user_data()
+                    .get::<UdevOutputId>()
+                    .map(|id| id.device_id == surface.device_id && id.crtc == surface.crtc)
+                    .unwrap_or(false)
+            });
 
         let output = match output {
             Some(output) => output,
             None => return,
         };
 
-        let geometry = match self.space.output_geometry(output) {
-            Some(geometry) => geometry.to_f64(),
-            None => return,
+        // A mirror shows the region of its source, fitted in its own mode.
+        let mirror = output_mirror(output);
+        let source_output = mirror.as_ref().and_then(|mirror| {
+            self.space
+                .outputs()
+                .find(|source| source.name() == mirror.source)
+        });
+        let canvas_geometry = output_canvas_geometry(source_output.unwrap_or(output));
+        let geometry = canvas_geometry.to_f64();
+        let destination = match (&mirror, source_output) {
+            (Some(mirror), Some(_)) => mirror_destination(
+                canvas_geometry.size.to_physical(1),
+                output_canvas_geometry(output).size.to_physical(1),
+                mirror.scaling,
+            ),
+            _ => Rectangle::from_loc_and_size((0, 0), canvas_geometry.size.to_physical(1)),
         };
 
         let scale = output.current_scale();
 
-        let flutter_texture = gles_renderer
-            .import_dmabuf(&slot.export().unwrap(), None)
-            .unwrap();
-        let flutter_texture_buffer = TextureBuffer::from_texture(
-            gles_renderer,
-            flutter_texture,
-            1,
-            Transform::Flipped180,
-            None,
-        );
-        let flutter_texture_element = TextureRenderElement::from_texture_buffer(
-            Point::from((0.0, 0.0)),
-            &flutter_texture_buffer,
-            None,
-            // TODO: I don't know why it has to be like this instead of just `geometry`.
-            Some(Rectangle::from_loc_and_size(
-                (geometry.loc.x, geometry.size.h - geometry.loc.y),
-                geometry.size,
-            )),
-            None,
-            Kind::Unspecified,
-        );
+        // The shell tells us when a single opaque surface covers the whole output.
+        // In that case, we skip the Flutter composition and hand the client buffer to
+        // the DRM compositor, which can put it directly on a plane.
+        let scanout_surface_id = self
+            .scanout_surface_per_output
+            .get(&output.name())
+            .copied()
+            .filter(|_| mirror.is_none());
+        let scanout_surface = scanout_surface_id
+            .and_then(|surface_id| self.surfaces.get(&surface_id))
+            .cloned();
 
-        let pointer_frame = self
-            .backend_data
-            .pointer_image
-            .get_image(1, self.clock.now().into());
+        let pointer_frame = self.backend_data.pointer_image.get_image(
+            scale.fractional_scale().round().max(1.0) as u32,
+            self.clock.now().into(),
+        );
 
-        let cursor_position = self
-            .pointer
-            .current_location()
-            .to_physical(scale.fractional_scale())
-            - Point::from((pointer_frame.xhot as f64, pointer_frame.yhot as f64))
-            - geometry.loc.to_physical(scale.fractional_scale());
+        // The pointer moves on the Flutter canvas, which is already in physical pixels.
+        let pointer_position = (self.pointer.current_location() - geometry.loc).to_physical(1.0);
+        let cursor_position =
+            Point::<f64, Physical>::from((
+                destination.loc.x as f64
+                    + pointer_position.x * destination.size.w as f64 / geometry.size.w,
+                destination.loc.y as f64
+                    + pointer_position.y * destination.size.h as f64 / geometry.size.h,
+            )) - Point::from((pointer_frame.xhot as f64, pointer_frame.yhot as f64));
 
         let pointer_images = &mut self.backend_data.pointer_images;
         let pointer_image = pointer_images
@@ -411,35 +640,363 @@ impl State<DrmBackend> {
             Kind::Cursor,
         );
 
-        surface
+        let vrr_mode = self
+            .vrr_mode_per_output
+            .get(&output.name())
+            .copied()
+            .unwrap_or(VrrMode::Off);
+        let use_vrr = match vrr_mode {
+            VrrMode::Always => true,
+            VrrMode::Fullscreen => scanout_surface.is_some(),
+            VrrMode::Off => false,
+        };
+        // Changed between two page flips, see `frame_submitted`.
+        surface.vrr_requested = surface.vrr_capable && use_vrr;
+
+        let mut elements: Vec<OutputRenderElements> = vec![cursor_element.into()];
+
+        if let Some(ref scanout_surface) = scanout_surface {
+            elements.extend(
+                render_elements_from_surface_tree::<_, OutputRenderElements>(
+                    gles_renderer,
+                    scanout_surface,
+                    Point::<i32, Physical>::from((0, 0)),
+                    Scale::from(scale.fractional_scale()),
+                    1.0,
+                    Kind::ScanoutCandidate,
+                ),
+            );
+        } else {
+            let flutter_texture = gles_renderer
+                .import_dmabuf(&slot.export().unwrap(), None)
+                .unwrap();
+            let flutter_texture_buffer = TextureBuffer::from_texture(
+                gles_renderer,
+                flutter_texture,
+                1,
+                Transform::Flipped180,
+                None,
+            );
+            let flutter_texture_element = TextureRenderElement::from_texture_buffer(
+                destination.loc.to_f64(),
+                &flutter_texture_buffer,
+                None,
+                // TODO: I don't know why it has to be like this instead of just `geometry`.
+                Some(Rectangle::from_loc_and_size(
+                    (geometry.loc.x, geometry.size.h - geometry.loc.y),
+                    geometry.size,
+                )),
+                // The DRM compositor scales elements by the output scale, but the canvas is already
+                // in physical pixels.
+                Some(
+                    destination
+                        .size
+                        .to_f64()
+                        .to_logical(scale.fractional_scale())
+                        .to_i32_round(),
+                ),
+                Kind::Unspecified,
+            );
+            elements.push(flutter_texture_element.into());
+        }
+
+        let mut render_frame_result = surface
             .compositor
-            .render_frame::<GlesRenderer, TextureRenderElement<GlesTexture>>(
+            .render_frame::<GlesRenderer, OutputRenderElements>(
                 gles_renderer,
-                &[cursor_element, flutter_texture_element],
+                &elements,
                 [0.0, 0.0, 0.0, 0.0],
             )
             .unwrap();
-        surface.compositor.queue_frame(None).unwrap();
+        if render_frame_result.is_empty {
+            // A scanned out client that didn't commit produces no damage,
+            // but we still need a page flip to keep receiving VBlank events.
+            surface.compositor.reset_buffers();
+            render_frame_result = surface
+                .compositor
+                .render_frame::<GlesRenderer, OutputRenderElements>(
+                    gles_renderer,
+                    &elements,
+                    [0.0, 0.0, 0.0, 0.0],
+                )
+                .unwrap();
+        }
+
+        let mut output_presentation_feedback = OutputPresentationFeedback::new(output);
+        if let Some(ref scanout_surface) = scanout_surface {
+            take_presentation_feedback_surface_tree(
+                scanout_surface,
+                &mut output_presentation_feedback,
+                |_, _| Some(output.clone()),
+                |surface, _| {
+                    surface_presentation_feedback_flags_from_states(
+                        surface,
+                        &render_frame_result.states,
+                    )
+                },
+            );
+        } else if Some(crtc) == self.backend_data.highest_hz_crtc.map(|(_, crtc)| crtc) {
+            // Flutter composites all the surfaces in a single frame, which is paced by the
+            // highest refresh rate output, so that's the one presenting them.
+            for surface_id in gpu_data.surface_ids_in_last_rendered_slot.drain() {
+                if let Some(wl_surface) = self.surfaces.get(&surface_id) {
+                    take_presentation_feedback_surface_tree(
+                        wl_surface,
+                        &mut output_presentation_feedback,
+                        |_, _| Some(output.clone()),
+                        |_, _| wp_presentation_feedback::Kind::empty(),
+                    );
+                }
+            }
+        }
+        surface
+            .compositor
+            .queue_frame(Some(output_presentation_feedback))
+            .unwrap();
+
+        // Explicitly synced buffers stay acquired while a plane scans them out,
+        // the page flip replacing them releases them.
+        let release_point = scanout_surface
+            .as_ref()
+            .and(scanout_surface_id)
+            .and_then(|surface_id| self.release_point_per_surface_id.get(&surface_id))
+            .cloned();
+        let replaced_release_point =
+            std::mem::replace(&mut surface.queued_release_point, release_point.clone());
+
+        // Steer the client towards a format that the planes can scan out directly,
+        // and back to the render formats when it stops being a scanout candidate.
+        if surface.scanout_surface != scanout_surface {
+            if let Some(ref dmabuf_feedback) = surface.dmabuf_feedback {
+                if let Some(ref previous) = surface.scanout_surface {
+                    set_dmabuf_feedback_surface_tree(previous, &dmabuf_feedback.render_feedback);
+                }
+                if let Some(ref current) = scanout_surface {
+                    set_dmabuf_feedback_surface_tree(current, &dmabuf_feedback.scanout_feedback);
+                }
+            }
+            surface.scanout_surface = scanout_surface;
+        }
+
+        if let Some(release_point) = release_point {
+            self.plane_acquired_release_point(release_point);
+        }
+        // The queued frame was replaced before its page flip, by a tearing one.
+        if let Some(replaced_release_point) = replaced_release_point {
+            self.plane_released_release_point(replaced_release_point);
+        }
+    }
+
+    fn output_for_crtc(&self, node: DrmNode, crtc: crtc::Handle) -> Option<Output> {
+        self.space
+            .outputs()
+            .chain(self.mirrored_outputs.iter())
+            .chain(self.disabled_outputs.iter())
+            .find(|output| {
+                output
+                    .user_data()
+                    .get::<UdevOutputId>()
+                    .map(|id| id.device_id == node && id.crtc == crtc)
+                    .unwrap_or(false)
+            })
+            .cloned()
+    }
+
+    fn frame_submitted(
+        &mut self,
+        node: DrmNode,
+        crtc: crtc::Handle,
+        metadata: &mut Option<DrmEventMetadata>,
+    ) {
+        let gpu_data = self.backend_data.gpus.get_mut(&node).unwrap();
+        let Some(surface) = gpu_data.surfaces.get_mut(&crtc) else {
+            return;
+        };
+
+        let now = Instant::now();
+        surface.vblank_interval = surface
+            .last_vblank_time
+            .replace(now)
+            .map(|last_vblank_time| now.duration_since(last_vblank_time));
+
+        // Nothing is in flight until the DRM compositor submits its queued frame below,
+        // so the property change can't collide with a page flip.
+        if surface.vrr_capable && surface.vrr_enabled != Some(surface.vrr_requested) {
+            match set_vrr_enabled(&gpu_data.drm_device, crtc, surface.vrr_requested) {
+                Ok(()) => surface.vrr_enabled = Some(surface.vrr_requested),
+                Err(err) => {
+                    warn!(?crtc, "Failed to toggle VRR, not trying again: {}", err);
+                    surface.vrr_capable = false;
+                    surface.vrr_requested = false;
+                    surface.vrr_enabled = Some(false);
+                }
+            }
+        }
+
+        // The buffer that was on screen until this page flip isn't scanned out anymore.
+        let replaced_release_point = std::mem::replace(
+            &mut surface.scanned_out_release_point,
+            surface.queued_release_point.take(),
+        );
+        if let Some(replaced_release_point) = replaced_release_point {
+            self.plane_released_release_point(replaced_release_point);
+        }
+        let gpu_data = self.backend_data.gpus.get_mut(&node).unwrap();
+        let surface = gpu_data.surfaces.get_mut(&crtc).unwrap();
+
+        let feedback = match surface.compositor.frame_submitted() {
+            Ok(feedback) => feedback.flatten(),
+            Err(err) => {
+                warn!("Failed to submit frame: {:?}", err);
+                None
+            }
+        };
+        let Some(mut feedback) = feedback else {
+            return;
+        };
+
+        let (time, flags): (Time<Monotonic>, _) =
+            match metadata.as_ref().map(|metadata| metadata.time) {
+                Some(DrmEventTime::Monotonic(time)) => (
+                    time.into(),
+                    wp_presentation_feedback::Kind::Vsync
+                        | wp_presentation_feedback::Kind::HwClock
+                        | wp_presentation_feedback::Kind::HwCompletion,
+                ),
+                _ => (self.clock.now(), wp_presentation_feedback::Kind::Vsync),
+            };
+        let sequence = metadata
+            .as_ref()
+            .map(|metadata| metadata.sequence)
+            .unwrap_or(0);
+
+        // The refresh interval is unknown when the panel follows our page flips.
+        let refresh = match feedback.output().and_then(|output| output.current_mode()) {
+            Some(mode) if surface.vrr_enabled != Some(true) => {
+                Duration::from_secs_f64(1_000f64 / mode.refresh as f64)
+            }
+            _ => Duration::ZERO,
+        };
+
+        feedback.presented(time, refresh, sequence as u64, flags);
+    }
+
+    /// Coming back from another VT or from suspend.
+    /// Whoever had the devices meanwhile may have changed anything, so nothing is assumed to be as we left it.
+    fn session_activated(&mut self) {
+        self.backend_data.activate_devices();
+
+        let mut release_points = Vec::new();
+        for gpu_data in self.backend_data.gpus.values_mut() {
+            for surface in gpu_data.surfaces.values_mut() {
+                // Pending page flips are gone, and the next frame replaces whatever is on the planes.
+                release_points.extend(surface.take_plane_release_points());
+                surface.last_vblank_time = None;
+                // The other session may have left VRR either way, it's set again on the next VBlank.
+                surface.vrr_enabled = None;
+                // The next frame is a full modeset with the mode we had, and a full redraw.
+                let result = if surface.powered_off {
+                    surface.compositor.clear()
+                } else {
+                    surface.compositor.reset_state()
+                };
+                if let Err(err) = result {
+                    warn!(?surface.crtc, "Failed to reset the DRM compositor: {}", err);
+                }
+                surface.compositor.reset_buffers();
+            }
+        }
+        for release_point in release_points {
+            self.plane_released_release_point(release_point);
+        }
+        self.backend_data.reapply_gamma();
+        // Imported again on the next frame.
+        self.backend_data.pointer_images.clear();
+
+        // Monitors may have been plugged or unplugged while we were away.
+        let nodes = self.backend_data.gpus.keys().copied().collect::<Vec<_>>();
+        for node in nodes {
+            self.device_changed(node);
+        }
+
+        // The key releases went to the other session.
+        self.release_all_keys();
+
+        // There's no VBlank to render on until a frame is queued.
+        let crtcs = self
+            .backend_data
+            .get_gpu_data()
+            .surfaces
+            .values()
+            .filter(|surface| !surface.powered_off)
+            .map(|surface| surface.crtc)
+            .collect::<Vec<_>>();
+        self.backend_data.crtcs_to_wake.extend(crtcs);
+        if let Err(err) = self.flutter_engine().schedule_frame() {
+            warn!("Failed to schedule a Flutter frame: {}", err);
+        }
     }
 
-    fn monitor_layout_changed(&mut self) {
-        let monitors = self.space.outputs().cloned().collect::<Vec<_>>();
-        self.flutter_engine_mut().monitor_layout_changed(monitors);
+    fn wake_crtcs(&mut self) {
+        let crtcs = std::mem::take(&mut self.backend_data.crtcs_to_wake);
+        for crtc in crtcs {
+            self.update_crtc_planes(crtc);
+        }
     }
-    fn determine_highest_hz_crtc(&mut self) {
-        self.backend_data.highest_hz_crtc = self
+
+    fn release_buffers_off_planes(&mut self) {
+        let release_points = std::mem::take(&mut self.backend_data.release_points_off_planes);
+        for release_point in release_points {
+            self.plane_released_release_point(release_point);
+        }
+    }
+
+    /// Clients asking for tearing presentation don't wait for the next VBlank.
+    /// Their new buffer replaces the frame queued for the next page flip as soon as it's committed.
+    fn present_tearing_surfaces(&mut self) {
+        if self.tearing_surface_ids_committed.is_empty() {
+            return;
+        }
+        let surface_ids = std::mem::take(&mut self.tearing_surface_ids_committed);
+
+        let crtcs = self
             .space
             .outputs()
-            // Ignore outputs that don't have a mode.
-            .filter_map(|output| output.current_mode().map(|mode| (mode.refresh, output)))
-            // Take the one with the highest refresh rate.
-            .max_by_key(|(refresh, output)| *refresh)
-            .map(|(refresh, output)| {
-                (
-                    refresh,
-                    output.user_data().get::<UdevOutputId>().unwrap().crtc,
-                )
-            });
+            .filter(|output| {
+                self.scanout_surface_per_output
+                    .get(&output.name())
+                    .map(|surface_id| surface_ids.contains(surface_id))
+                    .unwrap_or(false)
+            })
+            .filter_map(|output| output.user_data().get::<UdevOutputId>())
+            .map(|id| id.crtc)
+            .collect::<Vec<_>>();
+
+        for crtc in crtcs {
+            self.update_crtc_planes(crtc);
+        }
+    }
+
+    /// The refresh rate Flutter should target, in millihertz.
+    /// With VRR enabled, the panel follows our page flips, so the actual cadence
+    /// is measured from the VBlank events of the CRTC instead of taken from the mode.
+    fn effective_refresh_rate(&self, crtc: crtc::Handle, mode_mhz: i32) -> u32 {
+        let vblank_interval = self
+            .backend_data
+            .get_gpu_data()
+            .surfaces
+            .get(&crtc)
+            .filter(|surface| surface.vrr_enabled == Some(true))
+            .and_then(|surface| surface.vblank_interval);
+
+        match vblank_interval {
+            Some(vblank_interval) => {
+                let measured_mhz = (1000.0 / vblank_interval.as_secs_f64()) as i32;
+                // Don't let a long idle period make Flutter believe the panel runs slower than it can.
+                measured_mhz.clamp(mode_mhz / 4, mode_mhz) as u32
+            }
+            None => mode_mhz as u32,
+        }
     }
 }
 
@@ -447,7 +1004,10 @@ impl State<DrmBackend> {
 struct GpuData {
     surfaces: HashMap<crtc::Handle, SurfaceData>,
     non_desktop_connectors: Vec<(connector::Handle, crtc::Handle)>,
-    active_leases: Vec<DrmLease>,
+    leasing_global: Option<DrmLeaseState>,
+    /// Connectors of the lease being built, until smithay hands us the resulting lease.
+    pending_lease_connectors: Vec<connector::Handle>,
+    active_leases: Vec<(DrmLease, Vec<connector::Handle>)>,
     gbm_device: GbmDevice<DrmDeviceFd>,
     gbm_allocator: GbmAllocator<DrmDeviceFd>,
     drm_device: DrmDevice,
@@ -457,6 +1017,8 @@ struct GpuData {
     swapchain: Swapchain<Box<dyn Allocator<Buffer = Dmabuf, Error = AnyError> + 'static>>,
     current_slot: Option<Slot<Dmabuf>>,
     last_rendered_slot: Option<Slot<Dmabuf>>,
+    /// Surfaces whose textures were sampled by Flutter for the frames rendered since the last page flip.
+    surface_ids_in_last_rendered_slot: HashSet<u64>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -494,14 +1056,22 @@ impl State<DrmBackend> {
             .loop_handle
             .insert_source(
                 notifier,
-                move |event, _metadata, data: &mut State<_>| match event {
+                move |event, metadata, data: &mut State<_>| match event {
                     DrmEvent::VBlank(crtc) => {
-                        let gpu_data = data.backend_data.gpus.get_mut(&node).unwrap();
-
-                        if let Some(surface) = gpu_data.surfaces.get_mut(&crtc) {
-                            let _ = surface.compositor.frame_submitted();
+                        data.frame_submitted(node, crtc, metadata);
+
+                        // Clients are paced by the monitor they're displayed on.
+                        let is_primary_output = data
+                            .backend_data
+                            .highest_hz_crtc
+                            .map(|(_, highest_hz_crtc)| highest_hz_crtc == crtc)
+                            .unwrap_or(true);
+                        if let Some(output) = data.output_for_crtc(node, crtc) {
+                            data.send_frame_callbacks(&output, is_primary_output);
                         }
 
+                        let gpu_data = data.backend_data.gpus.get_mut(&node).unwrap();
+
                         let (mhz, highest_hz_crtc) = match data.backend_data.highest_hz_crtc {
                             Some(highest_hz_crtc) => highest_hz_crtc,
                             None => return,
@@ -516,29 +1086,11 @@ impl State<DrmBackend> {
                             data.update_crtc_planes(crtc);
                         }
 
+                        let mhz = data.effective_refresh_rate(crtc, mhz);
                         let drained: Vec<_> = data.batons.drain(..).collect(); // Mutable borrow ends here
 
                         for baton in drained {
-                            data.flutter_engine().on_vsync(baton, mhz as u32).unwrap();
-                        }
-                        let start_time = std::time::Instant::now();
-                        for surface in data.xdg_shell_state.toplevel_surfaces() {
-                            send_frames_surface_tree(
-                                surface.wl_surface(),
-                                start_time.elapsed().as_millis() as u32,
-                            );
-                        }
-                        for surface in data.xdg_popups.values() {
-                            send_frames_surface_tree(
-                                surface.wl_surface(),
-                                start_time.elapsed().as_millis() as u32,
-                            );
-                        }
-                        for surface in data.x11_surface_per_wl_surface.keys() {
-                            send_frames_surface_tree(
-                                surface,
-                                start_time.elapsed().as_millis() as u32,
-                            );
+                            data.flutter_engine().on_vsync(baton, mhz).unwrap();
                         }
                     }
                     DrmEvent::Error(error) => {
@@ -574,15 +1126,34 @@ impl State<DrmBackend> {
                     GbmAllocator::new(gbm_device.clone(), GbmBufferFlags::RENDERING);
                 Box::new(DmabufAllocator(gbm_allocator))
             };
-            let modifiers = gles_renderer
-                .egl_context()
-                .dmabuf_texture_formats()
+            // Render Flutter in 10-bit when the GPU can sample it, so content tone-mapped
+            // from HDR clients doesn't band. The CRTC formats already prefer 10-bit.
+            let texture_formats = gles_renderer.egl_context().dmabuf_texture_formats();
+            let fourcc = if std::env::var("ANVIL_DISABLE_10BIT").is_err()
+                && texture_formats
+                    .iter()
+                    .any(|format| format.code == Fourcc::Argb2101010)
+            {
+                Fourcc::Argb2101010
+            } else {
+                Fourcc::Argb8888
+            };
+            let modifiers = texture_formats
                 .iter()
+                .filter(|format| format.code == fourcc)
                 .map(|format| format.modifier)
                 .collect::<Vec<_>>();
-            Swapchain::new(dmabuf_allocator, 0, 0, Fourcc::Argb8888, modifiers)
+            Swapchain::new(dmabuf_allocator, 0, 0, fourcc, modifiers)
         };
 
+        let leasing_global = DrmLeaseState::new_with_filter::<State<DrmBackend>, _>(
+            &self.display_handle,
+            &node,
+            |client| can_view_privileged_global(client, PrivilegedProtocol::DrmLease),
+        )
+        .map_err(|err| warn!(?err, "Failed to initialize DRM lease global for {}", node))
+        .ok();
+
         self.gles_renderer = Some(gles_renderer);
         self.gl = Some(Gles2::load_with(
             |s| unsafe { egl::get_proc_address(s) } as *const _
@@ -597,11 +1168,14 @@ impl State<DrmBackend> {
                 drm_device: drm,
                 drm_scanner: DrmScanner::new(),
                 non_desktop_connectors: Vec::new(),
+                leasing_global,
+                pending_lease_connectors: Vec::new(),
                 render_node,
                 surfaces: HashMap::new(),
                 active_leases: Vec::new(),
                 swapchain,
                 current_slot: None,
+                surface_ids_in_last_rendered_slot: HashSet::new(),
                 last_rendered_slot: None,
             },
         );
@@ -621,32 +1195,17 @@ impl State<DrmBackend> {
             return;
         };
 
-        let output_name = format!(
-            "{}-{}",
-            connector.interface().as_str(),
-            connector.interface_id()
-        );
+        let output_name = connector_name(&connector);
         info!(?crtc, "Trying to setup connector {}", output_name,);
 
-        let non_desktop = device
-            .drm_device
-            .get_properties(connector.handle())
-            .ok()
-            .and_then(|props| {
-                let (info, value) = props
-                    .into_iter()
-                    .filter_map(|(handle, value)| {
-                        let info = device.drm_device.get_property(handle).ok()?;
-                        Some((info, value))
-                    })
-                    .find(|(info, _)| info.name().to_str() == Ok("non-desktop"))?;
-
-                info.value_type().convert_value(value).as_boolean()
-            })
+        let non_desktop = get_drm_property(&device.drm_device, connector.handle(), "non-desktop")
+            .and_then(|(info, value)| info.value_type().convert_value(value).as_boolean())
             .unwrap_or(false);
 
-        let (make, model) = EdidInfo::for_connector(&device.drm_device, connector.handle())
-            .map(|info| (info.manufacturer, info.model))
+        let edid_info = EdidInfo::for_connector(&device.drm_device, connector.handle());
+        let (make, model) = edid_info
+            .as_ref()
+            .map(|info| (info.manufacturer.clone(), info.model.clone()))
             .unwrap_or_else(|| ("Unknown".into(), "Unknown".into()));
 
         if non_desktop {
@@ -657,28 +1216,56 @@ impl State<DrmBackend> {
             device
                 .non_desktop_connectors
                 .push((connector.handle(), crtc));
+            if let Some(leasing_global) = device.leasing_global.as_mut() {
+                leasing_global.add_connector::<State<DrmBackend>>(
+                    connector.handle(),
+                    output_name,
+                    format!("{} {}", make, model),
+                );
+            }
             return;
         }
 
-        // check if there is a file in xdgConfigHome/veshell/persistence/Monitor/<output_name>.json and if so, get mode from there
-        // if not, get the preferred mode from the connector
-        info!("output_name: {}", output_name);
-        let mode_id = get_mode_id_for_monitor_from_file(&output_name).unwrap_or_else(|| {
-            connector
-                .modes()
-                .iter()
-                .position(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
-                .unwrap_or(0)
-        });
-        info!("using mode_id: {}", mode_id);
-
-        // log all modes
-        for (i, mode) in connector.modes().iter().enumerate() {
-            info!("mode {}: {:?}", i, mode);
+        // Start with the mode remembered for this monitor, so that it isn't modeset twice.
+        let identity = MonitorIdentity::from_edid(edid_info.as_ref(), &output_name);
+        let connected = self
+            .space
+            .outputs()
+            .map(MonitorIdentity::from_output)
+            .chain(std::iter::once(identity.clone()))
+            .collect::<Vec<_>>();
+        let wl_modes = connector
+            .modes()
+            .iter()
+            .map(|mode| Mode::from(*mode))
+            .collect::<Vec<_>>();
+        let preferred_mode_id = connector
+            .modes()
+            .iter()
+            .position(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
+            .unwrap_or(0);
+        if self
+            .monitor_profiles
+            .migrate_legacy_mode(&identity, &output_name, &wl_modes)
+        {
+            if let Err(err) = self.monitor_profiles.save() {
+                warn!("Failed to save the monitor profiles: {}", err);
+            }
         }
+        let mode_id = self
+            .monitor_profiles
+            .profile_for(&identity, &connected)
+            .and_then(|profile| profile.mode)
+            .and_then(|mode| find_mode(&wl_modes, mode))
+            .and_then(|mode| wl_modes.iter().position(|wl_mode| *wl_mode == mode))
+            .unwrap_or(preferred_mode_id);
+        info!(
+            "Connector {} uses mode {:?}",
+            output_name, wl_modes[mode_id]
+        );
 
         let drm_mode = connector.modes()[mode_id];
-        let wl_mode = Mode::from(drm_mode);
+        let wl_mode = wl_modes[mode_id];
 
         let surface = match device
             .drm_device
@@ -691,12 +1278,18 @@ impl State<DrmBackend> {
             }
         };
 
-        let (phys_w, phys_h) = connector.size().unwrap_or((0, 0));
+        // The kernel fills the size from the EDID too, but only from the basic display parameters
+        // when there's no detailed timing.
+        let (phys_w, phys_h) = edid_info
+            .as_ref()
+            .and_then(|info| info.physical_size)
+            .or(connector.size())
+            .unwrap_or((0, 0));
         let output = Output::new(
             output_name,
             PhysicalProperties {
                 size: (phys_w as i32, phys_h as i32).into(),
-                subpixel: Subpixel::Unknown,
+                subpixel: connector_subpixel(&connector),
                 make,
                 model,
             },
@@ -704,13 +1297,25 @@ impl State<DrmBackend> {
         let global = output.create_global::<State<DrmBackend>>(&self.display_handle);
 
         // Put the new output at the right of the last one.
-        let x = self.space.outputs().fold(0, |acc, o| {
-            acc + self.space.output_geometry(o).unwrap().size.w
-        });
+        let x = self
+            .space
+            .outputs()
+            .fold(0, |acc, o| acc + output_canvas_geometry(o).size.w);
         let position = (x, 0).into();
 
-        output.set_preferred(wl_mode);
-        output.change_current_state(Some(wl_mode), None, None, Some(position));
+        let scale = default_output_scale((phys_w, phys_h), wl_mode.size);
+        info!("Default scale of {}: {}", output.name(), scale);
+
+        for mode in &wl_modes {
+            output.add_mode(*mode);
+        }
+        output.set_preferred(wl_modes[preferred_mode_id]);
+        output.change_current_state(
+            Some(wl_mode),
+            None,
+            Some(smithay::output::Scale::Fractional(scale)),
+            Some(position),
+        );
         self.space.map_output(&output, position);
 
         output.user_data().insert_if_missing(|| UdevOutputId {
@@ -718,6 +1323,24 @@ impl State<DrmBackend> {
             device_id: node,
         });
 
+        let color_capabilities = OutputColorCapabilities {
+            hdr_static_metadata: edid_info.as_ref().and_then(|info| info.hdr_static_metadata),
+            colorimetry: edid_info
+                .as_ref()
+                .map(|info| info.colorimetry)
+                .unwrap_or_default(),
+        };
+        info!(
+            "Connector {} HDR capable: {}, {:?}",
+            output.name(),
+            color_capabilities.hdr_capable(),
+            color_capabilities
+        );
+        output.user_data().insert_if_missing(|| color_capabilities);
+        if let Some(edid_info) = edid_info {
+            output.user_data().insert_if_missing(|| edid_info);
+        }
+
         let color_formats = if std::env::var("ANVIL_DISABLE_10BIT").is_ok() {
             SUPPORTED_FORMATS_8BIT_ONLY
         } else {
@@ -764,7 +1387,7 @@ impl State<DrmBackend> {
             device.gbm_allocator.clone(),
             device.gbm_device.clone(),
             color_formats,
-            render_formats,
+            render_formats.clone(),
             device.drm_device.cursor_size(),
             Some(device.gbm_device.clone()),
         ) {
@@ -775,6 +1398,23 @@ impl State<DrmBackend> {
             }
         };
 
+        let texture_formats = self
+            .gles_renderer
+            .as_ref()
+            .unwrap()
+            .egl_context()
+            .dmabuf_texture_formats()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        let dmabuf_feedback =
+            get_surface_dmabuf_feedback(node, device.render_node, texture_formats, &compositor);
+
+        let vrr_capable = get_drm_property(&device.drm_device, connector.handle(), "vrr_capable")
+            .and_then(|(info, value)| info.value_type().convert_value(value).as_boolean())
+            .unwrap_or(false);
+        info!("Connector {} VRR capable: {}", output.name(), vrr_capable);
+
         let mut surface = SurfaceData {
             dh: self.display_handle.clone(),
             device_id: node,
@@ -782,6 +1422,18 @@ impl State<DrmBackend> {
             render_node: device.render_node,
             global: Some(global),
             compositor,
+            dmabuf_feedback,
+            scanout_surface: None,
+            vrr_capable,
+            vrr_requested: false,
+            vrr_enabled: None,
+            gamma_ramp: None,
+            modes: connector.modes().to_vec(),
+            powered_off: false,
+            queued_release_point: None,
+            scanned_out_release_point: None,
+            last_vblank_time: None,
+            vblank_interval: None,
         };
 
         // Start first frame with a solid color. This will trigger the first VBLank event.
@@ -798,22 +1450,8 @@ impl State<DrmBackend> {
 
         device.surfaces.insert(crtc, surface);
 
-        let bounding_box = self
-            .space
-            .outputs()
-            .map(|output| self.space.output_geometry(output).unwrap())
-            .reduce(|first, second| first.merge(second))
-            .unwrap();
-
-        device
-            .swapchain
-            .resize(bounding_box.size.w as u32, bounding_box.size.h as u32);
-        self.flutter_engine()
-            .send_window_metrics((bounding_box.size.w as u32, bounding_box.size.h as u32).into())
-            .unwrap();
-
-        self.determine_highest_hz_crtc();
-        self.monitor_layout_changed();
+        self.restore_gamma(&output);
+        self.apply_monitor_profiles();
     }
 
     fn connector_disconnected(
@@ -834,41 +1472,41 @@ impl State<DrmBackend> {
             .position(|(handle, _)| *handle == connector.handle())
         {
             let _ = device.non_desktop_connectors.remove(pos);
-        } else {
-            device.surfaces.remove(&crtc);
-        }
+            if let Some(leasing_global) = device.leasing_global.as_mut() {
+                leasing_global.withdraw_connector(connector.handle());
+            }
 
-        let output = self
-            .space
-            .outputs()
-            .find(|o| {
-                o.user_data()
-                    .get::<UdevOutputId>()
-                    .map(|id| id.device_id == node && id.crtc == crtc)
-                    .unwrap_or(false)
-            })
-            .cloned();
+            // Dropping a lease revokes it, the headset is gone anyway.
+            let lease_count = device.active_leases.len();
+            device
+                .active_leases
+                .retain(|(_, connectors)| !connectors.contains(&connector.handle()));
+            if device.active_leases.len() != lease_count {
+                self.flutter_engine_mut()
+                    .platform_method_channel
+                    .invoke_method(
+                        "drm_lease_revoked",
+                        Some(Box::new(json!({
+                            "connectors": [connector_name(&connector)],
+                        }))),
+                        None,
+                    );
+            }
+        } else if let Some(mut surface) = device.surfaces.remove(&crtc) {
+            for release_point in surface.take_plane_release_points() {
+                self.plane_released_release_point(release_point);
+            }
+        }
 
-        if let Some(output) = output {
+        if let Some(output) = self.output_for_crtc(node, crtc) {
             self.space.unmap_output(&output);
+            self.mirrored_outputs.retain(|mirror| *mirror != output);
+            self.disabled_outputs.retain(|disabled| *disabled != output);
+            self.output_power_gone(&output);
+            self.gamma_output_gone(&output);
         }
 
-        let bounding_box = self
-            .space
-            .outputs()
-            .map(|output| self.space.output_geometry(output).unwrap())
-            .reduce(|first, second| first.merge(second))
-            .unwrap_or(Rectangle::default());
-
-        device
-            .swapchain
-            .resize(bounding_box.size.w as u32, bounding_box.size.h as u32);
-        self.flutter_engine()
-            .send_window_metrics((bounding_box.size.w as u32, bounding_box.size.h as u32).into())
-            .unwrap();
-
-        self.determine_highest_hz_crtc();
-        self.monitor_layout_changed();
+        self.apply_monitor_profiles();
     }
 
     fn device_changed(&mut self, node: DrmNode) {
@@ -894,6 +1532,101 @@ impl State<DrmBackend> {
     }
 }
 
+impl DrmLeaseHandler for State<DrmBackend> {
+    fn drm_lease_state(&mut self, node: DrmNode) -> &mut DrmLeaseState {
+        self.backend_data
+            .gpus
+            .get_mut(&node)
+            .unwrap()
+            .leasing_global
+            .as_mut()
+            .unwrap()
+    }
+
+    fn lease_request(
+        &mut self,
+        node: DrmNode,
+        request: DrmLeaseRequest,
+    ) -> Result<DrmLeaseBuilder, LeaseRejected> {
+        let device = self
+            .backend_data
+            .gpus
+            .get_mut(&node)
+            .ok_or_else(LeaseRejected::default)?;
+
+        let mut builder = DrmLeaseBuilder::new(&device.drm_device);
+        for connector in request.connectors.iter() {
+            let Some((_, crtc)) = device
+                .non_desktop_connectors
+                .iter()
+                .find(|(handle, _)| handle == connector)
+            else {
+                warn!(
+                    ?connector,
+                    "Lease requested for a desktop connector, denying"
+                );
+                return Err(LeaseRejected::default());
+            };
+
+            builder.add_connector(*connector);
+            builder.add_crtc(*crtc);
+
+            let planes = device
+                .drm_device
+                .planes(crtc)
+                .map_err(LeaseRejected::with_cause)?;
+            let primary_plane_claim = device
+                .drm_device
+                .claim_plane(planes.primary.handle, *crtc)
+                .ok_or_else(LeaseRejected::default)?;
+            builder.add_plane(planes.primary.handle, primary_plane_claim);
+            if let Some(cursor) = planes.cursor {
+                if let Some(claim) = device.drm_device.claim_plane(cursor.handle, *crtc) {
+                    builder.add_plane(cursor.handle, claim);
+                }
+            }
+        }
+
+        device.pending_lease_connectors = request.connectors;
+        Ok(builder)
+    }
+
+    fn new_active_lease(&mut self, node: DrmNode, lease: DrmLease) {
+        let device = self.backend_data.gpus.get_mut(&node).unwrap();
+        let connectors = std::mem::take(&mut device.pending_lease_connectors);
+        let connector_names = connectors
+            .iter()
+            .filter_map(|handle| device.drm_device.get_connector(*handle, false).ok())
+            .map(|connector| connector_name(&connector))
+            .collect::<Vec<_>>();
+        info!(
+            lease_id = lease.id(),
+            "Granted DRM lease for {:?}", connector_names
+        );
+        device.active_leases.push((lease, connectors));
+
+        self.flutter_engine_mut()
+            .platform_method_channel
+            .invoke_method(
+                "drm_lease_granted",
+                Some(Box::new(json!({
+                    "connectors": connector_names,
+                }))),
+                None,
+            );
+    }
+
+    fn lease_destroyed(&mut self, node: DrmNode, lease_id: u32) {
+        if let Some(device) = self.backend_data.gpus.get_mut(&node) {
+            device
+                .active_leases
+                .retain(|(lease, _)| lease.id() != lease_id);
+        }
+    }
+}
+
+delegate_drm_lease!(State<DrmBackend>);
+
 #[allow(dead_code)]
 struct SurfaceData {
     dh: DisplayHandle,
@@ -902,6 +1635,45 @@ struct SurfaceData {
     render_node: DrmNode,
     global: Option<GlobalId>,
     compositor: GbmDrmCompositor,
+    dmabuf_feedback: Option<SurfaceDmabufFeedback>,
+    scanout_surface: Option<WlSurface>,
+    vrr_capable: bool,
+    /// Whether the next frames should be shown with VRR, applied between two page flips.
+    vrr_requested: bool,
+    /// `None` until it's set, and after another session had the device.
+    vrr_enabled: Option<bool>,
+    gamma_ramp: Option<Vec<u16>>,
+    /// The modes of the connector, to switch between them.
+    modes: Vec<DrmMode>,
+    powered_off: bool,
+    /// Release point of the scanned out client buffer of the frame waiting for its page flip.
+    queued_release_point: Option<DrmSyncPoint>,
+    /// Release point of the scanned out client buffer on screen.
+    scanned_out_release_point: Option<DrmSyncPoint>,
+    last_vblank_time: Option<Instant>,
+    /// Time between the last two VBlanks of this CRTC.
+    vblank_interval: Option<Duration>,
+}
+
+impl SurfaceData {
+    /// The release points of the client buffers on the planes, once the CRTC stops showing them.
+    fn take_plane_release_points(&mut self) -> impl Iterator<Item = DrmSyncPoint> {
+        self.queued_release_point
+            .take()
+            .into_iter()
+            .chain(self.scanned_out_release_point.take())
+    }
+}
+
+struct SurfaceDmabufFeedback {
+    render_feedback: DmabufFeedback,
+    scanout_feedback: DmabufFeedback,
+}
+
+render_elements! {
+    OutputRenderElements<=GlesRenderer>;
+    Texture=TextureRenderElement<GlesTexture>,
+    Surface=WaylandSurfaceRenderElement<GlesRenderer>,
 }
 
 pub type GbmDrmCompositor = DrmCompositor<
@@ -911,38 +1683,198 @@ pub type GbmDrmCompositor = DrmCompositor<
     DrmDeviceFd,
 >;
 
-fn get_mode_id_for_monitor_from_file(output_name: &str) -> Option<usize> {
-    let path = std::env::var("XDG_CONFIG_HOME")
-        .unwrap_or_else(|_| std::env::var("HOME").unwrap() + "/.config")
-        + "/veshell/persistence/Monitor/"
-        + output_name
-        + ".json";
+fn connector_name(connector: &connector::Info) -> String {
+    format!(
+        "{}-{}",
+        connector.interface().as_str(),
+        connector.interface_id()
+    )
+}
 
-    info!("path: {}", path);
+fn connector_subpixel(connector: &connector::Info) -> Subpixel {
+    match connector.subpixel() {
+        connector::SubPixel::HorizontalRgb => Subpixel::HorizontalRgb,
+        connector::SubPixel::HorizontalBgr => Subpixel::HorizontalBgr,
+        connector::SubPixel::VerticalRgb => Subpixel::VerticalRgb,
+        connector::SubPixel::VerticalBgr => Subpixel::VerticalBgr,
+        connector::SubPixel::None => Subpixel::None,
+        _ => Subpixel::Unknown,
+    }
+}
 
-    let file = match std::fs::File::open(path) {
-        Ok(file) => file,
-        Err(err) => {
-            error!("Failed to open file: {}", err);
-            return None;
-        }
+/// Picks a scale that brings the monitor close to 96 DPI, in steps of 25%.
+/// Projectors and TVs don't report a physical size, so they stay unscaled.
+fn default_output_scale(physical_size_mm: (u32, u32), mode_size: Size<i32, Physical>) -> f64 {
+    let (width_mm, _) = physical_size_mm;
+    if width_mm == 0 {
+        return 1.0;
+    }
+    let dpi = mode_size.w as f64 / (width_mm as f64 / 25.4);
+    let scale = (dpi / 96.0 * 4.0).round() / 4.0;
+    scale.clamp(1.0, 3.0)
+}
+
+/// The `GAMMA_LUT` property of the CRTC and its number of entries, on atomic drivers.
+/// It's often larger than the legacy gamma ramps, which not every driver maps onto it.
+fn get_gamma_lut(drm_device: &DrmDevice, crtc: crtc::Handle) -> Option<(property::Handle, usize)> {
+    if !drm_device.is_atomic() {
+        return None;
+    }
+    let (gamma_lut, _) = get_drm_property(drm_device, crtc, "GAMMA_LUT")?;
+    let (_, size) = get_drm_property(drm_device, crtc, "GAMMA_LUT_SIZE")?;
+    Some((gamma_lut.handle(), size as usize)).filter(|(_, size)| *size > 0)
+}
+
+fn get_gamma_size(drm_device: &DrmDevice, crtc: crtc::Handle) -> Option<usize> {
+    if let Some((_, size)) = get_gamma_lut(drm_device, crtc) {
+        return Some(size);
+    }
+    drm_device
+        .get_crtc(crtc)
+        .ok()
+        .map(|info| info.gamma_length() as usize)
+        .filter(|size| *size > 0)
+}
+
+/// Sets the red, green and blue ramps of the CRTC, or linear ones when `ramp` is `None`.
+fn set_crtc_gamma(
+    drm_device: &DrmDevice,
+    crtc: crtc::Handle,
+    ramp: Option<&[u16]>,
+) -> std::io::Result<()> {
+    let gamma_lut = get_gamma_lut(drm_device, crtc);
+    let size = match gamma_lut {
+        Some((_, size)) => size,
+        None => get_gamma_size(drm_device, crtc).ok_or(std::io::ErrorKind::Unsupported)?,
     };
-    let reader = std::io::BufReader::new(file);
-    let json: serde_json::Value = match serde_json::from_reader(reader) {
-        Ok(json) => json,
-        Err(err) => {
-            error!("Failed to parse JSON: {}", err);
-            return None;
+
+    let linear_ramp;
+    let ramp = match ramp {
+        Some(ramp) => ramp,
+        None => {
+            linear_ramp = (0..size * 3)
+                .map(|i| ((i % size) * u16::MAX as usize / (size - 1).max(1)) as u16)
+                .collect::<Vec<_>>();
+            &linear_ramp
         }
     };
-    info!("json: {:?}", json);
-    let mode = json["selectedMode"].as_u64();
+    if ramp.len() != size * 3 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Expected {} gamma entries, got {}", size * 3, ramp.len()),
+        ));
+    }
 
-    match mode {
-        Some(mode) => Some(mode as usize),
-        None => {
-            error!("selectedMode not found in JSON");
-            None
+    let (red, rest) = ramp.split_at(size);
+    let (green, blue) = rest.split_at(size);
+    match gamma_lut {
+        Some((gamma_lut, _)) => set_crtc_gamma_lut(drm_device, crtc, gamma_lut, red, green, blue),
+        None => drm_device.set_gamma(crtc, red, green, blue),
+    }
+}
+
+/// Commits the ramps as a blob of `struct drm_color_lut`.
+/// The commit is blocking, so it waits for the page flips of the DRM compositor instead of failing.
+fn set_crtc_gamma_lut(
+    drm_device: &DrmDevice,
+    crtc: crtc::Handle,
+    gamma_lut: property::Handle,
+    red: &[u16],
+    green: &[u16],
+    blue: &[u16],
+) -> std::io::Result<()> {
+    let mut data = Vec::with_capacity(red.len() * 4 * std::mem::size_of::<u16>());
+    for ((red, green), blue) in red.iter().zip(green).zip(blue) {
+        for value in [*red, *green, *blue, 0] {
+            data.extend_from_slice(&value.to_ne_bytes());
         }
     }
+    let blob = drm_ffi::mode::create_property_blob(drm_device.as_fd(), &mut data)?;
+
+    let mut request = AtomicModeReq::new();
+    request.add_property(crtc, gamma_lut, property::Value::Blob(blob.blob_id as u64));
+    let result = drm_device.atomic_commit(AtomicCommitFlags::empty(), request);
+    // The CRTC keeps its own reference to the blob.
+    if let Err(err) = drm_device.destroy_property_blob(blob.blob_id as u64) {
+        warn!(?crtc, "Failed to destroy the gamma blob: {}", err);
+    }
+    result
+}
+
+fn get_drm_property(
+    drm_device: &DrmDevice,
+    handle: impl ResourceHandle,
+    name: &str,
+) -> Option<(property::Info, property::RawValue)> {
+    drm_device
+        .get_properties(handle)
+        .ok()?
+        .into_iter()
+        .filter_map(|(handle, value)| {
+            let info = drm_device.get_property(handle).ok()?;
+            Some((info, value))
+        })
+        .find(|(info, _)| info.name().to_str() == Ok(name))
+}
+
+fn set_vrr_enabled(
+    drm_device: &DrmDevice,
+    crtc: crtc::Handle,
+    enabled: bool,
+) -> std::io::Result<()> {
+    let (info, _) = get_drm_property(drm_device, crtc, "VRR_ENABLED").ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "VRR_ENABLED property not found",
+        )
+    })?;
+    if !drm_device.is_atomic() {
+        return drm_device.set_property(crtc, info.handle(), enabled as property::RawValue);
+    }
+    let mut request = AtomicModeReq::new();
+    request.add_property(crtc, info.handle(), property::Value::Boolean(enabled));
+    // Blocking, and without a page flip event, so that the DRM compositor doesn't get one it didn't ask for.
+    drm_device.atomic_commit(AtomicCommitFlags::empty(), request)
+}
+
+/// Builds the dmabuf feedback sent to clients drawn on this surface.
+/// The scanout feedback advertises the formats of the planes first,
+/// so that a fullscreen client can allocate buffers the display controller can read directly.
+fn get_surface_dmabuf_feedback(
+    node: DrmNode,
+    render_node: DrmNode,
+    texture_formats: Vec<Format>,
+    compositor: &GbmDrmCompositor,
+) -> Option<SurfaceDmabufFeedback> {
+    let surface = compositor.surface();
+    let planes = surface.planes().clone();
+    let planes_formats = surface
+        .plane_info()
+        .formats
+        .iter()
+        .copied()
+        .chain(planes.overlay.into_iter().flat_map(|plane| plane.formats))
+        .filter(|format| texture_formats.contains(format))
+        .collect::<Vec<_>>();
+
+    let builder = DmabufFeedbackBuilder::new(node.dev_id(), texture_formats.clone());
+    let render_feedback = builder
+        .clone()
+        .add_preference_tranche(render_node.dev_id(), None, texture_formats.clone())
+        .build()
+        .ok()?;
+    let scanout_feedback = builder
+        .add_preference_tranche(
+            node.dev_id(),
+            Some(zwp_linux_dmabuf_feedback_v1::TrancheFlags::Scanout),
+            planes_formats,
+        )
+        .add_preference_tranche(render_node.dev_id(), None, texture_formats)
+        .build()
+        .ok()?;
+
+    Some(SurfaceDmabufFeedback {
+        render_feedback,
+        scanout_feedback,
+    })
 }
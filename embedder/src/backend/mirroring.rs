@@ -0,0 +1,190 @@
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+use smithay::output::Output;
+use smithay::utils::{Physical, Point, Rectangle, Size};
+use tracing::info;
+
+use crate::{output_canvas_geometry, state::State, Backend};
+
+/// How the content of the source output is fitted on a mirror with another size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MirrorScaling {
+    /// Keeps the aspect ratio and fills the rest with black bars.
+    #[default]
+    Letterbox,
+    Stretch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Mirror {
+    /// The name of the output being replicated.
+    pub source: String,
+    pub scaling: MirrorScaling,
+}
+
+struct OutputMirror(RefCell<Option<Mirror>>);
+
+/// What `output` replicates, if it's a mirror.
+pub fn output_mirror(output: &Output) -> Option<Mirror> {
+    output
+        .user_data()
+        .get::<OutputMirror>()
+        .and_then(|mirror| mirror.0.borrow().clone())
+}
+
+fn set_output_mirror(output: &Output, mirror: Option<Mirror>) {
+    output
+        .user_data()
+        .insert_if_missing(|| OutputMirror(RefCell::new(None)));
+    *output
+        .user_data()
+        .get::<OutputMirror>()
+        .unwrap()
+        .0
+        .borrow_mut() = mirror;
+}
+
+/// Where the whole source lands on a mirror of `target_size`, both in physical pixels.
+pub fn mirror_destination(
+    source_size: Size<i32, Physical>,
+    target_size: Size<i32, Physical>,
+    scaling: MirrorScaling,
+) -> Rectangle<i32, Physical> {
+    match scaling {
+        MirrorScaling::Stretch => Rectangle::from_loc_and_size((0, 0), target_size),
+        MirrorScaling::Letterbox => {
+            if source_size.w <= 0 || source_size.h <= 0 {
+                return Rectangle::from_loc_and_size((0, 0), target_size);
+            }
+            let ratio = f64::min(
+                target_size.w as f64 / source_size.w as f64,
+                target_size.h as f64 / source_size.h as f64,
+            );
+            let size = Size::<i32, Physical>::from((
+                (source_size.w as f64 * ratio).round() as i32,
+                (source_size.h as f64 * ratio).round() as i32,
+            ));
+            let location = Point::<i32, Physical>::from((
+                (target_size.w - size.w) / 2,
+                (target_size.h - size.h) / 2,
+            ));
+            Rectangle::from_loc_and_size(location, size)
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MirrorError {
+    #[error("Monitor {0} doesn't exist")]
+    UnknownSource(String),
+    #[error("Monitor {0} can't mirror itself")]
+    SelfMirror(String),
+}
+
+impl<BackendData: Backend + 'static> State<BackendData> {
+    /// Every connected output, including the mirrors and disabled outputs that aren't part of the canvas.
+    pub fn all_outputs(&self) -> Vec<Output> {
+        self.space
+            .outputs()
+            .chain(self.mirrored_outputs.iter())
+            .chain(self.disabled_outputs.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Makes `output` replicate `source` instead of showing its own region of the canvas.
+    /// The mirror leaves the space, so neither windows nor the pointer can end up on it.
+    pub fn mirror_output(
+        &mut self,
+        output: &Output,
+        source: &str,
+        scaling: MirrorScaling,
+    ) -> Result<(), MirrorError> {
+        if output.name() == source {
+            return Err(MirrorError::SelfMirror(source.to_string()));
+        }
+        // Mirrors aren't in the space, so this also rules out mirroring a mirror.
+        if !self.space.outputs().any(|output| output.name() == source) {
+            return Err(MirrorError::UnknownSource(source.to_string()));
+        }
+
+        // Mirrors of this output follow its new source.
+        for mirror in &self.mirrored_outputs {
+            if let Some(mut other) = output_mirror(mirror).filter(|m| m.source == output.name()) {
+                other.source = source.to_string();
+                set_output_mirror(mirror, Some(other));
+            }
+        }
+
+        info!("{} now mirrors {}", output.name(), source);
+        set_output_mirror(
+            output,
+            Some(Mirror {
+                source: source.to_string(),
+                scaling,
+            }),
+        );
+        if !self.mirrored_outputs.contains(output) {
+            self.space.unmap_output(output);
+            self.mirrored_outputs.push(output.clone());
+        }
+
+        self.arrange_monitors();
+        Ok(())
+    }
+
+    /// Gives a mirror its own region of the canvas back, at the right of the other outputs.
+    pub fn stop_mirroring(&mut self, output: &Output) {
+        if self.unmirror_output(output) {
+            self.arrange_monitors();
+        }
+    }
+
+    fn unmirror_output(&mut self, output: &Output) -> bool {
+        let Some(index) = self.mirrored_outputs.iter().position(|o| o == output) else {
+            return false;
+        };
+        info!("{} stops mirroring", output.name());
+        self.mirrored_outputs.remove(index);
+        set_output_mirror(output, None);
+
+        let x = self
+            .space
+            .outputs()
+            .map(|output| {
+                let geometry = output_canvas_geometry(output);
+                geometry.loc.x + geometry.size.w
+            })
+            .max()
+            .unwrap_or(0);
+        output.change_current_state(None, None, None, Some((x, 0).into()));
+        self.space.map_output(output, (x, 0));
+        true
+    }
+
+    /// Mirrors whose source was unplugged go back to extending the canvas.
+    /// Called when arranging the monitors.
+    pub fn stop_orphaned_mirrors(&mut self) {
+        let orphans = self
+            .mirrored_outputs
+            .iter()
+            .filter(|output| {
+                output_mirror(output)
+                    .map(|mirror| {
+                        !self
+                            .space
+                            .outputs()
+                            .any(|source| source.name() == mirror.source)
+                    })
+                    .unwrap_or(true)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for output in orphans {
+            self.unmirror_output(&output);
+        }
+    }
+}
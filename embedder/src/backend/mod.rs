@@ -1,12 +1,51 @@
 use smithay::backend::session::libseat::LibSeatSession;
+use smithay::output::{Mode, Output};
+use smithay::utils::{Logical, Size};
 
 pub mod drm_backend;
+pub mod mirroring;
+pub mod monitor_profiles;
 pub mod x11_client;
 
+/// When adaptive sync should be enabled on a monitor, as configured by the shell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VrrMode {
+    Always,
+    /// Only while a fullscreen surface is scanned out directly.
+    Fullscreen,
+    Off,
+}
+
 pub trait Backend {
     const HAS_RELATIVE_MOTION: bool = false;
 
     fn seat_name(&self) -> String;
 
     fn get_session(&self) -> LibSeatSession;
+
+    /// Number of entries per channel in the gamma ramp of `output`, if its gamma can be changed.
+    fn gamma_size(&self, _output: &Output) -> Option<usize> {
+        None
+    }
+
+    /// Sets the red, green and blue gamma ramps of `output`, packed one after the other.
+    /// `None` resets it to a linear ramp.
+    fn set_gamma(&mut self, _output: &Output, _ramp: Option<Vec<u16>>) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    /// Switches `output` to another of the modes it advertises.
+    fn set_output_mode(&mut self, _output: &Output, _mode: Mode) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    /// Turns the display of `output` off, or back on.
+    fn set_output_power(&mut self, _output: &Output, _on: bool) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    /// Monitors were added, removed, moved or changed mode,
+    /// and the canvas Flutter draws them all on is now `canvas_size`.
+    fn monitor_layout_changed(&mut self, _outputs: &[Output], _canvas_size: Size<i32, Logical>) {}
 }
@@ -0,0 +1,498 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use smithay::output::{Mode, Output, Scale};
+use smithay::utils::{Point, Rectangle, Transform};
+use smithay_drm_extras::edid::EdidInfo;
+use tracing::{info, warn};
+
+use crate::{output_canvas_geometry, state::State, Backend};
+
+/// Identifies a monitor regardless of the connector, dock or GPU it's plugged into.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorIdentity {
+    pub make: String,
+    pub model: String,
+    /// Two monitors of the same model are told apart by their serial.
+    pub serial: Option<String>,
+}
+
+impl MonitorIdentity {
+    /// Lets the backend look a monitor up before its output exists.
+    pub fn from_edid(edid_info: Option<&EdidInfo>, connector_name: &str) -> Self {
+        match edid_info {
+            Some(edid_info) => Self {
+                make: edid_info.manufacturer.clone(),
+                model: edid_info.model.clone(),
+                serial: edid_info.serial.clone(),
+            },
+            // Without EDID, the connector is all we have to recognize the monitor.
+            None => Self {
+                make: "Unknown".into(),
+                model: connector_name.into(),
+                serial: None,
+            },
+        }
+    }
+
+    pub fn from_output(output: &Output) -> Self {
+        Self::from_edid(output.user_data().get::<EdidInfo>(), &output.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileMode {
+    pub width: i32,
+    pub height: i32,
+    /// In mHz.
+    pub refresh_rate: i32,
+}
+
+impl From<Mode> for ProfileMode {
+    fn from(mode: Mode) -> Self {
+        Self {
+            width: mode.size.w,
+            height: mode.size.h,
+            refresh_rate: mode.refresh,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProfileTransform {
+    #[serde(rename = "normal")]
+    Normal,
+    #[serde(rename = "90")]
+    _90,
+    #[serde(rename = "180")]
+    _180,
+    #[serde(rename = "270")]
+    _270,
+    #[serde(rename = "flipped")]
+    Flipped,
+    #[serde(rename = "flipped90")]
+    Flipped90,
+    #[serde(rename = "flipped180")]
+    Flipped180,
+    #[serde(rename = "flipped270")]
+    Flipped270,
+}
+
+impl From<ProfileTransform> for Transform {
+    fn from(transform: ProfileTransform) -> Self {
+        match transform {
+            ProfileTransform::Normal => Transform::Normal,
+            ProfileTransform::_90 => Transform::_90,
+            ProfileTransform::_180 => Transform::_180,
+            ProfileTransform::_270 => Transform::_270,
+            ProfileTransform::Flipped => Transform::Flipped,
+            ProfileTransform::Flipped90 => Transform::Flipped90,
+            ProfileTransform::Flipped180 => Transform::Flipped180,
+            ProfileTransform::Flipped270 => Transform::Flipped270,
+        }
+    }
+}
+
+impl From<Transform> for ProfileTransform {
+    fn from(transform: Transform) -> Self {
+        match transform {
+            Transform::Normal => ProfileTransform::Normal,
+            Transform::_90 => ProfileTransform::_90,
+            Transform::_180 => ProfileTransform::_180,
+            Transform::_270 => ProfileTransform::_270,
+            Transform::Flipped => ProfileTransform::Flipped,
+            Transform::Flipped90 => ProfileTransform::Flipped90,
+            Transform::Flipped180 => ProfileTransform::Flipped180,
+            Transform::Flipped270 => ProfileTransform::Flipped270,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfilePosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// How a monitor is set up. Missing fields keep the defaults of the backend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorProfile {
+    pub identity: MonitorIdentity,
+    #[serde(default)]
+    pub mode: Option<ProfileMode>,
+    #[serde(default)]
+    pub scale: Option<f64>,
+    #[serde(default)]
+    pub position: Option<ProfilePosition>,
+    #[serde(default)]
+    pub transform: Option<ProfileTransform>,
+}
+
+impl MonitorProfile {
+    fn from_output(output: &Output) -> Self {
+        let location = output.current_location();
+        Self {
+            identity: MonitorIdentity::from_output(output),
+            mode: output.current_mode().map(ProfileMode::from),
+            scale: Some(output.current_scale().fractional_scale()),
+            position: Some(ProfilePosition {
+                x: location.x,
+                y: location.y,
+            }),
+            transform: Some(output.current_transform().into()),
+        }
+    }
+}
+
+/// The setup of a whole combination of monitors, like the docking station at the office.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorLayout {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub monitors: Vec<MonitorProfile>,
+}
+
+impl MonitorLayout {
+    fn matches(&self, identities: &HashSet<&MonitorIdentity>) -> bool {
+        self.monitors.len() == identities.len()
+            && self
+                .monitors
+                .iter()
+                .all(|profile| identities.contains(&profile.identity))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MonitorProfilesError {
+    #[error("Failed to access the monitor profiles: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid monitor profiles: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Monitor setups remembered across sessions, in `$XDG_CONFIG_HOME/veshell/monitors.json`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorProfiles {
+    #[serde(default)]
+    pub monitors: Vec<MonitorProfile>,
+    #[serde(default)]
+    pub layouts: Vec<MonitorLayout>,
+}
+
+/// What the shell saved per connector before monitor profiles existed, in
+/// `$XDG_CONFIG_HOME/veshell/persistence/Monitor/<connector>.json`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyMonitorConfiguration {
+    /// Index in the modes of the connector.
+    #[serde(default)]
+    selected_mode: Option<usize>,
+}
+
+fn veshell_config_dir() -> PathBuf {
+    let config_home = std::env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(std::env::var("HOME").unwrap()).join(".config"));
+    config_home.join("veshell")
+}
+
+impl MonitorProfiles {
+    pub fn path() -> PathBuf {
+        veshell_config_dir().join("monitors.json")
+    }
+
+    /// Starts from scratch when there's no file yet or it can't be read.
+    pub fn load() -> Self {
+        let path = Self::path();
+        match Self::read(&path) {
+            Ok(profiles) => profiles,
+            Err(MonitorProfilesError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                Self::default()
+            }
+            Err(err) => {
+                warn!("Ignoring {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    fn read(path: &PathBuf) -> Result<Self, MonitorProfilesError> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Gives a monitor without a profile the mode the shell used to remember for its connector.
+    /// Returns whether a profile was added.
+    pub fn migrate_legacy_mode(
+        &mut self,
+        identity: &MonitorIdentity,
+        connector_name: &str,
+        modes: &[Mode],
+    ) -> bool {
+        if self
+            .monitors
+            .iter()
+            .any(|profile| profile.identity == *identity)
+        {
+            return false;
+        }
+
+        let path = veshell_config_dir()
+            .join("persistence")
+            .join("Monitor")
+            .join(format!("{}.json", connector_name));
+        let legacy = match Self::read_legacy(&path) {
+            Ok(legacy) => legacy,
+            Err(MonitorProfilesError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                return false;
+            }
+            Err(err) => {
+                warn!("Ignoring {}: {}", path.display(), err);
+                return false;
+            }
+        };
+        let Some(mode) = legacy.selected_mode.and_then(|index| modes.get(index)) else {
+            return false;
+        };
+
+        info!(
+            "Migrating the mode of {} from {}",
+            connector_name,
+            path.display()
+        );
+        self.monitors.push(MonitorProfile {
+            identity: identity.clone(),
+            mode: Some((*mode).into()),
+            scale: None,
+            position: None,
+            transform: None,
+        });
+        true
+    }
+
+    fn read_legacy(path: &PathBuf) -> Result<LegacyMonitorConfiguration, MonitorProfilesError> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    pub fn save(&self) -> Result<(), MonitorProfilesError> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write then rename so that a crash never leaves a truncated file behind.
+        let temp_path = path.with_extension("json.temp");
+        std::fs::write(&temp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(temp_path, path)?;
+        Ok(())
+    }
+
+    /// The profile of a monitor within the combination of `connected` monitors.
+    /// A layout saved for that exact combination wins over the profile of the monitor alone.
+    pub fn profile_for(
+        &self,
+        identity: &MonitorIdentity,
+        connected: &[MonitorIdentity],
+    ) -> Option<&MonitorProfile> {
+        let connected = connected.iter().collect::<HashSet<_>>();
+        self.layouts
+            .iter()
+            .find(|layout| layout.matches(&connected))
+            .and_then(|layout| {
+                layout
+                    .monitors
+                    .iter()
+                    .find(|profile| profile.identity == *identity)
+            })
+            .or_else(|| {
+                self.monitors
+                    .iter()
+                    .find(|profile| profile.identity == *identity)
+            })
+    }
+
+    /// Remembers the current setup of each monitor and of their combination.
+    fn remember(&mut self, outputs: &[Output]) {
+        let profiles = outputs
+            .iter()
+            .map(MonitorProfile::from_output)
+            .collect::<Vec<_>>();
+
+        for profile in &profiles {
+            match self
+                .monitors
+                .iter_mut()
+                .find(|existing| existing.identity == profile.identity)
+            {
+                Some(existing) => *existing = profile.clone(),
+                None => self.monitors.push(profile.clone()),
+            }
+        }
+
+        let connected = profiles
+            .iter()
+            .map(|profile| &profile.identity)
+            .collect::<HashSet<_>>();
+        match self
+            .layouts
+            .iter_mut()
+            .find(|layout| layout.matches(&connected))
+        {
+            Some(layout) => layout.monitors = profiles,
+            None => self.layouts.push(MonitorLayout {
+                name: None,
+                monitors: profiles,
+            }),
+        }
+    }
+}
+
+/// Picks the mode of a profile among the modes of the monitor.
+/// Falls back to the closest refresh rate at the same resolution.
+pub fn find_mode(modes: &[Mode], wanted: ProfileMode) -> Option<Mode> {
+    modes
+        .iter()
+        .filter(|mode| mode.size.w == wanted.width && mode.size.h == wanted.height)
+        .min_by_key(|mode| (mode.refresh - wanted.refresh_rate).abs())
+        .copied()
+}
+
+impl<BackendData: Backend + 'static> State<BackendData> {
+    fn connected_monitor_identities(&self) -> Vec<MonitorIdentity> {
+        self.space
+            .outputs()
+            .map(MonitorIdentity::from_output)
+            .collect()
+    }
+
+    /// The profile to use for `output`, given the monitors currently mapped.
+    pub fn monitor_profile(&self, output: &Output) -> Option<MonitorProfile> {
+        let mut connected = self.connected_monitor_identities();
+        let identity = MonitorIdentity::from_output(output);
+        if !connected.contains(&identity) {
+            connected.push(identity.clone());
+        }
+        self.monitor_profiles
+            .profile_for(&identity, &connected)
+            .cloned()
+    }
+
+    /// Sets every monitor up as remembered for the current combination of monitors.
+    /// Called whenever a monitor is plugged or unplugged.
+    pub fn apply_monitor_profiles(&mut self) {
+        let outputs = self.space.outputs().cloned().collect::<Vec<_>>();
+
+        for output in &outputs {
+            let Some(profile) = self.monitor_profile(output) else {
+                continue;
+            };
+            info!("Applying the monitor profile of {}", output.name());
+
+            if let Some(mode) = profile.mode {
+                let current_mode = output.current_mode();
+                match find_mode(&output.modes(), mode) {
+                    Some(mode) if Some(mode) != current_mode => {
+                        if let Err(err) = self.backend_data.set_output_mode(output, mode) {
+                            warn!("Failed to change the mode of {}: {}", output.name(), err);
+                        } else {
+                            output.change_current_state(Some(mode), None, None, None);
+                        }
+                    }
+                    Some(_) => {}
+                    None => warn!("{} doesn't support the mode {:?}", output.name(), mode),
+                }
+            }
+            if let Some(scale) = profile
+                .scale
+                .filter(|scale| scale.is_finite() && *scale > 0.0)
+            {
+                output.change_current_state(None, None, Some(Scale::Fractional(scale)), None);
+                self.output_scale_changed(output);
+            }
+            if let Some(transform) = profile.transform {
+                output.change_current_state(None, Some(transform.into()), None, None);
+            }
+            if let Some(position) = profile.position {
+                let location = Point::from((position.x, position.y));
+                output.change_current_state(None, None, None, Some(location));
+                self.space.map_output(output, location);
+            }
+        }
+
+        self.arrange_monitors();
+    }
+
+    /// Saves the current setup so that it's restored the next time these monitors are plugged.
+    pub fn remember_monitor_profiles(&mut self) {
+        let outputs = self.space.outputs().cloned().collect::<Vec<_>>();
+        self.monitor_profiles.remember(&outputs);
+        if let Err(err) = self.monitor_profiles.save() {
+            warn!("Failed to save the monitor profiles: {}", err);
+        }
+    }
+
+    /// Keeps the monitors from overlapping and the canvas starting at the origin,
+    /// then resizes the canvas Flutter draws all monitors on.
+    pub fn arrange_monitors(&mut self) {
+        if self.space.outputs().next().is_none() {
+            self.enable_disabled_outputs();
+        }
+        self.stop_orphaned_mirrors();
+
+        let mut outputs = self.space.outputs().cloned().collect::<Vec<_>>();
+        outputs.sort_by_key(|output| output.current_location().x);
+
+        let mut placed: Vec<Rectangle<i32, smithay::utils::Logical>> = vec![];
+        for output in &outputs {
+            let mut geometry = output_canvas_geometry(output);
+            if placed.iter().any(|other| other.overlaps(geometry)) {
+                // Put monitors without a place of their own at the right of the others.
+                geometry.loc = (
+                    placed
+                        .iter()
+                        .map(|other| other.loc.x + other.size.w)
+                        .max()
+                        .unwrap_or(0),
+                    0,
+                )
+                    .into();
+            }
+            placed.push(geometry);
+        }
+
+        let origin = placed
+            .iter()
+            .map(|geometry| geometry.loc)
+            .reduce(|a, b| (a.x.min(b.x), a.y.min(b.y)).into())
+            .unwrap_or_default();
+        for (output, geometry) in outputs.iter().zip(placed.iter()) {
+            let location = geometry.loc - origin;
+            if location != output.current_location() {
+                output.change_current_state(None, None, None, Some(location));
+                self.space.map_output(output, location);
+            }
+        }
+
+        let canvas_size = self
+            .space
+            .outputs()
+            .map(output_canvas_geometry)
+            .reduce(|first, second| first.merge(second))
+            .unwrap_or_default()
+            .size;
+        let outputs = self.all_outputs();
+        self.backend_data
+            .monitor_layout_changed(&outputs, canvas_size);
+        self.flutter_engine()
+            .send_window_metrics((canvas_size.w as u32, canvas_size.h as u32).into())
+            .unwrap();
+        self.flutter_engine_mut().monitor_layout_changed(outputs);
+        self.update_xwayland_scale();
+    }
+}
@@ -8,7 +8,6 @@ use smithay::backend::renderer::{ImportDma, ImportEgl};
 use smithay::output::{Output, PhysicalProperties, Subpixel};
 use smithay::reexports::ash::ext;
 use smithay::reexports::calloop::channel::Event::Msg;
-use smithay::reexports::wayland_server::protocol::wl_shm;
 use smithay::reexports::x11rb::protocol::xproto::{
     AutoRepeatMode, ChangeKeyboardControlAux, ConnectionExt,
 };
@@ -36,7 +35,7 @@ use tracing::info;
 
 use crate::flutter_engine::FlutterEngine;
 use crate::state;
-use crate::{flutter_engine::EmbedderChannels, send_frames_surface_tree, State};
+use crate::{flutter_engine::EmbedderChannels, State};
 
 use super::Backend;
 
@@ -201,11 +200,7 @@ pub fn run_x11_client() {
         .send_window_metrics((size.w as u32, size.h as u32).into())
         .unwrap();
 
-    // Mandatory formats by the Wayland spec.
-    // TODO: Add more formats based on the GLES version.
-    state
-        .shm_state
-        .update_formats([wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888]);
+    state.update_shm_formats();
 
     event_loop
         .handle()
@@ -246,21 +241,9 @@ pub fn run_x11_client() {
                     for baton in drained {
                         data.flutter_engine().on_vsync(baton, 144_000).unwrap();
                     }
-                    let start_time = std::time::Instant::now();
-                    for surface in data.xdg_shell_state.toplevel_surfaces() {
-                        send_frames_surface_tree(
-                            surface.wl_surface(),
-                            start_time.elapsed().as_millis() as u32,
-                        );
-                    }
-                    for surface in data.xdg_popups.values() {
-                        send_frames_surface_tree(
-                            surface.wl_surface(),
-                            start_time.elapsed().as_millis() as u32,
-                        );
-                    }
-                    for surface in data.x11_surface_per_wl_surface.keys() {
-                        send_frames_surface_tree(surface, start_time.elapsed().as_millis() as u32);
+                    let outputs = data.space.outputs().cloned().collect::<Vec<_>>();
+                    for (index, output) in outputs.iter().enumerate() {
+                        data.send_frame_callbacks(output, index == 0);
                     }
                 }
 
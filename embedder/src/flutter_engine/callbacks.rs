@@ -218,12 +218,19 @@ where
     let flutter_engine = &mut *(user_data as *mut FlutterEngine<BackendData>);
     let channels = &mut flutter_engine.data.channels;
 
-    let (texture_name, format) = channels
+    let (texture_name, format, fences) = channels
         .tx_request_external_texture_name
         .send(texture_id)
         .ok()
         .and_then(|()| channels.rx_external_texture_name.recv().ok())
-        .unwrap_or((0, ffi::RGBA8));
+        .unwrap_or((0, ffi::RGBA8, vec![]));
+
+    // Makes the GPU wait for the compositor and the client to finish writing the texture
+    // before Flutter samples it.
+    let data = &flutter_engine.data;
+    for fence in fences {
+        fence.wait(&data.gl, data.main_egl_context.display());
+    }
 
     let texture_out = &mut *texture_out;
 
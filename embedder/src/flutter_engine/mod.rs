@@ -23,7 +23,8 @@ use smithay::{
         egl::{
             self,
             context::{GlAttributes, GlProfile, PixelFormatRequirements},
-            EGLContext,
+            ffi::egl as egl_ffi,
+            EGLContext, EGLDisplay,
         },
         renderer::gles::ffi::Gles2,
     },
@@ -42,7 +43,7 @@ use crate::flutter_engine::embedder::{
     FlutterEngineAOTDataSource__bindgen_ty_1, FlutterEngineCreateAOTData, FlutterEngineInitialize,
     FlutterEngineMarkExternalTextureFrameAvailable, FlutterEngineRegisterExternalTexture,
     FlutterEngineRunInitialized, FlutterEngineRunTask, FlutterEngineSendPointerEvent,
-    FlutterPointerEvent, FlutterTaskRunnerDescription,
+    FlutterEngineUnregisterExternalTexture, FlutterPointerEvent, FlutterTaskRunnerDescription,
 };
 use crate::flutter_engine::platform_channel_callbacks::platform_channel_method_handler;
 use crate::flutter_engine::platform_channels::basic_message_channel::BasicMessageChannel;
@@ -59,6 +60,7 @@ use crate::flutter_engine::wayland_messages::{EnvironmentVariables, MonitorsMess
 use crate::gles_framebuffer_importer::GlesFramebufferImporter;
 use crate::keyboard::KeyEvent;
 use crate::mouse_button_tracker::MouseButtonTracker;
+use crate::texture_swap_chain::TextureFence;
 use crate::{
     flutter_engine::{
         callbacks::{
@@ -67,9 +69,10 @@ use crate::{
         },
         embedder::{
             FlutterEngine as FlutterEngineHandle, FlutterEngineGetCurrentTime,
-            FlutterEngineOnVsync, FlutterEngineSendWindowMetricsEvent, FlutterEngineShutdown,
-            FlutterOpenGLRendererConfig, FlutterProjectArgs, FlutterRendererConfig,
-            FlutterRendererConfig__bindgen_ty_1, FlutterWindowMetricsEvent, FLUTTER_ENGINE_VERSION,
+            FlutterEngineOnVsync, FlutterEngineScheduleFrame, FlutterEngineSendWindowMetricsEvent,
+            FlutterEngineShutdown, FlutterOpenGLRendererConfig, FlutterProjectArgs,
+            FlutterRendererConfig, FlutterRendererConfig__bindgen_ty_1, FlutterWindowMetricsEvent,
+            FLUTTER_ENGINE_VERSION,
         },
     },
     State,
@@ -122,7 +125,8 @@ impl<BackendData: Backend + 'static> FlutterEngine<BackendData> {
             channel::channel::<Duration>();
         let (tx_request_external_texture_name, rx_request_external_texture_name) =
             channel::channel::<i64>();
-        let (tx_external_texture_name, rx_external_texture_name) = channel::channel::<(u32, u32)>();
+        let (tx_external_texture_name, rx_external_texture_name) =
+            channel::channel::<(u32, u32, Vec<TextureFence>)>();
 
         let flutter_engine_channels = FlutterEngineChannels {
             tx_present,
@@ -391,15 +395,18 @@ impl<BackendData: Backend + 'static> FlutterEngine<BackendData> {
             .loop_handle
             .insert_source(rx_request_external_texture_name, move |event, _, data| {
                 if let Msg(texture_id) = event {
+                    if let Some(surface_id) = data.surface_id_per_texture_id.get(&texture_id) {
+                        data.surface_ids_sampled_by_flutter.insert(*surface_id);
+                    }
                     let texture_swap_chain = data.texture_swapchains.get_mut(&texture_id);
-                    let texture_id = match texture_swap_chain {
+                    let (texture_id, fences) = match texture_swap_chain {
                         Some(texture) => {
-                            let texture = texture.start_read();
-                            texture.tex_id()
+                            let (texture, fences) = texture.start_read();
+                            (texture.tex_id(), fences)
                         }
-                        None => 0,
+                        None => (0, vec![]),
                     };
-                    let _ = tx_external_texture_name.send((texture_id, RGBA8));
+                    let _ = tx_external_texture_name.send((texture_id, RGBA8, fences));
                 }
             })
             .unwrap();
@@ -467,6 +474,15 @@ impl<BackendData: Backend + 'static> FlutterEngine<BackendData> {
         Ok(())
     }
 
+    /// Asks Flutter to redraw even though nothing changed.
+    pub fn schedule_frame(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let result = unsafe { FlutterEngineScheduleFrame(self.handle) };
+        if result != 0 {
+            return Err(format!("Could not schedule a frame, error {result}").into());
+        }
+        Ok(())
+    }
+
     /// mhz == millihertz
     pub fn on_vsync(&self, baton: Baton, mhz: u32) -> Result<(), Box<dyn std::error::Error>> {
         let now = unsafe { FlutterEngineGetCurrentTime() };
@@ -544,6 +560,17 @@ impl<BackendData: Backend + 'static> FlutterEngine<BackendData> {
         Ok(())
     }
 
+    pub fn unregister_external_texture(
+        &self,
+        texture_id: i64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let result = unsafe { FlutterEngineUnregisterExternalTexture(self.handle, texture_id) };
+        if result != 0 {
+            return Err(format!("Could not unregister external texture, error {result}").into());
+        }
+        Ok(())
+    }
+
     pub fn mark_external_texture_frame_available(
         &self,
         texture_id: i64,
@@ -625,7 +652,13 @@ impl FlutterEngineData {
             debug: false,
             vsync: false,
         };
-        let pixel_format_requirements = PixelFormatRequirements::_8_bit();
+        // Prefer a 10-bit config to match the 10-bit swapchain, but not every driver exposes one.
+        let pixel_format_requirements =
+            if std::env::var("ANVIL_DISABLE_10BIT").is_err() && has_10_bit_config(&egl_display) {
+                PixelFormatRequirements::_10_bit()
+            } else {
+                PixelFormatRequirements::_8_bit()
+            };
 
         Ok(Self {
             gl: Gles2::load_with(|s| unsafe { egl::get_proc_address(s) } as *const _),
@@ -648,6 +681,37 @@ impl FlutterEngineData {
     }
 }
 
+/// Whether the display has a GLES 2 window config with 10-bit color channels,
+/// the one `PixelFormatRequirements::_10_bit` asks for.
+fn has_10_bit_config(egl_display: &EGLDisplay) -> bool {
+    let attributes = [
+        egl_ffi::SURFACE_TYPE as egl_ffi::types::EGLint,
+        egl_ffi::WINDOW_BIT as egl_ffi::types::EGLint,
+        egl_ffi::RENDERABLE_TYPE as egl_ffi::types::EGLint,
+        egl_ffi::OPENGL_ES2_BIT as egl_ffi::types::EGLint,
+        egl_ffi::RED_SIZE as egl_ffi::types::EGLint,
+        10,
+        egl_ffi::GREEN_SIZE as egl_ffi::types::EGLint,
+        10,
+        egl_ffi::BLUE_SIZE as egl_ffi::types::EGLint,
+        10,
+        egl_ffi::ALPHA_SIZE as egl_ffi::types::EGLint,
+        2,
+        egl_ffi::NONE as egl_ffi::types::EGLint,
+    ];
+    let mut num_configs = 0;
+    let result = unsafe {
+        egl_ffi::ChooseConfig(
+            **egl_display.get_display_handle(),
+            attributes.as_ptr(),
+            null_mut(),
+            0,
+            &mut num_configs,
+        )
+    };
+    result == egl_ffi::TRUE && num_configs > 0
+}
+
 pub struct FlutterEngineChannels {
     tx_present: channel::Sender<()>,
     tx_request_fbo: channel::Sender<()>,
@@ -655,7 +719,7 @@ pub struct FlutterEngineChannels {
     rx_output_height: channel::Channel<u16>,
     tx_baton: channel::Sender<Baton>,
     tx_request_external_texture_name: channel::Sender<i64>,
-    rx_external_texture_name: channel::Channel<(u32, u32)>,
+    rx_external_texture_name: channel::Channel<(u32, u32, Vec<TextureFence>)>,
 }
 
 pub struct EmbedderChannels {
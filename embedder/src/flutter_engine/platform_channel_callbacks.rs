@@ -1,11 +1,12 @@
 use std::borrow::Borrow;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
 use serde_json::json;
 use smithay::backend::input::ButtonState;
 use smithay::input::pointer::{ButtonEvent, MotionEvent};
+use smithay::output::Scale;
 use smithay::reexports::calloop::channel::Event;
 use smithay::reexports::calloop::channel::Event::Msg;
 use smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel;
@@ -16,7 +17,9 @@ use smithay::wayland::shell::xdg;
 use smithay::wayland::xwayland_shell::XWAYLAND_SHELL_ROLE;
 use smithay::xwayland::xwm;
 
-use crate::backend::Backend;
+use crate::backend::mirroring::MirrorScaling;
+use crate::backend::monitor_profiles::{find_mode, ProfileMode, ProfilePosition, ProfileTransform};
+use crate::backend::{Backend, VrrMode};
 use crate::flutter_engine::platform_channels::method_call::MethodCall;
 use crate::flutter_engine::platform_channels::method_result::MethodResult;
 use crate::flutter_engine::wayland_messages::NewX11Surface;
@@ -24,6 +27,8 @@ use crate::focus::{KeyboardFocusTarget, PointerFocusTarget};
 use crate::mouse_button_tracker::FLUTTER_TO_LINUX_MOUSE_BUTTONS;
 
 use crate::state::State;
+use crate::wayland::foreign_toplevel::foreign_toplevel::ToplevelId;
+use crate::wayland::security_context::security_context::PrivilegedProtocol;
 use crate::wayland::wayland::get_surface_id;
 
 pub fn platform_channel_method_handler<BackendData: Backend + 'static>(
@@ -43,7 +48,27 @@ pub fn platform_channel_method_handler<BackendData: Backend + 'static>(
             "resize_window" => resize_window(method_call, result, data),
             "close_window" => close_window(method_call, result, data),
             "get_monitor_layout" => get_monitor_layout(method_call, result, data),
+            "set_scanout_surface" => set_scanout_surface(method_call, result, data),
+            "set_vrr_mode" => set_vrr_mode(method_call, result, data),
+            "set_surface_monitor" => set_surface_monitor(method_call, result, data),
+            "set_window_minimized" => set_window_minimized(method_call, result, data),
+            "set_monitor_scale" => set_monitor_scale(method_call, result, data),
+            "set_monitor_configuration" => set_monitor_configuration(method_call, result, data),
+            "set_monitor_mirror" => set_monitor_mirror(method_call, result, data),
+            "set_monitor_power" => set_monitor_power(method_call, result, data),
+            "set_idle_blank_timeout" => set_idle_blank_timeout(method_call, result, data),
+            "set_idle_timeout" => set_idle_timeout(method_call, result, data),
+            "set_power_handling" => set_power_handling(method_call, result, data),
+            "ready_to_sleep" => ready_to_sleep(method_call, result, data),
+            "suspend" => suspend(method_call, result, data),
+            "set_color_temperature" => set_color_temperature(method_call, result, data),
+            "create_activation_token" => create_activation_token(method_call, result, data),
+            "set_client_policy" => set_client_policy(method_call, result, data),
+            "set_virtual_input_permission" => {
+                set_virtual_input_permission(method_call, result, data)
+            }
             "get_environment_variables" => get_environment_variables(method_call, result, data),
+            "get_gpu_memory_statistics" => get_gpu_memory_statistics(method_call, result, data),
             "shell_ready" => on_shell_ready(method_call, result, data),
             _ => result.error(
                 "method_not_found".to_string(),
@@ -97,6 +122,7 @@ pub fn pointer_focus<BackendData: Backend + 'static>(
         data.surface_id_under_cursor = None;
         data.pointer_focus = None;
     }
+    data.update_pointer_constraint();
     result.success(None);
 }
 
@@ -108,6 +134,7 @@ pub fn pointer_exit<BackendData: Backend + 'static>(
     data.surface_id_under_cursor = None;
 
     data.pointer_focus = None;
+    data.update_pointer_constraint();
     result.success(None);
 }
 
@@ -240,6 +267,9 @@ pub fn activate_window<BackendData: Backend + 'static>(
                 return;
             };
             x11_surface.set_activated(payload.activate).unwrap();
+            data.update_foreign_toplevel(ToplevelId::X11(
+                State::<BackendData>::get_x11_surface_id(&x11_surface),
+            ));
 
             if payload.activate && !x11_surface.is_override_redirect() {
                 let _ = data.x11_wm.as_mut().unwrap().raise_window(&x11_surface);
@@ -338,7 +368,7 @@ pub fn resize_window<BackendData: Backend + 'static>(
             }
 
             let mut geometry = x11_surface.geometry();
-            geometry.size = (payload.width, payload.height).into();
+            geometry.size = data.logical_to_x11((payload.width, payload.height).into());
             x11_surface.configure(geometry).unwrap();
 
             result.success(None);
@@ -423,11 +453,479 @@ pub fn get_monitor_layout<BackendData: Backend + 'static>(
     mut result: Box<dyn MethodResult<serde_json::Value>>,
     data: &mut State<BackendData>,
 ) {
-    let monitors = data.space.outputs().cloned().collect::<Vec<_>>();
+    let monitors = data.all_outputs();
     data.flutter_engine_mut().monitor_layout_changed(monitors);
     result.success(None);
 }
 
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetScanoutSurfacePayload {
+    monitor_name: String,
+    surface_id: Option<u64>,
+}
+
+/// The shell calls this when a single opaque surface covers an entire monitor,
+/// typically a fullscreen video player or game, and again with no surface when it stops.
+pub fn set_scanout_surface<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetScanoutSurfacePayload = serde_json::from_value(args).unwrap();
+
+    match payload.surface_id {
+        Some(surface_id) => {
+            if !data.surfaces.contains_key(&surface_id) {
+                result.error(
+                    "surface_doesnt_exist".to_string(),
+                    format!("Surface {} doesn't exist", surface_id),
+                    None,
+                );
+                return;
+            }
+            data.scanout_surface_per_output
+                .insert(payload.monitor_name, surface_id);
+        }
+        None => {
+            data.scanout_surface_per_output
+                .remove(&payload.monitor_name);
+        }
+    }
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetVrrModePayload {
+    monitor_name: String,
+    mode: VrrMode,
+}
+
+pub fn set_vrr_mode<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetVrrModePayload = serde_json::from_value(args).unwrap();
+
+    data.vrr_mode_per_output
+        .insert(payload.monitor_name, payload.mode);
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetSurfaceMonitorPayload {
+    surface_id: u64,
+    monitor_name: Option<String>,
+}
+
+/// The shell tells us on which monitor a window is displayed, or that it's hidden
+/// (minimized, on another workspace, fully occluded) when there's no monitor.
+/// Frame callbacks are then paced by that monitor's refresh rate.
+pub fn set_surface_monitor<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetSurfaceMonitorPayload = serde_json::from_value(args).unwrap();
+
+    let surface = match data.surfaces.get(&payload.surface_id) {
+        Some(surface) => surface.clone(),
+        None => {
+            result.error(
+                "surface_doesnt_exist".to_string(),
+                format!("Surface {} doesn't exist", payload.surface_id),
+                None,
+            );
+            return;
+        }
+    };
+
+    // Surfaces that were never placed haven't entered any output yet.
+    let previous_output = data
+        .monitor_per_surface_id
+        .contains_key(&payload.surface_id)
+        .then(|| data.output_for_surface_id(payload.surface_id))
+        .flatten();
+
+    data.monitor_per_surface_id
+        .insert(payload.surface_id, payload.monitor_name);
+    data.surface_output_changed(&surface, previous_output);
+    data.update_idle_inhibition();
+    if let Some(toplevel_id) = data.toplevel_id_for_surface(payload.surface_id) {
+        data.update_foreign_toplevel(toplevel_id);
+    }
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetWindowMinimizedPayload {
+    surface_id: u64,
+    minimized: bool,
+}
+
+/// Only the shell knows which windows are minimized, taskbars are told through foreign toplevel management.
+pub fn set_window_minimized<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetWindowMinimizedPayload = serde_json::from_value(args).unwrap();
+
+    let Some(toplevel_id) = data.toplevel_id_for_surface(payload.surface_id) else {
+        result.error(
+            "toplevel_doesnt_exist".to_string(),
+            format!("Toplevel {} doesn't exist", payload.surface_id),
+            None,
+        );
+        return;
+    };
+    data.set_foreign_toplevel_minimized(toplevel_id, payload.minimized);
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetMonitorScalePayload {
+    monitor_name: String,
+    scale: f64,
+}
+
+pub fn set_monitor_scale<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetMonitorScalePayload = serde_json::from_value(args).unwrap();
+
+    if !(payload.scale.is_finite() && payload.scale > 0.0) {
+        result.error(
+            "invalid_scale".to_string(),
+            format!("Invalid scale {}", payload.scale),
+            None,
+        );
+        return;
+    }
+
+    let output = data
+        .space
+        .outputs()
+        .find(|output| output.name() == payload.monitor_name)
+        .cloned();
+    let Some(output) = output else {
+        result.error(
+            "monitor_doesnt_exist".to_string(),
+            format!("Monitor {} doesn't exist", payload.monitor_name),
+            None,
+        );
+        return;
+    };
+
+    output.change_current_state(None, None, Some(Scale::Fractional(payload.scale)), None);
+    data.output_scale_changed(&output);
+
+    let monitors = data.all_outputs();
+    data.flutter_engine_mut().monitor_layout_changed(monitors);
+    data.remember_monitor_profiles();
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetMonitorConfigurationPayload {
+    monitor_name: String,
+    mode: Option<ProfileMode>,
+    scale: Option<f64>,
+    position: Option<ProfilePosition>,
+    transform: Option<ProfileTransform>,
+}
+
+/// Changes how a monitor is set up from the display settings.
+/// The whole layout is remembered, so it comes back the next time these monitors are plugged.
+pub fn set_monitor_configuration<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetMonitorConfigurationPayload = serde_json::from_value(args).unwrap();
+
+    let output = data
+        .space
+        .outputs()
+        .find(|output| output.name() == payload.monitor_name)
+        .cloned();
+    let Some(output) = output else {
+        result.error(
+            "monitor_doesnt_exist".to_string(),
+            format!("Monitor {} doesn't exist", payload.monitor_name),
+            None,
+        );
+        return;
+    };
+
+    if let Some(scale) = payload.scale {
+        if !(scale.is_finite() && scale > 0.0) {
+            result.error(
+                "invalid_scale".to_string(),
+                format!("Invalid scale {}", scale),
+                None,
+            );
+            return;
+        }
+    }
+
+    if let Some(wanted) = payload.mode {
+        let Some(mode) = find_mode(&output.modes(), wanted) else {
+            result.error(
+                "invalid_mode".to_string(),
+                format!(
+                    "Monitor {} doesn't support {:?}",
+                    payload.monitor_name, wanted
+                ),
+                None,
+            );
+            return;
+        };
+        if Some(mode) != output.current_mode() {
+            if let Err(err) = data.backend_data.set_output_mode(&output, mode) {
+                result.error(
+                    "mode_change_failed".to_string(),
+                    format!(
+                        "Failed to change the mode of {}: {}",
+                        payload.monitor_name, err
+                    ),
+                    None,
+                );
+                return;
+            }
+            output.change_current_state(Some(mode), None, None, None);
+        }
+    }
+    if let Some(scale) = payload.scale {
+        output.change_current_state(None, None, Some(Scale::Fractional(scale)), None);
+        data.output_scale_changed(&output);
+    }
+    if let Some(transform) = payload.transform {
+        output.change_current_state(None, Some(transform.into()), None, None);
+    }
+    if let Some(position) = payload.position {
+        let location = (position.x, position.y).into();
+        output.change_current_state(None, None, None, Some(location));
+        data.space.map_output(&output, location);
+    }
+
+    data.arrange_monitors();
+    data.remember_monitor_profiles();
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetMonitorMirrorPayload {
+    monitor_name: String,
+    /// The monitor to replicate, `None` gives the monitor its own region of the canvas back.
+    source: Option<String>,
+    #[serde(default)]
+    scaling: MirrorScaling,
+}
+
+/// Presentation mode, typically to show the laptop screen on a projector.
+pub fn set_monitor_mirror<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetMonitorMirrorPayload = serde_json::from_value(args).unwrap();
+
+    let output = data
+        .all_outputs()
+        .into_iter()
+        .find(|output| output.name() == payload.monitor_name);
+    let Some(output) = output else {
+        result.error(
+            "monitor_doesnt_exist".to_string(),
+            format!("Monitor {} doesn't exist", payload.monitor_name),
+            None,
+        );
+        return;
+    };
+
+    match payload.source {
+        Some(source) => {
+            if let Err(err) = data.mirror_output(&output, &source, payload.scaling) {
+                result.error("invalid_mirror".to_string(), err.to_string(), None);
+                return;
+            }
+        }
+        None => data.stop_mirroring(&output),
+    }
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetMonitorPowerPayload {
+    monitor_name: String,
+    on: bool,
+}
+
+pub fn set_monitor_power<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetMonitorPowerPayload = serde_json::from_value(args).unwrap();
+
+    let output = data
+        .all_outputs()
+        .into_iter()
+        .find(|output| output.name() == payload.monitor_name);
+    let Some(output) = output else {
+        result.error(
+            "monitor_doesnt_exist".to_string(),
+            format!("Monitor {} doesn't exist", payload.monitor_name),
+            None,
+        );
+        return;
+    };
+
+    if let Err(err) = data.set_output_power(&output, payload.on) {
+        result.error(
+            "power_change_failed".to_string(),
+            format!(
+                "Failed to power {} {}: {}",
+                payload.monitor_name,
+                if payload.on { "on" } else { "off" },
+                err
+            ),
+            None,
+        );
+        return;
+    }
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetIdleBlankTimeoutPayload {
+    /// In milliseconds, `None` never blanks the monitors.
+    timeout: Option<u64>,
+}
+
+pub fn set_idle_blank_timeout<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetIdleBlankTimeoutPayload = serde_json::from_value(args).unwrap();
+
+    data.set_idle_blank_timeout(payload.timeout.map(Duration::from_millis));
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetIdleTimeoutPayload {
+    /// In milliseconds, `None` never reports the user as idle.
+    timeout: Option<u64>,
+}
+
+/// The shell dims or locks the screen when it receives `idle_state_changed`.
+pub fn set_idle_timeout<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetIdleTimeoutPayload = serde_json::from_value(args).unwrap();
+
+    data.set_idle_timeout(payload.timeout.map(Duration::from_millis));
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetPowerHandlingPayload {
+    /// The shell handles the power and suspend keys instead of logind.
+    power_keys: bool,
+    /// Suspend waits for `ready_to_sleep` after `prepare_for_sleep`.
+    lock_before_sleep: bool,
+}
+
+pub fn set_power_handling<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetPowerHandlingPayload = serde_json::from_value(args).unwrap();
+
+    data.set_power_handling(payload.power_keys, payload.lock_before_sleep);
+
+    result.success(None);
+}
+
+/// The shell calls this after `prepare_for_sleep`, once the screen is locked.
+pub fn ready_to_sleep<BackendData: Backend + 'static>(
+    _method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    data.ready_to_sleep();
+    result.success(None);
+}
+
+pub fn suspend<BackendData: Backend + 'static>(
+    _method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    data.suspend();
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetColorTemperaturePayload {
+    /// In Kelvin, `None` turns night light off.
+    temperature: Option<u32>,
+}
+
+pub fn set_color_temperature<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetColorTemperaturePayload = serde_json::from_value(args).unwrap();
+
+    data.set_color_temperature(payload.temperature);
+
+    result.success(None);
+}
+
 pub fn get_environment_variables<BackendData: Backend + 'static>(
     _method_call: MethodCall<serde_json::Value>,
     mut result: Box<dyn MethodResult<serde_json::Value>>,
@@ -449,6 +947,65 @@ pub fn get_environment_variables<BackendData: Backend + 'static>(
     result.success(None);
 }
 
+/// The shell launches apps with a token, so that they can take the focus once their window shows up.
+pub fn create_activation_token<BackendData: Backend + 'static>(
+    _method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let token = data.create_activation_token();
+    result.success(Some(json!(token)));
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetVirtualInputPermissionPayload {
+    app_id: String,
+    allowed: bool,
+}
+
+pub fn set_virtual_input_permission<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetVirtualInputPermissionPayload = serde_json::from_value(args).unwrap();
+
+    data.set_virtual_input_permission(payload.app_id, payload.allowed);
+
+    result.success(None);
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetClientPolicyPayload {
+    app_id: String,
+    allowed_protocols: HashSet<PrivilegedProtocol>,
+}
+
+pub fn set_client_policy<BackendData: Backend + 'static>(
+    method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let args = method_call.arguments().unwrap().clone();
+    let payload: SetClientPolicyPayload = serde_json::from_value(args).unwrap();
+
+    data.set_client_policy(payload.app_id, payload.allowed_protocols);
+
+    result.success(None);
+}
+
+pub fn get_gpu_memory_statistics<BackendData: Backend + 'static>(
+    _method_call: MethodCall<serde_json::Value>,
+    mut result: Box<dyn MethodResult<serde_json::Value>>,
+    data: &mut State<BackendData>,
+) {
+    let statistics = data.gpu_memory_statistics();
+    result.success(Some(json!(statistics)));
+}
+
 // For development purpose the shell can reload to apply current changes
 // In order to be the most transparent for the dev
 // We resend all existing surfaces to it so it can benefit from the Persistence
@@ -1,9 +1,14 @@
 use serde::ser::SerializeStruct;
 use serde::Serialize;
-use smithay::output::{Mode, Output, PhysicalProperties};
+use serde_json::json;
+use smithay::output::{Mode, Output, PhysicalProperties, Subpixel};
 use smithay::utils::{Buffer as BufferCoords, Logical, Point, Rectangle, Size};
+use smithay_drm_extras::edid::EdidInfo;
 use std::collections::HashMap;
 
+use crate::backend::mirroring::output_mirror;
+use crate::wayland::color_management::color_management::output_hdr_capable;
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SurfaceMessage {
@@ -13,9 +18,23 @@ pub struct SurfaceMessage {
     pub buffer_delta: Option<MyPoint<i32, Logical>>,
     pub buffer_size: Option<MySize<i32, BufferCoords>>,
     pub scale: i32,
+    pub viewport_source: Option<MyRectangle<f64, Logical>>,
+    pub viewport_destination: Option<MySize<i32, Logical>>,
     pub input_region: MyRectangle<i32, Logical>,
     pub subsurfaces_below: Vec<u64>,
     pub subsurfaces_above: Vec<u64>,
+    /// What changed in the buffer since the last commit, `None` if the buffer didn't change.
+    pub buffer_damage: Option<Vec<MyRectangle<i32, BufferCoords>>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientGpuMemoryMessage {
+    /// `None` for clients whose credentials can't be read.
+    pub pid: Option<i32>,
+    pub surface_ids: Vec<u64>,
+    pub texture_count: usize,
+    pub bytes: usize,
 }
 
 #[derive(Debug, Serialize)]
@@ -178,7 +197,7 @@ impl Serialize for MyOutput {
         S: serde::Serializer,
     {
         let output = &self.0;
-        let mut state = serializer.serialize_struct("Output", 7)?;
+        let mut state = serializer.serialize_struct("Output", 11)?;
         state.serialize_field("name", &output.name())?;
         state.serialize_field("description", &output.description())?;
         state.serialize_field(
@@ -203,6 +222,12 @@ impl Serialize for MyOutput {
                 .map(|mode| MyMode(mode))
                 .collect::<Vec<_>>(),
         )?;
+        state.serialize_field("hdrCapable", &output_hdr_capable(output))?;
+        state.serialize_field(
+            "edid",
+            &output.user_data().get::<EdidInfo>().map(MyEdidInfo),
+        )?;
+        state.serialize_field("mirror", &output_mirror(output))?;
         state.end()
     }
 }
@@ -246,10 +271,90 @@ impl Serialize for MyPhysicalProperties {
         S: serde::Serializer,
     {
         let properties = &self.0;
-        let mut state = serializer.serialize_struct("PhysicalProperties", 3)?;
+        let mut state = serializer.serialize_struct("PhysicalProperties", 4)?;
         state.serialize_field("size", &MySize(properties.size))?;
         state.serialize_field("make", &properties.make)?;
         state.serialize_field("model", &properties.model)?;
+        state.serialize_field(
+            "subpixel",
+            match properties.subpixel {
+                Subpixel::HorizontalRgb => "horizontalRgb",
+                Subpixel::HorizontalBgr => "horizontalBgr",
+                Subpixel::VerticalRgb => "verticalRgb",
+                Subpixel::VerticalBgr => "verticalBgr",
+                Subpixel::None => "none",
+                _ => "unknown",
+            },
+        )?;
+        state.end()
+    }
+}
+
+#[derive(Debug)]
+struct MyEdidInfo<'a>(&'a EdidInfo);
+
+impl Serialize for MyEdidInfo<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let info = self.0;
+        let mut state = serializer.serialize_struct("Edid", 6)?;
+        state.serialize_field("serial", &info.serial)?;
+        state.serialize_field(
+            "physicalSize",
+            &info.physical_size.map(|(width, height)| {
+                json!({
+                    "width": width,
+                    "height": height,
+                })
+            }),
+        )?;
+        state.serialize_field(
+            "preferredTiming",
+            &info.preferred_timing.map(|timing| {
+                json!({
+                    "size": {
+                        "width": timing.width,
+                        "height": timing.height,
+                    },
+                    "refreshRate": timing.refresh_rate,
+                    "pixelClock": timing.pixel_clock,
+                    "interlaced": timing.interlaced,
+                })
+            }),
+        )?;
+        state.serialize_field(
+            "vrrRange",
+            &info.vrr_range.map(|(min, max)| {
+                json!({
+                    "min": min,
+                    "max": max,
+                })
+            }),
+        )?;
+        state.serialize_field(
+            "colorimetry",
+            &json!({
+                "bt2020Rgb": info.colorimetry.bt2020_rgb,
+                "bt2020Ycc": info.colorimetry.bt2020_ycc,
+                "dciP3": info.colorimetry.dci_p3,
+            }),
+        )?;
+        state.serialize_field(
+            "hdrStaticMetadata",
+            &info.hdr_static_metadata.map(|metadata| {
+                json!({
+                    "eotfSdr": metadata.eotf_sdr,
+                    "eotfHdr": metadata.eotf_hdr,
+                    "eotfPq": metadata.eotf_pq,
+                    "eotfHlg": metadata.eotf_hlg,
+                    "maxLuminance": metadata.max_luminance,
+                    "maxFrameAverageLuminance": metadata.max_frame_average_luminance,
+                    "minLuminance": metadata.min_luminance,
+                })
+            }),
+        )?;
         state.end()
     }
 }
@@ -4,11 +4,12 @@ use input_linux::sys::{KEY_ESC, KEY_F1, KEY_LEFTALT};
 use smithay::backend::input::{
     self, AbsolutePositionEvent, Axis, AxisRelativeDirection, AxisSource, ButtonState, Event,
     InputBackend, InputEvent, KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent,
-    PointerMotionEvent,
+    PointerMotionEvent, Switch, SwitchState, SwitchToggleEvent,
 };
 use smithay::input::keyboard::{FilterResult, Keysym};
 use smithay::input::pointer::{AxisFrame, MotionEvent, RelativeMotionEvent};
-use smithay::utils::{Logical, Point, SERIAL_COUNTER};
+use smithay::utils::{Logical, Point, Size, SERIAL_COUNTER};
+use smithay::wayland::seat::WaylandFocus;
 
 use crate::backend::Backend;
 use crate::flutter_engine::embedder::{
@@ -19,6 +20,7 @@ use crate::flutter_engine::embedder::{
 };
 use crate::flutter_engine::FlutterEngine;
 use crate::focus::PointerFocusTarget;
+use crate::output_canvas_geometry;
 use crate::state::State;
 
 impl<BackendData: Backend> State<BackendData> {
@@ -27,132 +29,33 @@ impl<BackendData: Backend> State<BackendData> {
         BackendData: Backend + 'static,
     {
         use smithay::backend::input::Event;
+        if !matches!(
+            event,
+            InputEvent::DeviceAdded { .. } | InputEvent::DeviceRemoved { .. }
+        ) {
+            self.notify_activity();
+        }
+
         match event {
             InputEvent::DeviceAdded { .. } => {}
             InputEvent::DeviceRemoved { .. } => {}
             InputEvent::PointerMotion { event } => {
-                let pointer: smithay::input::pointer::PointerHandle<State<BackendData>> =
-                    self.pointer.clone();
-
-                let mut pointer_location = self.pointer.current_location();
-
-                pointer.relative_motion(
-                    self,
-                    self.pointer_focus.clone(),
-                    &RelativeMotionEvent {
-                        delta: event.delta(),
-                        delta_unaccel: event.delta_unaccel(),
-                        utime: event.time(),
-                    },
-                );
-
-                pointer_location += event.delta();
-
-                // clamp to screen limits
-                pointer_location = self.clamp_coords(pointer_location);
-
-                pointer.motion(
-                    self,
-                    self.pointer_focus.clone(),
-                    &MotionEvent {
-                        location: pointer_location,
-                        serial: SERIAL_COUNTER.next_serial(),
-                        time: event.time_msec(),
-                    },
+                self.pointer_motion(
+                    event.delta(),
+                    event.delta_unaccel(),
+                    event.time(),
+                    event.time_msec(),
                 );
-                self.registerFrame();
-
-                self.send_motion_event(pointer_location)
             }
             InputEvent::PointerMotionAbsolute { event } => {
-                let serial = SERIAL_COUNTER.next_serial();
-                let outputs: Vec<smithay::output::Output> =
-                    self.space.outputs().cloned().collect::<Vec<_>>();
-                let max_x = outputs.into_iter().fold(0, |acc, o| {
-                    acc + self.space.output_geometry(&o).unwrap().size.w
-                });
-
-                let max_h_output = self
-                    .space
-                    .outputs()
-                    .max_by_key(|o| self.space.output_geometry(o).unwrap().size.h)
-                    .unwrap();
-
-                let max_y = self.space.output_geometry(max_h_output).unwrap().size.h;
-
-                let mut pointer_location =
-                    (event.x_transformed(max_x), event.y_transformed(max_y)).into();
-
-                // clamp to screen limits
-                pointer_location = self.clamp_coords(pointer_location);
-
-                let pointer = self.pointer.clone();
-                pointer.motion(
-                    self,
-                    self.pointer_focus.clone(),
-                    &MotionEvent {
-                        location: pointer_location,
-                        serial,
-                        time: event.time_msec(),
-                    },
-                );
-                self.registerFrame();
-
-                self.send_motion_event(pointer_location)
+                let Some(size) = self.pointer_canvas_size() else {
+                    return;
+                };
+                let pointer_location = (event.x_transformed(size.w), event.y_transformed(size.h));
+                self.pointer_motion_absolute(pointer_location.into(), event.time_msec());
             }
             InputEvent::PointerButton { event } => {
-                let phase = if event.state() == ButtonState::Pressed {
-                    let are_any_buttons_pressed = self
-                        .flutter_engine()
-                        .mouse_button_tracker
-                        .are_any_buttons_pressed();
-                    let _ = self
-                        .flutter_engine_mut()
-                        .mouse_button_tracker
-                        .press(event.button_code() as u16);
-                    if are_any_buttons_pressed {
-                        FlutterPointerPhase_kMove
-                    } else {
-                        FlutterPointerPhase_kDown
-                    }
-                } else {
-                    let _ = self
-                        .flutter_engine_mut()
-                        .mouse_button_tracker
-                        .release(event.button_code() as u16);
-                    if self
-                        .flutter_engine()
-                        .mouse_button_tracker
-                        .are_any_buttons_pressed()
-                    {
-                        FlutterPointerPhase_kMove
-                    } else {
-                        FlutterPointerPhase_kUp
-                    }
-                };
-
-                self.flutter_engine()
-                    .send_pointer_event(FlutterPointerEvent {
-                        struct_size: size_of::<FlutterPointerEvent>(),
-                        phase,
-                        timestamp: FlutterEngine::<BackendData>::current_time_us() as usize,
-                        x: self.pointer.current_location().x,
-                        y: self.pointer.current_location().y,
-                        device: 0,
-                        signal_kind: FlutterPointerSignalKind_kFlutterPointerSignalKindNone,
-                        scroll_delta_x: 0.0,
-                        scroll_delta_y: 0.0,
-                        device_kind: FlutterPointerDeviceKind_kFlutterPointerDeviceKindMouse,
-                        buttons: self
-                            .flutter_engine()
-                            .mouse_button_tracker
-                            .get_flutter_button_bitmask(),
-                        pan_x: 0.0,
-                        pan_y: 0.0,
-                        scale: 1.0,
-                        rotation: 0.0,
-                    })
-                    .unwrap();
+                self.pointer_button(event.button_code(), event.state());
             }
             InputEvent::PointerAxis { event } => {
                 let horizontal_amount =
@@ -194,40 +97,7 @@ impl<BackendData: Backend> State<BackendData> {
                         frame = frame.stop(Axis::Vertical);
                     }
                 }
-                let pointer = self.pointer.clone();
-                pointer.axis(self, frame);
-                self.registerFrame();
-
-                self.flutter_engine()
-                    .send_pointer_event(FlutterPointerEvent {
-                        struct_size: size_of::<FlutterPointerEvent>(),
-                        phase: if self
-                            .flutter_engine()
-                            .mouse_button_tracker
-                            .are_any_buttons_pressed()
-                        {
-                            FlutterPointerPhase_kMove
-                        } else {
-                            FlutterPointerPhase_kDown
-                        },
-                        timestamp: FlutterEngine::<BackendData>::current_time_us() as usize,
-                        x: self.pointer.current_location().x,
-                        y: self.pointer.current_location().y,
-                        device: 0,
-                        signal_kind: FlutterPointerSignalKind_kFlutterPointerSignalKindScroll,
-                        scroll_delta_x: frame.axis.0,
-                        scroll_delta_y: frame.axis.1,
-                        device_kind: FlutterPointerDeviceKind_kFlutterPointerDeviceKindMouse,
-                        buttons: self
-                            .flutter_engine()
-                            .mouse_button_tracker
-                            .get_flutter_button_bitmask(),
-                        pan_x: 0.0,
-                        pan_y: 0.0,
-                        scale: 1.0,
-                        rotation: 0.0,
-                    })
-                    .unwrap();
+                self.pointer_axis(frame);
             }
             InputEvent::Keyboard { event } => {
                 self.handle_key_event(event.key_code(), event.state(), event.time_msec());
@@ -250,10 +120,227 @@ impl<BackendData: Backend> State<BackendData> {
             InputEvent::TabletToolTip { .. } => {}
             InputEvent::TabletToolButton { .. } => {}
             InputEvent::Special(_) => {}
-            InputEvent::SwitchToggle { .. } => {}
+            InputEvent::SwitchToggle { event } => {
+                if event.switch() == Some(Switch::Lid) {
+                    self.lid_switch_toggled(event.state() == SwitchState::On);
+                }
+            }
         }
     }
 
+    /// The pointer focus for a motion to `location`.
+    /// Smithay sends `location - origin` to the surface, the origin of X11 windows is moved
+    /// so that it's in X11 pixels.
+    pub fn pointer_focus_at(
+        &self,
+        location: Point<f64, Logical>,
+    ) -> Option<(PointerFocusTarget, Point<f64, Logical>)> {
+        let (focus, origin) = self.pointer_focus.clone()?;
+        let scale = focus
+            .wl_surface()
+            .map_or(1.0, |surface| self.surface_pixel_scale(&surface));
+        Some((focus, location - (location - origin).upscale(scale)))
+    }
+
+    /// Relative pointer motion, from a mouse or a virtual pointer.
+    pub fn pointer_motion(
+        &mut self,
+        delta: Point<f64, Logical>,
+        delta_unaccel: Point<f64, Logical>,
+        utime: u64,
+        time_msec: u32,
+    ) where
+        BackendData: Backend + 'static,
+    {
+        let pointer: smithay::input::pointer::PointerHandle<State<BackendData>> =
+            self.pointer.clone();
+
+        let mut pointer_location = self.pointer.current_location();
+
+        pointer.relative_motion(
+            self,
+            self.pointer_focus.clone(),
+            &RelativeMotionEvent {
+                delta,
+                delta_unaccel,
+                utime,
+            },
+        );
+
+        // Locked pointers only send relative motion.
+        self.update_pointer_constraint();
+        if self.is_pointer_locked() {
+            self.registerFrame();
+            return;
+        }
+
+        pointer_location += delta;
+
+        // clamp to screen limits
+        pointer_location = self.clamp_coords(pointer_location);
+
+        pointer.motion(
+            self,
+            self.pointer_focus_at(pointer_location),
+            &MotionEvent {
+                location: pointer_location,
+                serial: SERIAL_COUNTER.next_serial(),
+                time: time_msec,
+            },
+        );
+        self.registerFrame();
+
+        self.send_motion_event(pointer_location);
+        self.update_pointer_constraint();
+    }
+
+    /// The area absolute pointer positions are mapped to, `None` without any output.
+    pub fn pointer_canvas_size(&self) -> Option<Size<i32, Logical>> {
+        let max_x = self
+            .space
+            .outputs()
+            .fold(0, |acc, o| acc + output_canvas_geometry(o).size.w);
+
+        let max_h_output = self
+            .space
+            .outputs()
+            .max_by_key(|o| output_canvas_geometry(o).size.h)?;
+
+        let max_y = output_canvas_geometry(max_h_output).size.h;
+
+        Some((max_x, max_y).into())
+    }
+
+    /// Absolute pointer motion, from a tablet, a VM or a virtual pointer.
+    pub fn pointer_motion_absolute(&mut self, location: Point<f64, Logical>, time_msec: u32)
+    where
+        BackendData: Backend + 'static,
+    {
+        self.update_pointer_constraint();
+        if self.is_pointer_locked() {
+            return;
+        }
+
+        let serial = SERIAL_COUNTER.next_serial();
+
+        // clamp to screen limits
+        let pointer_location = self.clamp_coords(location);
+
+        let pointer = self.pointer.clone();
+        pointer.motion(
+            self,
+            self.pointer_focus_at(pointer_location),
+            &MotionEvent {
+                location: pointer_location,
+                serial,
+                time: time_msec,
+            },
+        );
+        self.registerFrame();
+
+        self.send_motion_event(pointer_location);
+        self.update_pointer_constraint();
+    }
+
+    /// Buttons go to Flutter first, which forwards them to the surface under the pointer.
+    pub fn pointer_button(&mut self, button_code: u32, state: ButtonState)
+    where
+        BackendData: Backend + 'static,
+    {
+        let phase = if state == ButtonState::Pressed {
+            let are_any_buttons_pressed = self
+                .flutter_engine()
+                .mouse_button_tracker
+                .are_any_buttons_pressed();
+            let _ = self
+                .flutter_engine_mut()
+                .mouse_button_tracker
+                .press(button_code as u16);
+            if are_any_buttons_pressed {
+                FlutterPointerPhase_kMove
+            } else {
+                FlutterPointerPhase_kDown
+            }
+        } else {
+            let _ = self
+                .flutter_engine_mut()
+                .mouse_button_tracker
+                .release(button_code as u16);
+            if self
+                .flutter_engine()
+                .mouse_button_tracker
+                .are_any_buttons_pressed()
+            {
+                FlutterPointerPhase_kMove
+            } else {
+                FlutterPointerPhase_kUp
+            }
+        };
+
+        self.flutter_engine()
+            .send_pointer_event(FlutterPointerEvent {
+                struct_size: size_of::<FlutterPointerEvent>(),
+                phase,
+                timestamp: FlutterEngine::<BackendData>::current_time_us() as usize,
+                x: self.pointer.current_location().x,
+                y: self.pointer.current_location().y,
+                device: 0,
+                signal_kind: FlutterPointerSignalKind_kFlutterPointerSignalKindNone,
+                scroll_delta_x: 0.0,
+                scroll_delta_y: 0.0,
+                device_kind: FlutterPointerDeviceKind_kFlutterPointerDeviceKindMouse,
+                buttons: self
+                    .flutter_engine()
+                    .mouse_button_tracker
+                    .get_flutter_button_bitmask(),
+                pan_x: 0.0,
+                pan_y: 0.0,
+                scale: 1.0,
+                rotation: 0.0,
+            })
+            .unwrap();
+    }
+
+    pub fn pointer_axis(&mut self, frame: AxisFrame)
+    where
+        BackendData: Backend + 'static,
+    {
+        let pointer = self.pointer.clone();
+        pointer.axis(self, frame);
+        self.registerFrame();
+
+        self.flutter_engine()
+            .send_pointer_event(FlutterPointerEvent {
+                struct_size: size_of::<FlutterPointerEvent>(),
+                phase: if self
+                    .flutter_engine()
+                    .mouse_button_tracker
+                    .are_any_buttons_pressed()
+                {
+                    FlutterPointerPhase_kMove
+                } else {
+                    FlutterPointerPhase_kDown
+                },
+                timestamp: FlutterEngine::<BackendData>::current_time_us() as usize,
+                x: self.pointer.current_location().x,
+                y: self.pointer.current_location().y,
+                device: 0,
+                signal_kind: FlutterPointerSignalKind_kFlutterPointerSignalKindScroll,
+                scroll_delta_x: frame.axis.0,
+                scroll_delta_y: frame.axis.1,
+                device_kind: FlutterPointerDeviceKind_kFlutterPointerDeviceKindMouse,
+                buttons: self
+                    .flutter_engine()
+                    .mouse_button_tracker
+                    .get_flutter_button_bitmask(),
+                pan_x: 0.0,
+                pan_y: 0.0,
+                scale: 1.0,
+                rotation: 0.0,
+            })
+            .unwrap();
+    }
+
     fn registerFrame(&mut self) {
         if self.pointer_frame_pending {
             return;
@@ -265,7 +352,7 @@ impl<BackendData: Backend> State<BackendData> {
         self.pointer_frame_pending = true;
     }
 
-    fn clamp_coords(&mut self, pos: Point<f64, Logical>) -> Point<f64, Logical>
+    pub fn clamp_coords(&mut self, pos: Point<f64, Logical>) -> Point<f64, Logical>
     where
         BackendData: Backend + 'static,
     {
@@ -274,28 +361,31 @@ impl<BackendData: Backend> State<BackendData> {
         }
 
         let (pos_x, pos_y) = pos.into();
-        let max_x = self.space.outputs().fold(0, |acc, o| {
-            acc + self.space.output_geometry(o).unwrap().size.w
-        });
+        let max_x = self
+            .space
+            .outputs()
+            .fold(0, |acc, o| acc + output_canvas_geometry(o).size.w);
         let clamped_x = pos_x.clamp(0.0, max_x as f64);
         let max_y = self
             .space
             .outputs()
             .find(|o| {
-                let geo = self.space.output_geometry(o).unwrap();
+                let geo = output_canvas_geometry(o);
                 geo.contains((clamped_x as i32, 0))
             })
-            .map(|o| self.space.output_geometry(o).unwrap().size.h);
+            .map(|o| output_canvas_geometry(o).size.h);
 
-        if let Some(max_y) = max_y {
+        let clamped = if let Some(max_y) = max_y {
             let clamped_y = pos_y.clamp(0.0, max_y as f64);
             (clamped_x, clamped_y).into()
         } else {
             (clamped_x, pos_y).into()
-        }
+        };
+
+        self.confine_pointer(clamped)
     }
 
-    fn send_motion_event(&mut self, location: Point<f64, Logical>)
+    pub fn send_motion_event(&mut self, location: Point<f64, Logical>)
     where
         BackendData: Backend + 'static,
     {
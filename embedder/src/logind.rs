@@ -0,0 +1,298 @@
+use serde_json::json;
+use smithay::input::keyboard::Keysym;
+use smithay::output::Output;
+use smithay::reexports::calloop::channel::{self, Event::Msg};
+use tracing::{info, warn};
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::OwnedFd;
+
+use crate::{output_canvas_geometry, state::State, Backend};
+
+#[derive(Debug)]
+pub enum LogindEvent {
+    /// The system is about to suspend, it waits for the sleep lock to be released.
+    PrepareForSleep,
+    Resumed,
+}
+
+/// Talks to systemd-logind, which handles suspend and the power keys for the whole seat.
+/// Logind keeps doing so until the shell opts in with `set_power_handling`.
+pub struct Logind {
+    connection: Connection,
+    /// Whether the shell locks the screen before suspending, and so wants a sleep lock.
+    delays_sleep: bool,
+    /// Delays suspend until the shell has locked the screen.
+    sleep_lock: Option<OwnedFd>,
+    /// Keeps logind from acting on the power and suspend keys, the shell handles them.
+    power_key_lock: Option<OwnedFd>,
+}
+
+impl Logind {
+    pub fn new() -> zbus::Result<Self> {
+        Ok(Self {
+            connection: Connection::system()?,
+            delays_sleep: false,
+            sleep_lock: None,
+            power_key_lock: None,
+        })
+    }
+
+    fn manager(&self) -> zbus::Result<Proxy<'static>> {
+        Proxy::new(
+            &self.connection,
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+        )
+    }
+
+    fn inhibit(&self, what: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd> {
+        self.manager()?
+            .call("Inhibit", &(what, "Veshell", why, mode))
+    }
+
+    fn take_sleep_lock(&mut self) {
+        if !self.delays_sleep || self.sleep_lock.is_some() {
+            return;
+        }
+        match self.inhibit("sleep", "Lock the screen before suspending", "delay") {
+            Ok(lock) => self.sleep_lock = Some(lock),
+            Err(err) => warn!("Failed to take the logind sleep lock: {}", err),
+        }
+    }
+
+    /// Lets the system suspend.
+    fn release_sleep_lock(&mut self) {
+        self.sleep_lock = None;
+    }
+
+    pub fn handles_power_keys(&self) -> bool {
+        self.power_key_lock.is_some()
+    }
+
+    fn set_handles_power_keys(&mut self, enabled: bool) {
+        if !enabled {
+            self.power_key_lock = None;
+        } else if self.power_key_lock.is_none() {
+            match self.inhibit(
+                "handle-power-key:handle-suspend-key",
+                "The shell asks what to do",
+                "block",
+            ) {
+                Ok(lock) => self.power_key_lock = Some(lock),
+                Err(err) => warn!("Failed to take the logind power key lock: {}", err),
+            }
+        }
+    }
+
+    fn set_delays_sleep(&mut self, enabled: bool) {
+        self.delays_sleep = enabled;
+        if enabled {
+            self.take_sleep_lock();
+        } else {
+            self.release_sleep_lock();
+        }
+    }
+
+    pub fn suspend(&self) -> zbus::Result<()> {
+        self.manager()?.call("Suspend", &(false,))
+    }
+
+    /// Forwards the sleep signals of logind from a thread of their own.
+    fn listen(&self, sender: channel::Sender<LogindEvent>) -> zbus::Result<()> {
+        let manager = self.manager()?;
+        std::thread::Builder::new()
+            .name("logind".to_string())
+            .spawn(move || {
+                let signals = match manager.receive_signal("PrepareForSleep") {
+                    Ok(signals) => signals,
+                    Err(err) => {
+                        warn!("Failed to listen to logind: {}", err);
+                        return;
+                    }
+                };
+                for message in signals {
+                    let event = match message.body().deserialize::<bool>() {
+                        Ok(true) => LogindEvent::PrepareForSleep,
+                        Ok(false) => LogindEvent::Resumed,
+                        Err(_) => continue,
+                    };
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+            })?;
+        Ok(())
+    }
+}
+
+/// Built-in panels of laptops, which are off while the lid is closed.
+pub fn is_internal_panel(output: &Output) -> bool {
+    let name = output.name();
+    ["eDP", "LVDS", "DSI"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+impl<BackendData: Backend + 'static> State<BackendData> {
+    /// Only makes sense when we own the seat, not when running nested.
+    pub fn init_logind(&mut self) {
+        let logind = match Logind::new() {
+            Ok(logind) => logind,
+            Err(err) => {
+                warn!("systemd-logind isn't available: {}", err);
+                return;
+            }
+        };
+
+        let (sender, receiver) = channel::channel();
+        if let Err(err) = logind.listen(sender) {
+            warn!("Failed to listen to logind: {}", err);
+        }
+        self.loop_handle
+            .insert_source(receiver, |event, _, data| {
+                if let Msg(event) = event {
+                    data.logind_event(event);
+                }
+            })
+            .unwrap();
+
+        self.logind = Some(logind);
+    }
+
+    /// The shell opts in once it has handlers for `power_key_pressed` and `prepare_for_sleep`,
+    /// until then logind acts on the power keys and suspends right away.
+    pub fn set_power_handling(&mut self, power_keys: bool, lock_before_sleep: bool) {
+        if let Some(logind) = self.logind.as_mut() {
+            logind.set_handles_power_keys(power_keys);
+            logind.set_delays_sleep(lock_before_sleep);
+        }
+    }
+
+    fn logind_event(&mut self, event: LogindEvent) {
+        info!("logind: {:?}", event);
+        let method = match event {
+            LogindEvent::PrepareForSleep => "prepare_for_sleep",
+            LogindEvent::Resumed => {
+                // Ready to delay the next suspend.
+                if let Some(logind) = self.logind.as_mut() {
+                    logind.take_sleep_lock();
+                }
+                "resumed_from_sleep"
+            }
+        };
+        self.flutter_engine_mut()
+            .platform_method_channel
+            .invoke_method(method, None, None);
+    }
+
+    /// The shell calls this once the screen is locked, or right away if it doesn't lock it.
+    pub fn ready_to_sleep(&mut self) {
+        if let Some(logind) = self.logind.as_mut() {
+            logind.release_sleep_lock();
+        }
+    }
+
+    pub fn suspend(&mut self) {
+        match self.logind.as_ref() {
+            Some(logind) => {
+                if let Err(err) = logind.suspend() {
+                    warn!("Failed to suspend: {}", err);
+                }
+            }
+            None => warn!("Can't suspend without systemd-logind"),
+        }
+    }
+
+    /// Returns whether the key was a power key, which isn't forwarded to the shell as is.
+    pub fn handle_power_key(&mut self, keysym: Keysym, pressed: bool) -> bool {
+        if !self
+            .logind
+            .as_ref()
+            .map(Logind::handles_power_keys)
+            .unwrap_or(false)
+        {
+            return false;
+        }
+
+        if keysym == Keysym::XF86_PowerOff {
+            if pressed {
+                // The shell shows a menu to lock, suspend or shut down.
+                self.flutter_engine_mut()
+                    .platform_method_channel
+                    .invoke_method("power_key_pressed", None, None);
+            }
+            true
+        } else if keysym == Keysym::XF86_Sleep || keysym == Keysym::XF86_Suspend {
+            if pressed {
+                self.suspend();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Closing the lid of a docked laptop turns its panel off and takes it out of the canvas.
+    /// Without an external monitor, logind suspends the laptop instead.
+    pub fn lid_switch_toggled(&mut self, closed: bool) {
+        info!("Lid {}", if closed { "closed" } else { "opened" });
+        self.flutter_engine_mut()
+            .platform_method_channel
+            .invoke_method(
+                "lid_switch_toggled",
+                Some(Box::new(json!({
+                    "closed": closed,
+                }))),
+                None,
+            );
+
+        if closed {
+            let panels = self
+                .space
+                .outputs()
+                .filter(|output| is_internal_panel(output))
+                .cloned()
+                .collect::<Vec<_>>();
+            let is_docked = self
+                .space
+                .outputs()
+                .any(|output| !is_internal_panel(output));
+            if !is_docked {
+                return;
+            }
+            for panel in panels {
+                if let Err(err) = self.set_output_power(&panel, false) {
+                    warn!("Failed to turn {} off: {}", panel.name(), err);
+                }
+                self.space.unmap_output(&panel);
+                self.disabled_outputs.push(panel);
+            }
+        } else {
+            self.enable_disabled_outputs();
+        }
+
+        self.arrange_monitors();
+    }
+
+    /// Brings the panel back when the lid opens, or when the last external monitor is unplugged.
+    /// The caller arranges the monitors.
+    pub fn enable_disabled_outputs(&mut self) {
+        for output in std::mem::take(&mut self.disabled_outputs) {
+            let x = self
+                .space
+                .outputs()
+                .map(|output| {
+                    let geometry = output_canvas_geometry(output);
+                    geometry.loc.x + geometry.size.w
+                })
+                .max()
+                .unwrap_or(0);
+            output.change_current_state(None, None, None, Some((x, 0).into()));
+            self.space.map_output(&output, (x, 0));
+            if let Err(err) = self.set_output_power(&output, true) {
+                warn!("Failed to turn {} on: {}", output.name(), err);
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::env;
 use std::process::Command;
 
@@ -6,6 +7,7 @@ use log::debug;
 use smithay::backend::session::libseat::LibSeatSession;
 use smithay::output::Output;
 use smithay::reexports::calloop::{channel, EventSource};
+use smithay::utils::{Logical, Rectangle};
 use smithay::{
     backend::allocator::dmabuf::Dmabuf,
     reexports::wayland_server::{
@@ -18,7 +20,8 @@ use smithay::{
             with_surface_tree_downward, CompositorClientState, CompositorHandler,
             SurfaceAttributes, TraversalAction,
         },
-        dmabuf::DmabufHandler,
+        dmabuf::{DmabufFeedback, DmabufHandler, SurfaceDmabufFeedbackState},
+        security_context::SecurityContext,
         shell::xdg::XdgShellHandler,
         shm::ShmHandler,
     },
@@ -28,6 +31,7 @@ use crate::flutter_engine::platform_channels::binary_messenger::BinaryMessenger;
 use crate::flutter_engine::FlutterEngine;
 use crate::mouse_button_tracker::MouseButtonTracker;
 use crate::state::State;
+use crate::wayland::security_context::security_context::{ClientPolicy, PrivilegedProtocol};
 
 mod backend;
 mod cursor;
@@ -36,9 +40,12 @@ mod focus;
 mod gles_framebuffer_importer;
 mod input_handling;
 mod keyboard;
+mod logind;
 mod mouse_button_tracker;
 mod state;
+mod surface_textures;
 mod texture_swap_chain;
+mod tone_mapping;
 mod wayland;
 mod xwayland;
 
@@ -88,9 +95,51 @@ pub fn send_frames_surface_tree(surface: &wl_surface::WlSurface, time: u32) {
     );
 }
 
-#[derive(Default)]
+/// The region of the Flutter view covered by `output`.
+/// Flutter draws every monitor at its native resolution and the shell applies the monitor scale itself,
+/// so this spans the mode size rather than the logical size of the output.
+pub fn output_canvas_geometry(output: &Output) -> Rectangle<i32, Logical> {
+    let size = output
+        .current_mode()
+        .map(|mode| output.current_transform().transform_size(mode.size))
+        .unwrap_or_default();
+    Rectangle::from_loc_and_size(output.current_location(), (size.w, size.h))
+}
+
+pub fn set_dmabuf_feedback_surface_tree(
+    surface: &wl_surface::WlSurface,
+    feedback: &DmabufFeedback,
+) {
+    with_surface_tree_downward(
+        surface,
+        (),
+        |_, _, &()| TraversalAction::DoChildren(()),
+        |_surf, states, &()| {
+            // Only surfaces created through a per-surface feedback object have this state.
+            if let Some(surface_feedback) = SurfaceDmabufFeedbackState::from_states(states) {
+                surface_feedback.set_feedback(feedback);
+            }
+        },
+        |_, _, &()| true,
+    );
+}
+
 struct ClientState {
     compositor_state: CompositorClientState,
+    /// Set for clients connecting through a sandbox socket.
+    security_context: Option<SecurityContext>,
+    privileged_protocols: HashSet<PrivilegedProtocol>,
+}
+
+impl ClientState {
+    fn new(security_context: Option<SecurityContext>, client_policy: &ClientPolicy) -> Self {
+        let privileged_protocols = client_policy.allowed_protocols(security_context.as_ref());
+        Self {
+            compositor_state: CompositorClientState::default(),
+            security_context,
+            privileged_protocols,
+        }
+    }
 }
 
 impl ClientData for ClientState {
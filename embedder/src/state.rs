@@ -14,14 +14,16 @@ use smithay::backend::allocator::dmabuf::Dmabuf;
 use smithay::backend::input::KeyState;
 use smithay::backend::renderer::gles::ffi::Gles2;
 use smithay::backend::renderer::gles::GlesRenderer;
-use smithay::backend::renderer::{ImportAll, ImportDma, Texture};
+use smithay::backend::renderer::{ImportAll, ImportDma, ImportMemWl, Texture};
 use smithay::backend::session::Session;
 use smithay::desktop::{Space, Window};
 use smithay::input::keyboard::{KeyboardHandle, Keysym, ModifiersState, XkbConfig};
 use smithay::input::pointer::{CursorImageStatus, PointerHandle};
 use smithay::input::{Seat, SeatHandler, SeatState};
+use smithay::output::Output;
 use smithay::reexports::calloop::channel::Event::Msg;
 use smithay::reexports::calloop::generic::Generic;
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
 use smithay::reexports::calloop::{channel, Interest, LoopHandle, Mode, PostAction};
 use smithay::reexports::wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1;
 use smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel;
@@ -32,18 +34,29 @@ use smithay::reexports::wayland_server::{Client, Display, DisplayHandle, Resourc
 use smithay::reexports::winit::keyboard::KeyCode;
 use smithay::reexports::x11rb::protocol::xproto::Window as X11Window;
 use smithay::utils::{
-    Buffer as BufferCoords, Clock, Logical, Monotonic, Point, Rectangle, Serial, Size,
+    Buffer as BufferCoords, Clock, Logical, Monotonic, Point, Rectangle, Serial, Size, Transform,
     SERIAL_COUNTER,
 };
 use smithay::wayland::buffer::BufferHandler;
 use smithay::wayland::compositor::{self, get_parent, RectangleKind};
 use smithay::wayland::compositor::{
-    with_states, CompositorState, SubsurfaceCachedState, SurfaceAttributes,
+    with_states, with_surface_tree_downward, CompositorState, SubsurfaceCachedState,
+    SurfaceAttributes, TraversalAction,
 };
 use smithay::wayland::dmabuf::{DmabufGlobal, DmabufHandler, DmabufState, ImportNotifier};
+use smithay::wayland::drm_syncobj::{DrmSyncPoint, DrmSyncobjState};
+use smithay::wayland::fractional_scale::{
+    with_fractional_scale, FractionalScaleHandler, FractionalScaleManagerState,
+};
+use smithay::wayland::idle_inhibit::IdleInhibitManagerState;
+use smithay::wayland::idle_notify::IdleNotifierState;
+use smithay::wayland::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitState;
 use smithay::wayland::output::OutputHandler;
+use smithay::wayland::pointer_constraints::PointerConstraintsState;
+use smithay::wayland::presentation::PresentationState;
 use smithay::wayland::relative_pointer::RelativePointerManagerState;
 use smithay::wayland::seat::WaylandFocus;
+use smithay::wayland::security_context::SecurityContextState;
 use smithay::wayland::selection::data_device::{
     set_data_device_focus, ClientDndGrabHandler, DataDeviceHandler, DataDeviceState,
     ServerDndGrabHandler,
@@ -60,6 +73,8 @@ use smithay::wayland::shell::xdg::{
 };
 use smithay::wayland::shm::{ShmHandler, ShmState};
 use smithay::wayland::socket::ListeningSocketSource;
+use smithay::wayland::viewporter::{ViewportCachedState, ViewporterState};
+use smithay::wayland::xdg_activation::XdgActivationState;
 use smithay::wayland::xwayland_shell::{
     self, XWaylandShellHandler, XWaylandShellState, XWAYLAND_SHELL_ROLE,
 };
@@ -69,11 +84,14 @@ use smithay::xwayland::{
 };
 use smithay::{
     delegate_compositor, delegate_data_control, delegate_data_device, delegate_dmabuf,
-    delegate_output, delegate_primary_selection, delegate_relative_pointer, delegate_seat,
-    delegate_shm, delegate_xdg_shell, delegate_xwayland_shell,
+    delegate_fractional_scale, delegate_output, delegate_presentation, delegate_primary_selection,
+    delegate_relative_pointer, delegate_seat, delegate_shm, delegate_viewporter,
+    delegate_xdg_shell, delegate_xwayland_shell,
 };
 use tracing::{info, warn};
 
+use crate::backend::monitor_profiles::MonitorProfiles;
+use crate::backend::VrrMode;
 use crate::cursor::Cursor;
 use crate::flutter_engine::wayland_messages::{
     MyPoint, PopupMessage, SubsurfaceMessage, SurfaceMessage, SurfaceRole, ToplevelMessage,
@@ -83,57 +101,121 @@ use crate::flutter_engine::FlutterEngine;
 use crate::focus::{KeyboardFocusTarget, PointerFocusTarget};
 use crate::keyboard::key_repeater::KeyRepeater;
 use crate::keyboard::KeyEvent;
+use crate::logind::Logind;
 use crate::texture_swap_chain::TextureSwapChain;
+use crate::tone_mapping::ToneMapper;
+use crate::wayland::color_management::color_management::ColorManagementState;
+use crate::wayland::foreign_toplevel::foreign_toplevel::ForeignToplevelManagerState;
+use crate::wayland::gamma_control::gamma_control::GammaControlManagerState;
+use crate::wayland::idle::idle::IdleState;
+use crate::wayland::output_power_management::output_power_management::OutputPowerManagementState;
+use crate::wayland::pointer_constraints::pointer_constraints::ActivePointerConstraint;
+use crate::wayland::security_context::security_context::{
+    can_view_privileged_global, can_view_security_context_global, ClientPolicy, PrivilegedProtocol,
+};
+use crate::wayland::tearing_control::tearing_control::TearingControlManagerState;
+use crate::wayland::virtual_input::virtual_input::VirtualInputState;
 use crate::wayland::wayland::{get_direct_subsurfaces, get_surface_id};
-use crate::{flutter_engine, Backend, ClientState};
+use crate::{flutter_engine, send_frames_surface_tree, Backend, ClientState};
+
+const HIDDEN_SURFACE_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The layout of the seat keyboard, virtual keyboards are translated to it.
+pub const KEYBOARD_LAYOUT: &str = "us(altgr-intl)";
 
 pub struct State<BackendData: Backend + 'static> {
+    pub active_pointer_constraint: Option<ActivePointerConstraint>,
     pub backend_data: Box<BackendData>,
     pub batons: Vec<flutter_engine::Baton>,
+    pub client_policy: ClientPolicy,
+    /// Keys forwarded straight to the client inhibiting shortcuts, which gets their release too.
+    pub client_pressed_keys: HashSet<u32>,
     pub clock: Clock<Monotonic>,
+    pub color_management_state: ColorManagementState,
     pub compositor_state: CompositorState,
     pub data_control_state: DataControlState,
     pub data_device_state: DataDeviceState,
     pub display_handle: DisplayHandle,
     pub dmabuf_state: Option<DmabufState>,
+    /// Only created when the GPU supports waiting for syncobj points with an eventfd.
+    pub drm_syncobj_state: Option<DrmSyncobjState>,
     pub flutter_engine: Option<Box<FlutterEngine<BackendData>>>,
+    /// Keys whose press went through Flutter, which has to see their release too.
+    pub flutter_pressed_keys: HashSet<u32>,
+    pub foreign_toplevel_manager_state: ForeignToplevelManagerState,
+    pub fractional_scale_manager_state: FractionalScaleManagerState,
+    pub gamma_control_manager_state: GammaControlManagerState,
     pub gl: Option<Gles2>,
     pub gles_renderer: Option<GlesRenderer>,
-    pub imported_dmabufs: Vec<Dmabuf>,
+    pub idle_inhibit_manager_state: IdleInhibitManagerState,
+    pub idle_notifier_state: IdleNotifierState<State<BackendData>>,
+    pub idle_state: IdleState,
+    /// Outputs taken out of the canvas, like the panel of a docked laptop with its lid closed.
+    pub disabled_outputs: Vec<Output>,
     pub is_next_flutter_frame_scheduled: bool,
     pub keyboard: KeyboardHandle<State<BackendData>>,
+    pub keyboard_shortcuts_inhibit_state: KeyboardShortcutsInhibitState,
     pub key_repeater: KeyRepeater<BackendData>,
+    pub logind: Option<Logind>,
     pub loop_handle: LoopHandle<'static, State<BackendData>>,
+    /// Outputs replicating another one, they aren't mapped in the space.
+    pub mirrored_outputs: Vec<Output>,
+    /// Where the shell displays each surface. `None` means the surface is hidden or off-screen.
+    /// Surfaces the shell hasn't reported yet are missing from the map.
+    pub monitor_per_surface_id: HashMap<u64, Option<String>>,
+    pub monitor_profiles: MonitorProfiles,
     pub next_surface_id: u64,
     pub next_texture_id: i64,
     pub next_x11_surface_id: u64,
+    pub output_power_management_state: OutputPowerManagementState,
     pub pointer: PointerHandle<State<BackendData>>,
     pub pointer_frame_pending: bool,
+    /// Release points of replaced buffers, signaled once Flutter presents its next frame.
+    pub pending_release_points: Vec<DrmSyncPoint>,
+    pub presentation_state: PresentationState,
     pub primary_selection_state: PrimarySelectionState,
+    /// The release point of the buffer each surface currently shows.
+    pub release_point_per_surface_id: HashMap<u64, DrmSyncPoint>,
+    /// Release points of the buffers planes scan out, or will from the next page flip.
+    /// A buffer can be on several planes at once.
+    pub release_points_on_planes: Vec<DrmSyncPoint>,
     pub repeat_delay: u64,
     pub repeat_rate: u64,
     pub running: Arc<AtomicBool>,
+    pub scanout_surface_per_output: HashMap<String, u64>,
     pub seat: Seat<State<BackendData>>,
     pub seat_state: SeatState<State<BackendData>>,
+    pub security_context_state: SecurityContextState,
+    pub shortcuts_inhibiting_surface_id: Option<u64>,
     pub shm_state: ShmState,
     pub space: Space<Window>,
     pub surface_id_per_texture_id: HashMap<i64, u64>,
+    pub surface_ids_sampled_by_flutter: HashSet<u64>,
     pub surface_id_under_cursor: Option<u64>,
     pub pointer_focus: Option<(PointerFocusTarget, Point<f64, Logical>)>,
     pub surfaces: HashMap<u64, WlSurface>,
     pub subsurfaces: HashMap<u64, WlSurface>,
+    pub tearing_control_manager_state: TearingControlManagerState,
+    pub tearing_surface_ids_committed: HashSet<u64>,
     pub texture_ids_per_surface_id: HashMap<u64, Vec<(i64, Size<i32, BufferCoords>)>>,
     pub texture_swapchains: HashMap<i64, TextureSwapChain>,
+    pub tone_mapper: Option<ToneMapper>,
     pub tx_fbo: Option<channel::Sender<Option<Dmabuf>>>,
     pub tx_flutter_handled_key_event: channel::Sender<(KeyEvent, bool)>,
+    pub viewporter_state: ViewporterState,
+    pub virtual_input_state: VirtualInputState,
+    pub vrr_mode_per_output: HashMap<String, VrrMode>,
     pub wayland_socket_name: Option<String>,
     pub x11_surface_per_wl_surface: HashMap<WlSurface, X11Surface>,
     pub x11_surface_per_x11_window: HashMap<X11Window, X11Surface>,
     pub x11_wm: Option<X11Wm>,
+    pub xdg_activation_state: XdgActivationState,
     pub xdg_popups: HashMap<u64, PopupSurface>,
     pub xdg_shell_state: XdgShellState,
     pub xdg_toplevels: HashMap<u64, ToplevelSurface>,
     pub xwayland_display: Option<u32>,
+    /// Scale of the DPI X11 clients are told, see `update_xwayland_scale`.
+    pub xwayland_scale: f64,
     pub xwayland_shell_state: xwayland_shell::XWaylandShellState,
 }
 
@@ -156,6 +238,16 @@ impl<BackendData: Backend + 'static> State<BackendData> {
         texture_id
     }
 
+    /// Advertises every shm format the renderer can upload, on top of the mandatory ARGB and XRGB.
+    pub fn update_shm_formats(&mut self) {
+        let formats = self
+            .gles_renderer
+            .as_ref()
+            .map(|gles_renderer| gles_renderer.shm_formats().collect::<Vec<_>>())
+            .unwrap_or_default();
+        self.shm_state.update_formats(formats);
+    }
+
     pub fn handle_key_event(&mut self, mut key_code: u32, state: KeyState, time: u32) {
         // Update the state of the keyboard.
         // Every key event must be passed through `glfw_key_codes.input_intercept`
@@ -178,6 +270,48 @@ impl<BackendData: Backend + 'static> State<BackendData> {
 
         // 3. Check if the keystroke result in compositor hotkeys shortcuts
 
+        if self.handle_power_key(keysym, state == KeyState::Pressed) {
+            return;
+        }
+
+        // Every release goes where its press went, whether or not shortcuts are still inhibited,
+        // like Alt after Alt+Tab into a VM viewer, or Super after escaping from it.
+        if state == KeyState::Released && self.client_pressed_keys.remove(&key_code) {
+            keyboard.input_forward(
+                self,
+                key_code,
+                state,
+                SERIAL_COUNTER.next_serial(),
+                time,
+                mods_changed,
+            );
+            return;
+        }
+        if state == KeyState::Released && !self.flutter_pressed_keys.contains(&key_code) {
+            // The press was swallowed, like the Escape of the escape hatch.
+            return;
+        }
+
+        // Remote desktops, VM viewers and games get every other key while they inhibit shortcuts,
+        // without going through Flutter first.
+        // The escape hatch isn't Alt+Escape, so that pressing it twice doesn't exit the compositor.
+        if state == KeyState::Pressed && self.are_shortcuts_inhibited() {
+            if keysym == Keysym::Escape && mods.logo {
+                self.escape_shortcuts_inhibitor();
+                return;
+            }
+            self.client_pressed_keys.insert(key_code);
+            keyboard.input_forward(
+                self,
+                key_code,
+                state,
+                SERIAL_COUNTER.next_serial(),
+                time,
+                mods_changed,
+            );
+            return;
+        }
+
         // Switching to another VT
         if (Keysym::XF86_Switch_VT_1.raw()..=Keysym::XF86_Switch_VT_12.raw())
             .contains(&keysym.raw())
@@ -198,6 +332,10 @@ impl<BackendData: Backend + 'static> State<BackendData> {
         }
 
         // 4. Forward the key event to Flutter.
+        match state {
+            KeyState::Pressed => self.flutter_pressed_keys.insert(key_code),
+            KeyState::Released => self.flutter_pressed_keys.remove(&key_code),
+        };
         self.flutter_engine.as_mut().unwrap().send_key_event(
             self.tx_flutter_handled_key_event.clone(),
             KeyEvent {
@@ -260,6 +398,7 @@ delegate_seat!(@<BackendData: Backend + 'static> State<BackendData>);
 delegate_data_device!(@<BackendData: Backend + 'static> State<BackendData>);
 delegate_xwayland_shell!(@<BackendData: Backend + 'static> State<BackendData>);
 delegate_relative_pointer!(@<BackendData: Backend + 'static> State<BackendData>);
+delegate_presentation!(@<BackendData: Backend + 'static> State<BackendData>);
 
 impl<BackendData: Backend + 'static> State<BackendData> {
     pub fn new(
@@ -284,7 +423,7 @@ impl<BackendData: Backend + 'static> State<BackendData> {
         let keyboard = seat
             .add_keyboard(
                 XkbConfig {
-                    layout: "us(altgr-intl)",
+                    layout: KEYBOARD_LAYOUT,
                     ..XkbConfig::default()
                 },
                 repeat_delay as i32,
@@ -293,9 +432,11 @@ impl<BackendData: Backend + 'static> State<BackendData> {
             .unwrap();
 
         let pointer = seat.add_pointer();
-        // Expose global only if backend supports relative motion events
+        // Expose globals only if backend supports relative motion events,
+        // locking the pointer is useless without them
         if BackendData::HAS_RELATIVE_MOTION {
             RelativePointerManagerState::new::<Self>(&display_handle);
+            PointerConstraintsState::new::<Self>(&display_handle);
         }
 
         let data_device_state = DataDeviceState::new::<Self>(&display_handle);
@@ -303,18 +444,20 @@ impl<BackendData: Backend + 'static> State<BackendData> {
         let data_control_state = DataControlState::new::<Self, _>(
             &display_handle,
             Some(&primary_selection_state),
-            |_| true,
+            |client| can_view_privileged_global(client, PrivilegedProtocol::DataControl),
         );
+        let security_context_state =
+            SecurityContextState::new::<Self, _>(&display_handle, can_view_security_context_global);
 
         // init wayland clients
         let source = ListeningSocketSource::new_auto().unwrap();
         let socket_name = source.socket_name().to_string_lossy().into_owned();
         loop_handle
             .insert_source(source, |client_stream, _, data| {
-                if let Err(err) = data
-                    .display_handle
-                    .insert_client(client_stream, Arc::new(ClientState::default()))
-                {
+                if let Err(err) = data.display_handle.insert_client(
+                    client_stream,
+                    Arc::new(ClientState::new(None, &data.client_policy)),
+                ) {
                     warn!("Error adding wayland client: {}", err);
                 };
             })
@@ -340,6 +483,18 @@ impl<BackendData: Backend + 'static> State<BackendData> {
             )
             .expect("Failed to init wayland server source");
 
+        // Hidden clients still get frame callbacks, but at a slow pace so they don't block
+        // forever waiting for one, without burning CPU either.
+        loop_handle
+            .insert_source(
+                Timer::from_duration(HIDDEN_SURFACE_FRAME_INTERVAL),
+                |_, _, data| {
+                    data.send_frame_callbacks_to_hidden_surfaces();
+                    TimeoutAction::ToDuration(HIDDEN_SURFACE_FRAME_INTERVAL)
+                },
+            )
+            .unwrap();
+
         let (tx_flutter_handled_key_event, rx_flutter_handled_key_event) =
             channel::channel::<(KeyEvent, bool)>();
 
@@ -416,12 +571,39 @@ impl<BackendData: Backend + 'static> State<BackendData> {
         );
 
         let xwayland_shell_state = xwayland_shell::XWaylandShellState::new::<Self>(&display_handle);
+        let fractional_scale_manager_state =
+            FractionalScaleManagerState::new::<Self>(&display_handle);
+        let viewporter_state = ViewporterState::new::<Self>(&display_handle);
+        let presentation_state = PresentationState::new::<Self>(&display_handle, clock.id() as u32);
+        let color_management_state = ColorManagementState::new::<BackendData>(&display_handle);
+        let gamma_control_manager_state =
+            GammaControlManagerState::new::<BackendData>(&display_handle);
+        let tearing_control_manager_state =
+            TearingControlManagerState::new::<BackendData>(&display_handle);
+        let output_power_management_state =
+            OutputPowerManagementState::new::<BackendData>(&display_handle);
+        let foreign_toplevel_manager_state =
+            ForeignToplevelManagerState::new::<BackendData>(&display_handle);
+        let idle_notifier_state = IdleNotifierState::new(&display_handle, loop_handle.clone());
+        let idle_inhibit_manager_state = IdleInhibitManagerState::new::<Self>(&display_handle);
+        let xdg_activation_state = XdgActivationState::new::<Self>(&display_handle);
+        let keyboard_shortcuts_inhibit_state =
+            KeyboardShortcutsInhibitState::new::<Self>(&display_handle);
+        let virtual_input_state = VirtualInputState::new::<BackendData>(&display_handle);
 
         Self {
             running: Arc::new(AtomicBool::new(true)),
             display_handle,
             loop_handle,
+            mirrored_outputs: Vec::new(),
+            disabled_outputs: Vec::new(),
+            logind: None,
+            monitor_per_surface_id: HashMap::new(),
+            monitor_profiles: MonitorProfiles::load(),
+            output_power_management_state,
             clock,
+            client_policy: ClientPolicy::default(),
+            color_management_state,
             tx_fbo: None,
             batons: vec![],
             backend_data: Box::new(backend_data),
@@ -431,15 +613,31 @@ impl<BackendData: Backend + 'static> State<BackendData> {
             xdg_shell_state,
             shm_state,
             flutter_engine: None,
+            flutter_pressed_keys: HashSet::new(),
+            client_pressed_keys: HashSet::new(),
+            foreign_toplevel_manager_state,
+            fractional_scale_manager_state,
+            gamma_control_manager_state,
+            idle_inhibit_manager_state,
+            idle_notifier_state,
+            idle_state: IdleState::new(),
             dmabuf_state,
+            drm_syncobj_state: None,
             seat,
             seat_state,
+            security_context_state,
             data_device_state,
             primary_selection_state,
             data_control_state,
             pointer,
             pointer_frame_pending: false,
+            pending_release_points: Vec::new(),
+            presentation_state,
             keyboard,
+            keyboard_shortcuts_inhibit_state,
+            shortcuts_inhibiting_surface_id: None,
+            release_point_per_surface_id: HashMap::new(),
+            release_points_on_planes: Vec::new(),
             repeat_delay,
             repeat_rate,
             tx_flutter_handled_key_event,
@@ -447,27 +645,167 @@ impl<BackendData: Backend + 'static> State<BackendData> {
             x11_wm: None,
             wayland_socket_name: Some(socket_name),
             xwayland_display: None,
+            xwayland_scale: 1.0,
             next_surface_id: 1,
             next_x11_surface_id: 1,
             next_texture_id: 1,
-            imported_dmabufs: Vec::new(),
             gles_renderer: None,
             gl: None,
             surfaces: HashMap::new(),
+            scanout_surface_per_output: HashMap::new(),
             subsurfaces: HashMap::new(),
+            tearing_control_manager_state,
+            tearing_surface_ids_committed: HashSet::new(),
+            viewporter_state,
+            virtual_input_state,
+            vrr_mode_per_output: HashMap::new(),
+            xdg_activation_state,
             xdg_toplevels: HashMap::new(),
             xdg_popups: HashMap::new(),
             x11_surface_per_x11_window: HashMap::new(),
             x11_surface_per_wl_surface: HashMap::new(),
             texture_ids_per_surface_id: HashMap::new(),
             surface_id_per_texture_id: HashMap::new(),
+            surface_ids_sampled_by_flutter: HashSet::new(),
             texture_swapchains: HashMap::new(),
+            tone_mapper: None,
             xwayland_shell_state,
             space: Space::default(),
             pointer_focus: None,
+            active_pointer_constraint: None,
+        }
+    }
+
+    /// Sends frame callbacks to the surfaces the shell displays on `output`,
+    /// so that clients draw at the refresh rate of the monitor they're on.
+    /// Surfaces the shell hasn't placed yet follow the primary output, so they can draw their first frames.
+    pub fn send_frame_callbacks(&self, output: &Output, is_primary_output: bool) {
+        let output_name = output.name();
+        let time = Duration::from(self.clock.now()).as_millis() as u32;
+
+        for surface in self.root_surfaces() {
+            let surface_id = get_surface_id(&surface);
+            let is_on_output = match self.monitor_per_surface_id.get(&surface_id) {
+                Some(Some(monitor_name)) => *monitor_name == output_name,
+                Some(None) => false,
+                None => is_primary_output,
+            };
+            if is_on_output {
+                send_frames_surface_tree(&surface, time);
+            }
         }
     }
 
+    /// Includes the surfaces on monitors that are turned off, which don't have VBlanks to pace them.
+    fn send_frame_callbacks_to_hidden_surfaces(&self) {
+        let time = Duration::from(self.clock.now()).as_millis() as u32;
+        let power_state = &self.output_power_management_state;
+
+        for surface in self.root_surfaces() {
+            let surface_id = get_surface_id(&surface);
+            let is_hidden = match self.monitor_per_surface_id.get(&surface_id) {
+                // The monitor might have been unplugged before the shell moved the window.
+                Some(Some(monitor_name)) => !self.space.outputs().any(|output| {
+                    output.name() == *monitor_name && power_state.is_powered_on(output)
+                }),
+                Some(None) => true,
+                // Unplaced surfaces follow the primary output, which is one that's on.
+                None => !self
+                    .space
+                    .outputs()
+                    .any(|output| power_state.is_powered_on(output)),
+            };
+            if is_hidden {
+                send_frames_surface_tree(&surface, time);
+            }
+        }
+    }
+
+    /// The output the shell displays this surface on.
+    /// Surfaces that haven't been placed yet are assumed to show up on the first output.
+    pub fn output_for_surface_id(&self, surface_id: u64) -> Option<Output> {
+        match self.monitor_per_surface_id.get(&surface_id) {
+            Some(Some(monitor_name)) => self
+                .space
+                .outputs()
+                .find(|output| output.name() == *monitor_name)
+                .cloned(),
+            Some(None) => None,
+            None => self.space.outputs().next().cloned(),
+        }
+    }
+
+    /// Moves a surface tree from `previous_output` to the output the shell now displays it on.
+    /// Integer-scale clients pick their buffer scale from wl_surface.enter,
+    /// fractional-scale clients get the preferred scale of the new output.
+    pub fn surface_output_changed(&self, surface: &WlSurface, previous_output: Option<Output>) {
+        let output = self.output_for_surface_id(get_surface_id(surface));
+        if output == previous_output {
+            return;
+        }
+
+        with_surface_tree_downward(
+            surface,
+            (),
+            |_, _, &()| TraversalAction::DoChildren(()),
+            |surface, surface_data, &()| {
+                if let Some(ref previous_output) = previous_output {
+                    previous_output.leave(surface);
+                }
+                if let Some(ref output) = output {
+                    output.enter(surface);
+                    let scale = output.current_scale().fractional_scale();
+                    with_fractional_scale(surface_data, |fractional_scale| {
+                        fractional_scale.set_preferred_scale(scale);
+                    });
+                }
+            },
+            |_, _, &()| true,
+        );
+    }
+
+    /// Sends the new preferred scale to the clients displayed on `output`,
+    /// and the new DPI to X11 clients if it's now the largest scale.
+    pub fn output_scale_changed(&mut self, output: &Output) {
+        let scale = output.current_scale().fractional_scale();
+
+        for surface in self.root_surfaces() {
+            if self
+                .output_for_surface_id(get_surface_id(&surface))
+                .as_ref()
+                != Some(output)
+            {
+                continue;
+            }
+            with_surface_tree_downward(
+                &surface,
+                (),
+                |_, _, &()| TraversalAction::DoChildren(()),
+                |_, surface_data, &()| {
+                    with_fractional_scale(surface_data, |fractional_scale| {
+                        fractional_scale.set_preferred_scale(scale);
+                    });
+                },
+                |_, _, &()| true,
+            );
+        }
+        self.update_xwayland_scale();
+    }
+
+    fn root_surfaces(&self) -> Vec<WlSurface> {
+        self.xdg_shell_state
+            .toplevel_surfaces()
+            .iter()
+            .map(|toplevel| toplevel.wl_surface().clone())
+            .chain(
+                self.xdg_popups
+                    .values()
+                    .map(|popup| popup.wl_surface().clone()),
+            )
+            .chain(self.x11_surface_per_wl_surface.keys().cloned())
+            .collect()
+    }
+
     pub fn change_keyboard_repeat_info(&mut self, repeat_delay: u64, repeat_rate: u64) {
         self.repeat_delay = repeat_delay;
         self.repeat_rate = repeat_rate;
@@ -479,13 +817,38 @@ impl<BackendData: Backend + 'static> State<BackendData> {
         let surface_id = get_surface_id(surface);
         let role = self.construct_surface_role_message(surface);
 
-        let (buffer_delta, buffer_scale, input_region) = with_states(surface, |surface_data| {
-            let mut binding = surface_data.cached_state.get::<SurfaceAttributes>();
-            let surface_state = binding.current();
-            let buffer_delta = surface_state.buffer_delta;
-            let buffer_scale = surface_state.buffer_scale;
-            let input_region = surface_state.input_region.clone();
-            (buffer_delta, buffer_scale, input_region)
+        let (buffer_delta, buffer_scale, buffer_transform, input_region) =
+            with_states(surface, |surface_data| {
+                let mut binding = surface_data.cached_state.get::<SurfaceAttributes>();
+                let surface_state = binding.current();
+                let buffer_delta = surface_state.buffer_delta;
+                let buffer_scale = surface_state.buffer_scale;
+                let buffer_transform = surface_state.buffer_transform;
+                let input_region = surface_state.input_region.clone();
+                (buffer_delta, buffer_scale, buffer_transform, input_region)
+            });
+        let (viewport_source, viewport_destination) = with_states(surface, |surface_data| {
+            let mut binding = surface_data.cached_state.get::<ViewportCachedState>();
+            let viewport = binding.current();
+            (viewport.src, viewport.size())
+        });
+
+        // X11 windows are drawn at the XWayland scale, the shell shows them at their logical size.
+        let pixel_scale = self.surface_pixel_scale(surface);
+        let viewport_destination = viewport_destination.or_else(|| {
+            if pixel_scale == 1.0 {
+                return None;
+            }
+            let (_, size) = self
+                .texture_ids_per_surface_id
+                .get(&surface_id)
+                .and_then(|ids| ids.last().cloned())?;
+            Some(
+                size.to_logical(1, Transform::Normal)
+                    .to_f64()
+                    .downscale(pixel_scale)
+                    .to_i32_round(),
+            )
         });
 
         let (texture_id, buffer_size) = self
@@ -508,10 +871,16 @@ impl<BackendData: Backend + 'static> State<BackendData> {
                 }
             }
             acc.unwrap_or_default()
+                .to_f64()
+                .downscale(pixel_scale)
+                .to_i32_round()
         } else {
-            // TODO: Account for DPI scaling.
-            buffer_size
-                .map(|size| Rectangle::from_loc_and_size((0, 0), (size.w, size.h)))
+            // The viewport takes precedence over the buffer scale to map the buffer to the surface.
+            viewport_destination
+                .or_else(|| {
+                    buffer_size.map(|size| size.to_logical(buffer_scale, buffer_transform.into()))
+                })
+                .map(|size| Rectangle::from_loc_and_size((0, 0), size))
                 .unwrap_or_default()
         };
 
@@ -524,9 +893,12 @@ impl<BackendData: Backend + 'static> State<BackendData> {
             buffer_delta: buffer_delta.map(|delta| delta.into()),
             buffer_size: buffer_size.map(|b| b.into()),
             scale: buffer_scale,
+            viewport_source: viewport_source.map(|source| source.into()),
+            viewport_destination: viewport_destination.map(|destination| destination.into()),
             input_region: input_region.into(),
             subsurfaces_below,
             subsurfaces_above,
+            buffer_damage: None,
         }
     }
 
@@ -694,7 +1066,6 @@ impl<BackendData: Backend> DmabufHandler for State<BackendData> {
             .is_ok()
         {
             let _ = notifier.successful::<State<BackendData>>();
-            self.imported_dmabufs.push(dmabuf);
         } else {
             notifier.failed();
         }
@@ -727,9 +1098,10 @@ impl<BackendData: Backend> SeatHandler for State<BackendData> {
     fn focus_changed(&mut self, seat: &Seat<Self>, target: Option<&KeyboardFocusTarget>) {
         let dh = &self.display_handle;
         let wl_surface = target.and_then(WaylandFocus::wl_surface);
-        let client = wl_surface.and_then(|s| dh.get_client(s.id()).ok());
+        let client = wl_surface.as_ref().and_then(|s| dh.get_client(s.id()).ok());
         set_data_device_focus(dh, seat, client.clone());
         set_primary_focus(dh, seat, client);
+        self.update_shortcuts_inhibitor(wl_surface.as_deref());
     }
 
     fn cursor_image(&mut self, _seat: &Seat<Self>, image: CursorImageStatus) {}
@@ -792,3 +1164,24 @@ impl<BackendData: Backend> DataControlHandler for State<BackendData> {
     }
 }
 delegate_data_control!(@<BackendData: Backend + 'static> State<BackendData>);
+
+impl<BackendData: Backend> FractionalScaleHandler for State<BackendData> {
+    fn new_fractional_scale(&mut self, surface: WlSurface) {
+        let mut root = surface.clone();
+        while let Some(parent) = get_parent(&root) {
+            root = parent;
+        }
+        let scale = self
+            .output_for_surface_id(get_surface_id(&root))
+            .map(|output| output.current_scale().fractional_scale())
+            .unwrap_or(1.0);
+
+        with_states(&surface, |surface_data| {
+            with_fractional_scale(surface_data, |fractional_scale| {
+                fractional_scale.set_preferred_scale(scale);
+            });
+        });
+    }
+}
+delegate_fractional_scale!(@<BackendData: Backend + 'static> State<BackendData>);
+delegate_viewporter!(@<BackendData: Backend + 'static> State<BackendData>);
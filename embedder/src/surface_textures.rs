@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use smithay::reexports::wayland_server::Resource;
+use smithay::utils::{Buffer as BufferCoords, Size};
+use tracing::warn;
+
+use crate::backend::Backend;
+use crate::flutter_engine::wayland_messages::ClientGpuMemoryMessage;
+use crate::state::State;
+
+/// Surfaces keep the texture of their previous size until the shell lays them out with the new one.
+const TEXTURES_PER_SURFACE: usize = 2;
+
+/// Client textures are RGBA, 10-bit formats take as much room.
+const BYTES_PER_PIXEL: usize = 4;
+
+impl<BackendData: Backend + 'static> State<BackendData> {
+    /// The Flutter external texture the buffer of a surface goes to.
+    /// A new one is registered when the size changes, which unregisters the oldest one.
+    pub fn surface_texture_id(
+        &mut self,
+        surface_id: u64,
+        size: Size<i32, BufferCoords>,
+        size_changed: bool,
+    ) -> i64 {
+        if !size_changed {
+            let current = self
+                .texture_ids_per_surface_id
+                .get(&surface_id)
+                .and_then(|ids| ids.last())
+                .map(|(id, _)| *id);
+            if let Some(texture_id) = current {
+                return texture_id;
+            }
+        }
+
+        let texture_id = self.get_new_texture_id();
+        let texture_ids = self
+            .texture_ids_per_surface_id
+            .entry(surface_id)
+            .or_default();
+        let retired = if texture_ids.len() >= TEXTURES_PER_SURFACE {
+            texture_ids
+                .drain(..=texture_ids.len() - TEXTURES_PER_SURFACE)
+                .collect()
+        } else {
+            vec![]
+        };
+        texture_ids.push((texture_id, size));
+        for (retired_texture_id, _) in retired {
+            self.release_texture(retired_texture_id);
+        }
+
+        self.surface_id_per_texture_id
+            .insert(texture_id, surface_id);
+        self.flutter_engine_mut()
+            .register_external_texture(texture_id)
+            .unwrap();
+        texture_id
+    }
+
+    /// Unregisters the textures of a destroyed surface.
+    pub fn release_surface_textures(&mut self, surface_id: u64) {
+        let Some(texture_ids) = self.texture_ids_per_surface_id.remove(&surface_id) else {
+            return;
+        };
+        for (texture_id, _) in texture_ids {
+            self.release_texture(texture_id);
+        }
+        // Imports of dmabufs whose buffers are gone are only freed on cleanup.
+        if let Some(gles_renderer) = self.gles_renderer.as_mut() {
+            if let Err(err) = gles_renderer.cleanup_texture_cache() {
+                warn!("Failed to clean up the texture cache: {}", err);
+            }
+        }
+    }
+
+    fn release_texture(&mut self, texture_id: i64) {
+        self.surface_id_per_texture_id.remove(&texture_id);
+        // Dropping the swapchain drops the last references to its `GlesTexture`s.
+        if let Some(swapchain) = self.texture_swapchains.remove(&texture_id) {
+            swapchain.release(self.gl.as_ref().unwrap());
+        }
+        if let Err(err) = self
+            .flutter_engine()
+            .unregister_external_texture(texture_id)
+        {
+            warn!("{}", err);
+        }
+    }
+
+    /// The GPU memory taken by the textures of each client, for the shell to point out the hungry ones.
+    pub fn gpu_memory_statistics(&self) -> Vec<ClientGpuMemoryMessage> {
+        let mut statistics = HashMap::<Option<i32>, ClientGpuMemoryMessage>::new();
+        for (surface_id, texture_ids) in &self.texture_ids_per_surface_id {
+            let Some(surface) = self.surfaces.get(surface_id) else {
+                continue;
+            };
+            let pid = surface
+                .client()
+                .and_then(|client| client.get_credentials(&self.display_handle).ok())
+                .map(|credentials| credentials.pid);
+
+            let client_statistics =
+                statistics
+                    .entry(pid)
+                    .or_insert_with(|| ClientGpuMemoryMessage {
+                        pid,
+                        surface_ids: vec![],
+                        texture_count: 0,
+                        bytes: 0,
+                    });
+            client_statistics.surface_ids.push(*surface_id);
+            for (_, size) in texture_ids {
+                client_statistics.texture_count += 1;
+                client_statistics.bytes += size.w as usize * size.h as usize * BYTES_PER_PIXEL;
+            }
+        }
+        statistics.into_values().collect()
+    }
+}
@@ -1,9 +1,73 @@
-use smithay::backend::renderer::gles::GlesTexture;
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd};
+
+use smithay::backend::egl::{ffi::egl as egl_ffi, EGLDisplay};
+use smithay::backend::renderer::gles::{ffi, GlesTexture};
+use tracing::warn;
+
+/// Signaled once the GPU is done writing a texture.
+/// Flutter waits for it on its own context instead of the compositor stalling on `glFinish`.
+#[derive(Debug)]
+pub enum TextureFence {
+    /// Created after the compositor imported or converted the texture.
+    Gl(ffi::types::GLsync),
+    /// The acquire point of a client using explicit sync.
+    SyncFile(OwnedFd),
+}
+
+// The fence is created on the compositor context and waited for on the Flutter raster thread,
+// which share their objects.
+unsafe impl Send for TextureFence {}
+
+impl TextureFence {
+    /// Makes the GPU of the current context wait for the fence, without blocking the CPU.
+    pub unsafe fn wait(self, gl: &ffi::Gles2, display: &EGLDisplay) {
+        match self {
+            TextureFence::Gl(sync) => {
+                gl.WaitSync(sync, 0, ffi::TIMEOUT_IGNORED);
+                gl.DeleteSync(sync);
+            }
+            TextureFence::SyncFile(sync_file) => wait_for_sync_file(display, sync_file),
+        }
+    }
+
+    unsafe fn delete(self, gl: &ffi::Gles2) {
+        if let TextureFence::Gl(sync) = self {
+            gl.DeleteSync(sync);
+        }
+    }
+}
+
+/// Imports the sync file as an EGL native fence and waits for it on the GPU of the current context.
+pub unsafe fn wait_for_sync_file(display: &EGLDisplay, sync_file: OwnedFd) {
+    let display = **display.get_display_handle();
+    let attributes = [
+        egl_ffi::SYNC_NATIVE_FENCE_FD_ANDROID as egl_ffi::types::EGLint,
+        sync_file.as_raw_fd(),
+        egl_ffi::NONE as egl_ffi::types::EGLint,
+    ];
+    let sync = egl_ffi::CreateSyncKHR(
+        display,
+        egl_ffi::SYNC_NATIVE_FENCE_ANDROID,
+        attributes.as_ptr(),
+    );
+    if sync.is_null() {
+        warn!("Failed to import the sync file as an EGL fence");
+        return;
+    }
+    // EGL owns the file descriptor once the fence is created.
+    let _ = sync_file.into_raw_fd();
+    egl_ffi::WaitSyncKHR(display, sync, 0);
+    egl_ffi::DestroySyncKHR(display, sync);
+}
 
 #[derive(Default, Debug)]
 pub struct TextureSwapChain {
     pub newest: Option<GlesTexture>,
+    /// Handed out with the first read of `newest`, whoever gets them waits for them and deletes them.
+    newest_fences: Vec<TextureFence>,
     in_use: Option<GlesTexture>,
+    /// Textures the compositor rendered the surface into, reused once Flutter stopped sampling them.
+    render_targets: Vec<GlesTexture>,
 }
 
 impl TextureSwapChain {
@@ -11,13 +75,55 @@ impl TextureSwapChain {
         Default::default()
     }
 
-    pub fn commit(&mut self, texture: GlesTexture) {
+    pub fn commit(&mut self, gl: &ffi::Gles2, texture: GlesTexture, fences: Vec<TextureFence>) {
+        // Nobody read the previous texture, so nobody has its fences either.
+        for previous_fence in self.newest_fences.drain(..) {
+            unsafe {
+                previous_fence.delete(gl);
+            }
+        }
         self.newest = Some(texture);
+        self.newest_fences = fences;
     }
 
-    pub fn start_read(&mut self) -> GlesTexture {
+    /// A texture to render the next frame into, `create` is only called when all of them are still sampled.
+    pub fn render_target<E>(
+        &mut self,
+        create: impl FnOnce() -> Result<GlesTexture, E>,
+    ) -> Result<GlesTexture, E> {
+        let is_sampled = |texture: &GlesTexture| {
+            [&self.newest, &self.in_use]
+                .into_iter()
+                .flatten()
+                .any(|sampled| sampled.tex_id() == texture.tex_id())
+        };
+        if let Some(texture) = self
+            .render_targets
+            .iter()
+            .find(|texture| !is_sampled(texture))
+        {
+            return Ok(texture.clone());
+        }
+        let texture = create()?;
+        self.render_targets.push(texture.clone());
+        Ok(texture)
+    }
+
+    pub fn start_read(&mut self) -> (GlesTexture, Vec<TextureFence>) {
         self.in_use = self.newest.clone();
-        self.in_use.clone().unwrap()
+        (
+            self.in_use.clone().unwrap(),
+            std::mem::take(&mut self.newest_fences),
+        )
+    }
+
+    /// Deletes the fences nobody read, the textures are freed once dropped.
+    pub fn release(mut self, gl: &ffi::Gles2) {
+        for fence in self.newest_fences.drain(..) {
+            unsafe {
+                fence.delete(gl);
+            }
+        }
     }
 
     pub fn end_read(&mut self) {
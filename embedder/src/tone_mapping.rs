@@ -0,0 +1,376 @@
+use smithay::backend::allocator::Fourcc;
+use smithay::backend::renderer::gles::{
+    GlesError, GlesRenderer, GlesTexProgram, GlesTexture, Uniform, UniformName, UniformType,
+    UniformValue,
+};
+use smithay::backend::renderer::{Bind, Frame, Offscreen, Renderer, Texture, Unbind};
+use smithay::utils::{Rectangle, Transform};
+use tracing::warn;
+
+use crate::state::State;
+use crate::wayland::color_management::color_management::{
+    ImageDescription, Primaries, TransferFunction,
+};
+use crate::Backend;
+
+/// Converts HDR and wide gamut client buffers to the SDR sRGB encoding Flutter composites in.
+const TONE_MAPPING_SHADER: &str = r#"
+#version 100
+//_DEFINES_
+
+#if defined(EXTERNAL)
+#extension GL_OES_EGL_image_external : require
+#endif
+
+precision highp float;
+#if defined(EXTERNAL)
+uniform samplerExternalOES tex;
+#else
+uniform sampler2D tex;
+#endif
+
+uniform float alpha;
+varying vec2 v_coords;
+
+#if defined(DEBUG_FLAGS)
+uniform float tint;
+#endif
+
+uniform float transfer_function;
+uniform mat3 to_srgb;
+uniform float reference_luminance;
+uniform float max_luminance;
+
+vec3 srgb_eotf(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(low, high, step(vec3(0.04045), color));
+}
+
+vec3 srgb_inverse_eotf(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(low, high, step(vec3(0.0031308), color));
+}
+
+// Returns absolute luminance in cd/m².
+vec3 pq_eotf(vec3 color) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 p = pow(color, vec3(1.0 / m2));
+    return 10000.0 * pow(max(p - c1, 0.0) / (c2 - c3 * p), vec3(1.0 / m1));
+}
+
+// Returns absolute luminance in cd/m², with the reference OOTF for a display peaking at max_luminance.
+vec3 hlg_eotf(vec3 color) {
+    const float a = 0.17883277;
+    const float b = 0.28466892;
+    const float c = 0.55991073;
+    vec3 low = color * color / 3.0;
+    vec3 high = (exp((color - c) / a) + b) / 12.0;
+    vec3 scene = mix(low, high, step(vec3(0.5), color));
+    float luma = dot(scene, vec3(0.2627, 0.6780, 0.0593));
+    return max_luminance * pow(max(luma, 0.0001), 0.2) * scene;
+}
+
+// Linear light relative to the reference white.
+vec3 decode(vec3 color) {
+    if (transfer_function < 0.5) {
+        return srgb_eotf(color);
+    } else if (transfer_function < 1.5) {
+        return pow(color, vec3(2.2));
+    } else if (transfer_function < 2.5) {
+        return color;
+    } else if (transfer_function < 3.5) {
+        return pq_eotf(color) / reference_luminance;
+    } else {
+        return hlg_eotf(color) / reference_luminance;
+    }
+}
+
+// Extended Reinhard on the brightest channel, so that hues are kept while highlights roll off.
+vec3 tone_map(vec3 color) {
+    float peak = max(max_luminance / reference_luminance, 1.0);
+    float value = max(max(color.r, color.g), color.b);
+    if (peak <= 1.0 || value <= 0.0) {
+        return color;
+    }
+    float mapped = value * (1.0 + value / (peak * peak)) / (1.0 + value);
+    return color * (mapped / value);
+}
+
+void main() {
+    vec4 color = texture2D(tex, v_coords);
+#if defined(NO_ALPHA)
+    color.a = 1.0;
+#endif
+    vec3 rgb = color.a > 0.0 ? color.rgb / color.a : vec3(0.0);
+
+    rgb = to_srgb * decode(rgb);
+    rgb = clamp(tone_map(max(rgb, 0.0)), 0.0, 1.0);
+    rgb = srgb_inverse_eotf(rgb);
+
+    color = vec4(rgb * color.a, color.a) * alpha;
+
+#if defined(DEBUG_FLAGS)
+    if (tint == 1.0)
+        color = vec4(0.0, 0.3, 0.0, 0.2) + color * 0.8;
+#endif
+
+    gl_FragColor = color;
+}
+"#;
+
+pub struct ToneMapper {
+    program: GlesTexProgram,
+}
+
+impl ToneMapper {
+    pub fn new(renderer: &mut GlesRenderer) -> Result<Self, GlesError> {
+        let program = renderer.compile_custom_texture_shader(
+            TONE_MAPPING_SHADER,
+            &[
+                UniformName::new("transfer_function", UniformType::_1f),
+                UniformName::new("to_srgb", UniformType::Matrix3x3),
+                UniformName::new("reference_luminance", UniformType::_1f),
+                UniformName::new("max_luminance", UniformType::_1f),
+            ],
+        )?;
+        Ok(Self { program })
+    }
+
+    /// Renders `texture` into `target`, an sRGB texture of the same size.
+    pub fn tone_map(
+        &self,
+        renderer: &mut GlesRenderer,
+        texture: &GlesTexture,
+        target: &GlesTexture,
+        description: &ImageDescription,
+    ) -> Result<(), GlesError> {
+        let size = texture.size();
+        let transfer_function: f32 = match description.transfer_function {
+            TransferFunction::Srgb => 0.0,
+            TransferFunction::Gamma22 => 1.0,
+            TransferFunction::ExtLinear => 2.0,
+            TransferFunction::St2084Pq => 3.0,
+            TransferFunction::Hlg => 4.0,
+        };
+        let uniforms = [
+            Uniform::new("transfer_function", transfer_function),
+            Uniform::new(
+                "to_srgb",
+                UniformValue::Matrix3x3 {
+                    matrices: vec![to_srgb_matrix(&description.primaries)],
+                    transpose: false,
+                },
+            ),
+            Uniform::new(
+                "reference_luminance",
+                description.luminances.reference as f32,
+            ),
+            Uniform::new("max_luminance", description.content_max_luminance() as f32),
+        ];
+
+        renderer.bind(target.clone())?;
+        let physical_size = size.to_logical(1, Transform::Normal).to_physical(1);
+        let dst = Rectangle::from_loc_and_size((0, 0), physical_size);
+        let result = renderer
+            .render(physical_size, Transform::Normal)
+            .and_then(|mut frame| {
+                frame.render_texture_from_to(
+                    texture,
+                    Rectangle::from_loc_and_size((0.0, 0.0), size.to_f64()),
+                    dst,
+                    &[dst],
+                    &[],
+                    Transform::Normal,
+                    1.0,
+                    Some(&self.program),
+                    &uniforms,
+                )?;
+                frame.finish()
+            });
+        renderer.unbind()?;
+        result?;
+
+        Ok(())
+    }
+}
+
+impl<BackendData: Backend + 'static> State<BackendData> {
+    /// Tone maps a client buffer that isn't sRGB, into a texture of the swapchain of `texture_id`.
+    /// Falls back to showing the buffer as is if the GPU can't do it.
+    pub fn tone_map(
+        &mut self,
+        texture_id: i64,
+        texture: GlesTexture,
+        description: &ImageDescription,
+    ) -> GlesTexture {
+        let renderer = self.gles_renderer.as_mut().unwrap();
+
+        if self.tone_mapper.is_none() {
+            match ToneMapper::new(renderer) {
+                Ok(tone_mapper) => self.tone_mapper = Some(tone_mapper),
+                Err(err) => {
+                    warn!("Failed to compile the tone mapping shader: {}", err);
+                    return texture;
+                }
+            }
+        }
+
+        let size = texture.size();
+        let swapchain = self.texture_swapchains.entry(texture_id).or_default();
+        let result = swapchain
+            .render_target(|| {
+                // 10-bit keeps the tone-mapped gradients smooth, when the driver can render to it.
+                renderer
+                    .create_buffer(Fourcc::Abgr2101010, size)
+                    .or_else(|_| renderer.create_buffer(Fourcc::Abgr8888, size))
+            })
+            .and_then(|target| {
+                self.tone_mapper
+                    .as_ref()
+                    .unwrap()
+                    .tone_map(renderer, &texture, &target, description)
+                    .map(|()| target)
+            });
+        result.unwrap_or_else(|err| {
+            warn!("Failed to tone map a surface: {}", err);
+            texture
+        })
+    }
+}
+
+/// Column-major matrix converting linear RGB in `primaries` to linear sRGB.
+/// There's no chromatic adaptation, all supported named primaries use D65.
+fn to_srgb_matrix(primaries: &Primaries) -> [[f32; 3]; 3] {
+    let m = multiply(
+        &invert(&rgb_to_xyz(&Primaries::SRGB)),
+        &rgb_to_xyz(primaries),
+    );
+    let mut columns = [[0.0f32; 3]; 3];
+    for (column, values) in columns.iter_mut().enumerate() {
+        for (row, value) in values.iter_mut().enumerate() {
+            *value = m[row][column] as f32;
+        }
+    }
+    columns
+}
+
+type Matrix = [[f64; 3]; 3];
+
+fn rgb_to_xyz(primaries: &Primaries) -> Matrix {
+    let xyz = |[x, y]: [f64; 2]| [x / y, 1.0, (1.0 - x - y) / y];
+    let [r, g, b] = [
+        xyz(primaries.red),
+        xyz(primaries.green),
+        xyz(primaries.blue),
+    ];
+    let white = xyz(primaries.white);
+
+    let m = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+    let inverse = invert(&m);
+    let s: Vec<f64> = (0..3)
+        .map(|row| (0..3).map(|k| inverse[row][k] * white[k]).sum())
+        .collect();
+
+    let mut result = m;
+    for row in result.iter_mut() {
+        for (column, value) in row.iter_mut().enumerate() {
+            *value *= s[column];
+        }
+    }
+    result
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.0; 3]; 3];
+    for (row, values) in result.iter_mut().enumerate() {
+        for (column, value) in values.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[row][k] * b[k][column]).sum();
+        }
+    }
+    result
+}
+
+fn invert(m: &Matrix) -> Matrix {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let determinant = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+
+    [
+        [
+            cofactor(1, 2, 1, 2) / determinant,
+            -cofactor(0, 2, 1, 2) / determinant,
+            cofactor(0, 1, 1, 2) / determinant,
+        ],
+        [
+            -cofactor(1, 2, 0, 2) / determinant,
+            cofactor(0, 2, 0, 2) / determinant,
+            -cofactor(0, 1, 0, 2) / determinant,
+        ],
+        [
+            cofactor(1, 2, 0, 1) / determinant,
+            -cofactor(0, 2, 0, 1) / determinant,
+            cofactor(0, 1, 0, 1) / determinant,
+        ],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    fn assert_close(actual: &Matrix, expected: &Matrix, tolerance: f64) {
+        for row in 0..3 {
+            for column in 0..3 {
+                assert!(
+                    (actual[row][column] - expected[row][column]).abs() < tolerance,
+                    "{:?} != {:?}",
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+
+    /// Transposes back the column-major matrix handed to the shader.
+    fn rows(columns: [[f32; 3]; 3]) -> Matrix {
+        let mut m = [[0.0; 3]; 3];
+        for (column, values) in columns.iter().enumerate() {
+            for (row, value) in values.iter().enumerate() {
+                m[row][column] = *value as f64;
+            }
+        }
+        m
+    }
+
+    #[test]
+    fn srgb_to_srgb_is_identity() {
+        assert_close(&rows(to_srgb_matrix(&Primaries::SRGB)), &IDENTITY, 1e-6);
+    }
+
+    #[test]
+    fn bt2020_to_srgb() {
+        // ITU-R BT.2087, the inverse of the BT.709 to BT.2020 conversion.
+        let expected = [
+            [1.6605, -0.5876, -0.0728],
+            [-0.1246, 1.1329, -0.0083],
+            [-0.0182, -0.1006, 1.1187],
+        ];
+        assert_close(&rows(to_srgb_matrix(&Primaries::BT2020)), &expected, 1e-3);
+    }
+
+    #[test]
+    fn invert_gives_identity() {
+        let m = rgb_to_xyz(&Primaries::DISPLAY_P3);
+        assert_close(&multiply(&invert(&m), &m), &IDENTITY, 1e-9);
+        assert_close(&multiply(&m, &invert(&m)), &IDENTITY, 1e-9);
+    }
+}
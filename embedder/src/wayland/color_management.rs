@@ -0,0 +1,928 @@
+pub mod color_management {
+    use std::cell::RefCell;
+    use std::sync::{Arc, Mutex};
+
+    use smithay::{
+        output::Output,
+        reexports::wayland_server::{
+            backend::GlobalId,
+            protocol::{wl_output::WlOutput, wl_surface::WlSurface},
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum, Weak,
+        },
+        wayland::compositor::{with_states, Cacheable},
+    };
+
+    use crate::{state::State, Backend};
+
+    use self::protocol::{
+        wp_color_management_output_v1::{self, WpColorManagementOutputV1},
+        wp_color_management_surface_feedback_v1::{self, WpColorManagementSurfaceFeedbackV1},
+        wp_color_management_surface_v1::{self, WpColorManagementSurfaceV1},
+        wp_color_manager_v1::{self, WpColorManagerV1},
+        wp_image_description_creator_icc_v1::{self, WpImageDescriptionCreatorIccV1},
+        wp_image_description_creator_params_v1::{self, WpImageDescriptionCreatorParamsV1},
+        wp_image_description_info_v1::WpImageDescriptionInfoV1,
+        wp_image_description_v1::{self, WpImageDescriptionV1},
+    };
+
+    /// wp_color_management_v1 isn't part of the wayland-protocols release Smithay depends on yet.
+    pub mod protocol {
+        use smithay::reexports::wayland_server;
+        use smithay::reexports::wayland_server::protocol::*;
+
+        pub mod __interfaces {
+            use smithay::reexports::wayland_server::protocol::__interfaces::*;
+            wayland_scanner::generate_interfaces!("protocols/color-management-v1.xml");
+        }
+        use self::__interfaces::*;
+
+        wayland_scanner::generate_server_code!("protocols/color-management-v1.xml");
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum TransferFunction {
+        Srgb,
+        Gamma22,
+        ExtLinear,
+        St2084Pq,
+        Hlg,
+    }
+
+    impl TransferFunction {
+        fn from_protocol(tf: wp_color_manager_v1::TransferFunction) -> Option<Self> {
+            match tf {
+                wp_color_manager_v1::TransferFunction::Srgb => Some(Self::Srgb),
+                wp_color_manager_v1::TransferFunction::Gamma22 => Some(Self::Gamma22),
+                wp_color_manager_v1::TransferFunction::ExtLinear => Some(Self::ExtLinear),
+                wp_color_manager_v1::TransferFunction::St2084Pq => Some(Self::St2084Pq),
+                wp_color_manager_v1::TransferFunction::Hlg => Some(Self::Hlg),
+                _ => None,
+            }
+        }
+
+        fn to_protocol(self) -> wp_color_manager_v1::TransferFunction {
+            match self {
+                Self::Srgb => wp_color_manager_v1::TransferFunction::Srgb,
+                Self::Gamma22 => wp_color_manager_v1::TransferFunction::Gamma22,
+                Self::ExtLinear => wp_color_manager_v1::TransferFunction::ExtLinear,
+                Self::St2084Pq => wp_color_manager_v1::TransferFunction::St2084Pq,
+                Self::Hlg => wp_color_manager_v1::TransferFunction::Hlg,
+            }
+        }
+
+        /// Default luminances of a transfer function, as defined by the protocol.
+        fn default_luminances(self) -> Luminances {
+            match self {
+                Self::St2084Pq => Luminances {
+                    min: 0.005,
+                    max: 10000.0,
+                    reference: 203.0,
+                },
+                Self::Hlg => Luminances {
+                    min: 0.005,
+                    max: 1000.0,
+                    reference: 203.0,
+                },
+                Self::Srgb | Self::Gamma22 | Self::ExtLinear => Luminances {
+                    min: 0.2,
+                    max: 80.0,
+                    reference: 80.0,
+                },
+            }
+        }
+    }
+
+    /// CIE 1931 xy chromaticities of the red, green and blue primaries and of the white point.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Primaries {
+        pub red: [f64; 2],
+        pub green: [f64; 2],
+        pub blue: [f64; 2],
+        pub white: [f64; 2],
+    }
+
+    const D65: [f64; 2] = [0.3127, 0.3290];
+
+    impl Primaries {
+        pub const SRGB: Self = Self {
+            red: [0.64, 0.33],
+            green: [0.30, 0.60],
+            blue: [0.15, 0.06],
+            white: D65,
+        };
+        pub const BT2020: Self = Self {
+            red: [0.708, 0.292],
+            green: [0.170, 0.797],
+            blue: [0.131, 0.046],
+            white: D65,
+        };
+        pub const DISPLAY_P3: Self = Self {
+            red: [0.680, 0.320],
+            green: [0.265, 0.690],
+            blue: [0.150, 0.060],
+            white: D65,
+        };
+
+        fn from_protocol(primaries: wp_color_manager_v1::Primaries) -> Option<Self> {
+            match primaries {
+                wp_color_manager_v1::Primaries::Srgb => Some(Self::SRGB),
+                wp_color_manager_v1::Primaries::Bt2020 => Some(Self::BT2020),
+                wp_color_manager_v1::Primaries::DisplayP3 => Some(Self::DISPLAY_P3),
+                _ => None,
+            }
+        }
+
+        fn to_protocol(self) -> Option<wp_color_manager_v1::Primaries> {
+            [
+                wp_color_manager_v1::Primaries::Srgb,
+                wp_color_manager_v1::Primaries::Bt2020,
+                wp_color_manager_v1::Primaries::DisplayP3,
+            ]
+            .into_iter()
+            .find(|&named| Self::from_protocol(named) == Some(self))
+        }
+
+        /// Coordinates are multiplied by 1 million on the wire.
+        #[allow(clippy::too_many_arguments)]
+        fn from_wire(
+            r_x: i32,
+            r_y: i32,
+            g_x: i32,
+            g_y: i32,
+            b_x: i32,
+            b_y: i32,
+            w_x: i32,
+            w_y: i32,
+        ) -> Self {
+            let c = |x: i32, y: i32| [x as f64 / 1_000_000.0, y as f64 / 1_000_000.0];
+            Self {
+                red: c(r_x, r_y),
+                green: c(g_x, g_y),
+                blue: c(b_x, b_y),
+                white: c(w_x, w_y),
+            }
+        }
+
+        fn to_wire(self) -> [i32; 8] {
+            let c = |v: f64| (v * 1_000_000.0).round() as i32;
+            [
+                c(self.red[0]),
+                c(self.red[1]),
+                c(self.green[0]),
+                c(self.green[1]),
+                c(self.blue[0]),
+                c(self.blue[1]),
+                c(self.white[0]),
+                c(self.white[1]),
+            ]
+        }
+    }
+
+    /// Luminances in cd/m².
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Luminances {
+        pub min: f64,
+        pub max: f64,
+        pub reference: f64,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ImageDescription {
+        pub identity: u32,
+        pub transfer_function: TransferFunction,
+        pub primaries: Primaries,
+        pub luminances: Luminances,
+        pub mastering_primaries: Option<Primaries>,
+        pub mastering_luminance: Option<(f64, f64)>,
+        pub max_cll: Option<u32>,
+        pub max_fall: Option<u32>,
+    }
+
+    impl ImageDescription {
+        /// Content in this encoding can be handed to Flutter as is.
+        pub fn is_srgb(&self) -> bool {
+            matches!(
+                self.transfer_function,
+                TransferFunction::Srgb | TransferFunction::Gamma22
+            ) && self.primaries == Primaries::SRGB
+        }
+
+        /// Peak luminance of the content, the mastering display limits it when known.
+        pub fn content_max_luminance(&self) -> f64 {
+            self.max_cll
+                .map(|max_cll| max_cll as f64)
+                .or(self.mastering_luminance.map(|(_, max)| max))
+                .unwrap_or(self.luminances.max)
+                .min(self.luminances.max)
+        }
+
+        fn send_information(&self, info: &WpImageDescriptionInfoV1) {
+            let [r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y] = self.primaries.to_wire();
+            info.primaries(r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y);
+            if let Some(named) = self.primaries.to_protocol() {
+                info.primaries_named(named);
+            }
+            info.tf_named(self.transfer_function.to_protocol());
+            info.luminances(
+                (self.luminances.min * 10000.0).round() as u32,
+                self.luminances.max.round() as u32,
+                self.luminances.reference.round() as u32,
+            );
+
+            let target_primaries = self.mastering_primaries.unwrap_or(self.primaries);
+            let [r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y] = target_primaries.to_wire();
+            info.target_primaries(r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y);
+            let (target_min, target_max) = self
+                .mastering_luminance
+                .unwrap_or((self.luminances.min, self.luminances.max));
+            info.target_luminance(
+                (target_min * 10000.0).round() as u32,
+                target_max.round() as u32,
+            );
+            if let Some(max_cll) = self.max_cll {
+                info.target_max_cll(max_cll);
+            }
+            if let Some(max_fall) = self.max_fall {
+                info.target_max_fall(max_fall);
+            }
+            info.done();
+        }
+    }
+
+    /// What the monitor behind an output accepts, read from its EDID.
+    /// Attached to the output's user data by the backend.
+    #[derive(Debug, Clone, Default)]
+    pub struct OutputColorCapabilities {
+        pub hdr_static_metadata: Option<smithay_drm_extras::edid::HdrStaticMetadata>,
+        pub colorimetry: smithay_drm_extras::edid::Colorimetry,
+    }
+
+    impl OutputColorCapabilities {
+        pub fn hdr_capable(&self) -> bool {
+            self.hdr_static_metadata
+                .is_some_and(|metadata| metadata.eotf_pq)
+        }
+    }
+
+    pub fn output_hdr_capable(output: &Output) -> bool {
+        output
+            .user_data()
+            .get::<OutputColorCapabilities>()
+            .is_some_and(|capabilities| capabilities.hdr_capable())
+    }
+
+    /// Double-buffered image description of a surface, applied on commit.
+    #[derive(Debug, Default, Clone)]
+    pub struct ColorManagementCachedState {
+        pub image_description: Option<Arc<ImageDescription>>,
+    }
+
+    impl Cacheable for ColorManagementCachedState {
+        fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+            self.clone()
+        }
+
+        fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+            *into = self;
+        }
+    }
+
+    #[derive(Default)]
+    struct ColorManagementSurfaceData {
+        is_bound: bool,
+    }
+
+    /// The committed image description of the surface, `None` when the content is sRGB.
+    pub fn surface_image_description(surface: &WlSurface) -> Option<Arc<ImageDescription>> {
+        with_states(surface, |surface_data| {
+            surface_data
+                .cached_state
+                .get::<ColorManagementCachedState>()
+                .current()
+                .image_description
+                .clone()
+        })
+        .filter(|description| !description.is_srgb())
+    }
+
+    pub struct ColorManagementState {
+        global: GlobalId,
+        next_identity: u32,
+        /// Flutter composites in SDR, so every output is described as sRGB
+        /// and that's what clients are asked to prefer.
+        output_description: Arc<ImageDescription>,
+    }
+
+    impl ColorManagementState {
+        pub fn new<BackendData: Backend + 'static>(display_handle: &DisplayHandle) -> Self {
+            let global =
+                display_handle.create_global::<State<BackendData>, WpColorManagerV1, _>(1, ());
+            Self {
+                global,
+                next_identity: 2,
+                output_description: Arc::new(ImageDescription {
+                    identity: 1,
+                    transfer_function: TransferFunction::Gamma22,
+                    primaries: Primaries::SRGB,
+                    luminances: TransferFunction::Gamma22.default_luminances(),
+                    mastering_primaries: None,
+                    mastering_luminance: None,
+                    max_cll: None,
+                    max_fall: None,
+                }),
+            }
+        }
+
+        pub fn global(&self) -> GlobalId {
+            self.global.clone()
+        }
+
+        fn new_identity(&mut self) -> u32 {
+            let identity = self.next_identity;
+            self.next_identity = self.next_identity.wrapping_add(1).max(2);
+            identity
+        }
+    }
+
+    const SUPPORTED_TRANSFER_FUNCTIONS: &[wp_color_manager_v1::TransferFunction] = &[
+        wp_color_manager_v1::TransferFunction::Srgb,
+        wp_color_manager_v1::TransferFunction::Gamma22,
+        wp_color_manager_v1::TransferFunction::ExtLinear,
+        wp_color_manager_v1::TransferFunction::St2084Pq,
+        wp_color_manager_v1::TransferFunction::Hlg,
+    ];
+
+    const SUPPORTED_PRIMARIES: &[wp_color_manager_v1::Primaries] = &[
+        wp_color_manager_v1::Primaries::Srgb,
+        wp_color_manager_v1::Primaries::Bt2020,
+        wp_color_manager_v1::Primaries::DisplayP3,
+    ];
+
+    const SUPPORTED_FEATURES: &[wp_color_manager_v1::Feature] = &[
+        wp_color_manager_v1::Feature::Parametric,
+        wp_color_manager_v1::Feature::SetPrimaries,
+        wp_color_manager_v1::Feature::SetLuminances,
+        wp_color_manager_v1::Feature::SetMasteringDisplayPrimaries,
+    ];
+
+    /// `None` once creating the description failed.
+    pub struct ImageDescriptionData {
+        description: Option<Arc<ImageDescription>>,
+        /// Only descriptions made by the compositor can be inspected with get_information.
+        allows_information: bool,
+    }
+
+    #[derive(Default)]
+    pub struct ImageDescriptionParams {
+        transfer_function: Option<TransferFunction>,
+        primaries: Option<Primaries>,
+        luminances: Option<Luminances>,
+        mastering_primaries: Option<Primaries>,
+        mastering_luminance: Option<(f64, f64)>,
+        max_cll: Option<u32>,
+        max_fall: Option<u32>,
+    }
+
+    impl<BackendData: Backend + 'static> GlobalDispatch<WpColorManagerV1, ()> for State<BackendData> {
+        fn bind(
+            _state: &mut Self,
+            _handle: &DisplayHandle,
+            _client: &Client,
+            resource: New<WpColorManagerV1>,
+            _global_data: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            let manager = data_init.init(resource, ());
+            manager.supported_intent(wp_color_manager_v1::RenderIntent::Perceptual);
+            for &feature in SUPPORTED_FEATURES {
+                manager.supported_feature(feature);
+            }
+            for &tf in SUPPORTED_TRANSFER_FUNCTIONS {
+                manager.supported_tf_named(tf);
+            }
+            for &primaries in SUPPORTED_PRIMARIES {
+                manager.supported_primaries_named(primaries);
+            }
+            manager.done();
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<WpColorManagerV1, ()> for State<BackendData> {
+        fn request(
+            _state: &mut Self,
+            _client: &Client,
+            manager: &WpColorManagerV1,
+            request: wp_color_manager_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                wp_color_manager_v1::Request::GetOutput { id, output } => {
+                    data_init.init(id, output.downgrade());
+                }
+                wp_color_manager_v1::Request::GetSurface { id, surface } => {
+                    let already_bound = with_states(&surface, |surface_data| {
+                        surface_data.data_map.insert_if_missing(|| {
+                            RefCell::new(ColorManagementSurfaceData::default())
+                        });
+                        let mut color_management_data = surface_data
+                            .data_map
+                            .get::<RefCell<ColorManagementSurfaceData>>()
+                            .unwrap()
+                            .borrow_mut();
+                        std::mem::replace(&mut color_management_data.is_bound, true)
+                    });
+
+                    if already_bound {
+                        manager.post_error(
+                            wp_color_manager_v1::Error::SurfaceExists,
+                            "The surface already has a color management surface object",
+                        );
+                        return;
+                    }
+
+                    data_init.init(id, surface.downgrade());
+                }
+                wp_color_manager_v1::Request::GetSurfaceFeedback { id, surface } => {
+                    data_init.init(id, surface.downgrade());
+                }
+                wp_color_manager_v1::Request::CreateIccCreator { obj } => {
+                    data_init.init(obj, ());
+                    manager.post_error(
+                        wp_color_manager_v1::Error::UnsupportedFeature,
+                        "ICC profiles are not supported",
+                    );
+                }
+                wp_color_manager_v1::Request::CreateParametricCreator { obj } => {
+                    data_init.init(obj, Mutex::new(ImageDescriptionParams::default()));
+                }
+                wp_color_manager_v1::Request::CreateWindowsScrgb { image_description } => {
+                    data_init.init(
+                        image_description,
+                        ImageDescriptionData {
+                            description: None,
+                            allows_information: false,
+                        },
+                    );
+                    manager.post_error(
+                        wp_color_manager_v1::Error::UnsupportedFeature,
+                        "Windows scRGB is not supported",
+                    );
+                }
+                wp_color_manager_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<WpColorManagementOutputV1, Weak<WlOutput>>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            _resource: &WpColorManagementOutputV1,
+            request: wp_color_management_output_v1::Request,
+            output: &Weak<WlOutput>,
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                wp_color_management_output_v1::Request::GetImageDescription {
+                    image_description,
+                } => {
+                    let output_exists = output
+                        .upgrade()
+                        .ok()
+                        .and_then(|output| Output::from_resource(&output))
+                        .is_some();
+                    if output_exists {
+                        let description = state.color_management_state.output_description.clone();
+                        let image_description = data_init.init(
+                            image_description,
+                            ImageDescriptionData {
+                                description: Some(description.clone()),
+                                allows_information: true,
+                            },
+                        );
+                        image_description.ready(description.identity);
+                    } else {
+                        let image_description = data_init.init(
+                            image_description,
+                            ImageDescriptionData {
+                                description: None,
+                                allows_information: false,
+                            },
+                        );
+                        image_description.failed(
+                            wp_image_description_v1::Cause::NoOutput,
+                            "The output no longer exists".into(),
+                        );
+                    }
+                }
+                wp_color_management_output_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<WpColorManagementSurfaceV1, Weak<WlSurface>>
+        for State<BackendData>
+    {
+        fn request(
+            _state: &mut Self,
+            _client: &Client,
+            resource: &WpColorManagementSurfaceV1,
+            request: wp_color_management_surface_v1::Request,
+            surface: &Weak<WlSurface>,
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            let surface = surface.upgrade().ok();
+
+            match request {
+                wp_color_management_surface_v1::Request::SetImageDescription {
+                    image_description,
+                    render_intent,
+                } => {
+                    let Some(surface) = surface else {
+                        resource.post_error(
+                            wp_color_management_surface_v1::Error::Inert,
+                            "The surface was destroyed",
+                        );
+                        return;
+                    };
+                    if render_intent != WEnum::Value(wp_color_manager_v1::RenderIntent::Perceptual)
+                    {
+                        resource.post_error(
+                            wp_color_management_surface_v1::Error::RenderIntent,
+                            "Only the perceptual rendering intent is supported",
+                        );
+                        return;
+                    }
+                    let Some(description) = image_description
+                        .data::<ImageDescriptionData>()
+                        .and_then(|data| data.description.clone())
+                    else {
+                        resource.post_error(
+                            wp_color_management_surface_v1::Error::ImageDescription,
+                            "The image description is not ready",
+                        );
+                        return;
+                    };
+                    with_states(&surface, |surface_data| {
+                        surface_data
+                            .cached_state
+                            .get::<ColorManagementCachedState>()
+                            .pending()
+                            .image_description = Some(description);
+                    });
+                }
+                wp_color_management_surface_v1::Request::UnsetImageDescription => {
+                    let Some(surface) = surface else {
+                        resource.post_error(
+                            wp_color_management_surface_v1::Error::Inert,
+                            "The surface was destroyed",
+                        );
+                        return;
+                    };
+                    with_states(&surface, |surface_data| {
+                        surface_data
+                            .cached_state
+                            .get::<ColorManagementCachedState>()
+                            .pending()
+                            .image_description = None;
+                    });
+                }
+                wp_color_management_surface_v1::Request::Destroy => {
+                    let Some(surface) = surface else {
+                        return;
+                    };
+                    // Destroying the object reverts the surface to sRGB.
+                    with_states(&surface, |surface_data| {
+                        surface_data
+                            .cached_state
+                            .get::<ColorManagementCachedState>()
+                            .pending()
+                            .image_description = None;
+                        if let Some(color_management_data) = surface_data
+                            .data_map
+                            .get::<RefCell<ColorManagementSurfaceData>>()
+                        {
+                            color_management_data.borrow_mut().is_bound = false;
+                        }
+                    });
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static>
+        Dispatch<WpColorManagementSurfaceFeedbackV1, Weak<WlSurface>> for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            resource: &WpColorManagementSurfaceFeedbackV1,
+            request: wp_color_management_surface_feedback_v1::Request,
+            surface: &Weak<WlSurface>,
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                wp_color_management_surface_feedback_v1::Request::GetPreferred {
+                    image_description,
+                }
+                | wp_color_management_surface_feedback_v1::Request::GetPreferredParametric {
+                    image_description,
+                } => {
+                    if surface.upgrade().is_err() {
+                        data_init.init(
+                            image_description,
+                            ImageDescriptionData {
+                                description: None,
+                                allows_information: false,
+                            },
+                        );
+                        resource.post_error(
+                            wp_color_management_surface_feedback_v1::Error::Inert,
+                            "The surface was destroyed",
+                        );
+                        return;
+                    }
+                    // All outputs share the same description, so the preference never changes
+                    // when a surface moves between monitors.
+                    let description = state.color_management_state.output_description.clone();
+                    let image_description = data_init.init(
+                        image_description,
+                        ImageDescriptionData {
+                            description: Some(description.clone()),
+                            allows_information: true,
+                        },
+                    );
+                    image_description.ready(description.identity);
+                }
+                wp_color_management_surface_feedback_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<WpImageDescriptionCreatorIccV1, ()>
+        for State<BackendData>
+    {
+        fn request(
+            _state: &mut Self,
+            _client: &Client,
+            _resource: &WpImageDescriptionCreatorIccV1,
+            request: wp_image_description_creator_icc_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            // The client is disconnected as soon as it creates this object.
+            if let wp_image_description_creator_icc_v1::Request::Create { image_description } =
+                request
+            {
+                data_init.init(
+                    image_description,
+                    ImageDescriptionData {
+                        description: None,
+                        allows_information: false,
+                    },
+                );
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static>
+        Dispatch<WpImageDescriptionCreatorParamsV1, Mutex<ImageDescriptionParams>>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            resource: &WpImageDescriptionCreatorParamsV1,
+            request: wp_image_description_creator_params_v1::Request,
+            params: &Mutex<ImageDescriptionParams>,
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            use wp_image_description_creator_params_v1::Error;
+
+            let mut params = params.lock().unwrap();
+            let already_set = |resource: &WpImageDescriptionCreatorParamsV1| {
+                resource.post_error(Error::AlreadySet, "The property is already set");
+            };
+
+            match request {
+                wp_image_description_creator_params_v1::Request::Create { image_description } => {
+                    let (Some(transfer_function), Some(primaries)) =
+                        (params.transfer_function, params.primaries)
+                    else {
+                        data_init.init(
+                            image_description,
+                            ImageDescriptionData {
+                                description: None,
+                                allows_information: false,
+                            },
+                        );
+                        resource.post_error(
+                            Error::IncompleteSet,
+                            "The transfer function and primaries are required",
+                        );
+                        return;
+                    };
+
+                    let description = Arc::new(ImageDescription {
+                        identity: state.color_management_state.new_identity(),
+                        transfer_function,
+                        primaries,
+                        luminances: params
+                            .luminances
+                            .unwrap_or_else(|| transfer_function.default_luminances()),
+                        mastering_primaries: params.mastering_primaries,
+                        mastering_luminance: params.mastering_luminance,
+                        max_cll: params.max_cll,
+                        max_fall: params.max_fall,
+                    });
+                    let image_description = data_init.init(
+                        image_description,
+                        ImageDescriptionData {
+                            description: Some(description.clone()),
+                            allows_information: false,
+                        },
+                    );
+                    image_description.ready(description.identity);
+                }
+                wp_image_description_creator_params_v1::Request::SetTfNamed { tf } => {
+                    if params.transfer_function.is_some() {
+                        return already_set(resource);
+                    }
+                    let Some(tf) = tf
+                        .into_result()
+                        .ok()
+                        .and_then(TransferFunction::from_protocol)
+                    else {
+                        resource.post_error(Error::InvalidTf, "Unsupported transfer function");
+                        return;
+                    };
+                    params.transfer_function = Some(tf);
+                }
+                wp_image_description_creator_params_v1::Request::SetTfPower { .. } => {
+                    resource.post_error(
+                        Error::UnsupportedFeature,
+                        "Power curve transfer functions are not supported",
+                    );
+                }
+                wp_image_description_creator_params_v1::Request::SetPrimariesNamed {
+                    primaries,
+                } => {
+                    if params.primaries.is_some() {
+                        return already_set(resource);
+                    }
+                    let Some(primaries) = primaries
+                        .into_result()
+                        .ok()
+                        .and_then(Primaries::from_protocol)
+                    else {
+                        resource.post_error(Error::InvalidPrimariesNamed, "Unsupported primaries");
+                        return;
+                    };
+                    params.primaries = Some(primaries);
+                }
+                wp_image_description_creator_params_v1::Request::SetPrimaries {
+                    r_x,
+                    r_y,
+                    g_x,
+                    g_y,
+                    b_x,
+                    b_y,
+                    w_x,
+                    w_y,
+                } => {
+                    if params.primaries.is_some() {
+                        return already_set(resource);
+                    }
+                    params.primaries =
+                        Some(Primaries::from_wire(r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y));
+                }
+                wp_image_description_creator_params_v1::Request::SetLuminances {
+                    min_lum,
+                    max_lum,
+                    reference_lum,
+                } => {
+                    if params.luminances.is_some() {
+                        return already_set(resource);
+                    }
+                    let luminances = Luminances {
+                        min: min_lum as f64 / 10000.0,
+                        max: max_lum as f64,
+                        reference: reference_lum as f64,
+                    };
+                    if luminances.max <= luminances.min || luminances.reference <= luminances.min {
+                        resource.post_error(Error::InvalidLuminance, "Invalid luminance range");
+                        return;
+                    }
+                    params.luminances = Some(luminances);
+                }
+                wp_image_description_creator_params_v1::Request::SetMasteringDisplayPrimaries {
+                    r_x,
+                    r_y,
+                    g_x,
+                    g_y,
+                    b_x,
+                    b_y,
+                    w_x,
+                    w_y,
+                } => {
+                    if params.mastering_primaries.is_some() {
+                        return already_set(resource);
+                    }
+                    params.mastering_primaries =
+                        Some(Primaries::from_wire(r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y));
+                }
+                wp_image_description_creator_params_v1::Request::SetMasteringLuminance {
+                    min_lum,
+                    max_lum,
+                } => {
+                    if params.mastering_luminance.is_some() {
+                        return already_set(resource);
+                    }
+                    let (min, max) = (min_lum as f64 / 10000.0, max_lum as f64);
+                    if max <= min {
+                        resource.post_error(Error::InvalidLuminance, "Invalid mastering luminance");
+                        return;
+                    }
+                    params.mastering_luminance = Some((min, max));
+                }
+                wp_image_description_creator_params_v1::Request::SetMaxCll { max_cll } => {
+                    if params.max_cll.is_some() {
+                        return already_set(resource);
+                    }
+                    params.max_cll = Some(max_cll);
+                }
+                wp_image_description_creator_params_v1::Request::SetMaxFall { max_fall } => {
+                    if params.max_fall.is_some() {
+                        return already_set(resource);
+                    }
+                    params.max_fall = Some(max_fall);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<WpImageDescriptionV1, ImageDescriptionData>
+        for State<BackendData>
+    {
+        fn request(
+            _state: &mut Self,
+            _client: &Client,
+            resource: &WpImageDescriptionV1,
+            request: wp_image_description_v1::Request,
+            data: &ImageDescriptionData,
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                wp_image_description_v1::Request::GetInformation { information } => {
+                    let info = data_init.init(information, ());
+                    let Some(ref description) = data.description else {
+                        resource.post_error(
+                            wp_image_description_v1::Error::NotReady,
+                            "The image description is not ready",
+                        );
+                        return;
+                    };
+                    if !data.allows_information {
+                        resource.post_error(
+                            wp_image_description_v1::Error::NoInformation,
+                            "Client-made image descriptions can't be inspected",
+                        );
+                        return;
+                    }
+                    description.send_information(&info);
+                }
+                wp_image_description_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<WpImageDescriptionInfoV1, ()> for State<BackendData> {
+        fn request(
+            _state: &mut Self,
+            _client: &Client,
+            _resource: &WpImageDescriptionInfoV1,
+            _request: <WpImageDescriptionInfoV1 as Resource>::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            // The info object has no requests.
+        }
+    }
+}
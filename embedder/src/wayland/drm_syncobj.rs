@@ -0,0 +1,140 @@
+pub mod drm_syncobj {
+    use std::os::fd::OwnedFd;
+
+    use smithay::{
+        delegate_drm_syncobj,
+        reexports::wayland_server::{protocol::wl_surface::WlSurface, Resource},
+        wayland::{
+            compositor::{add_blocker, add_pre_commit_hook, with_states, SurfaceData},
+            drm_syncobj::{
+                DrmSyncPoint, DrmSyncobjCachedState, DrmSyncobjHandler, DrmSyncobjState,
+            },
+        },
+    };
+    use tracing::warn;
+
+    use crate::{state::State, Backend};
+
+    /// The sync points of the commit being applied.
+    /// The acquire point is exported as a sync file that Flutter waits for before sampling the buffer.
+    pub fn take_explicit_sync_points(
+        surface_data: &SurfaceData,
+    ) -> (Option<OwnedFd>, Option<DrmSyncPoint>) {
+        let mut binding = surface_data.cached_state.get::<DrmSyncobjCachedState>();
+        let sync_state = binding.current();
+        let acquire_fence = sync_state.acquire_point.take().and_then(|acquire_point| {
+            acquire_point
+                .export_sync_file()
+                .map_err(|err| warn!("Failed to export the acquire point: {}", err))
+                .ok()
+        });
+        (acquire_fence, sync_state.release_point.take())
+    }
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// Clients may commit before submitting the work of the acquire point.
+        /// The commit is held back until the point has a fence that can be exported.
+        pub fn add_acquire_point_hook(&mut self, surface: &WlSurface) {
+            if self.drm_syncobj_state.is_none() {
+                return;
+            }
+            add_pre_commit_hook::<Self, _>(surface, |state, _display_handle, surface| {
+                let acquire_point = with_states(surface, |surface_data| {
+                    surface_data
+                        .cached_state
+                        .get::<DrmSyncobjCachedState>()
+                        .pending()
+                        .acquire_point
+                        .clone()
+                });
+                let Some(acquire_point) = acquire_point else {
+                    return;
+                };
+                if acquire_point.export_sync_file().is_ok() {
+                    return;
+                }
+                let Ok((blocker, source)) = acquire_point.generate_blocker() else {
+                    return;
+                };
+                let Some(client) = surface.client() else {
+                    return;
+                };
+                let result = state.loop_handle.insert_source(source, move |_, _, data| {
+                    let display_handle = data.display_handle.clone();
+                    data.client_compositor_state(&client)
+                        .blocker_cleared(data, &display_handle);
+                    Ok(())
+                });
+                if result.is_ok() {
+                    add_blocker(surface, blocker);
+                }
+            });
+        }
+
+        /// The previous buffer of the surface is released once Flutter is done with the frame
+        /// that may still be sampling it.
+        pub fn set_surface_release_point(
+            &mut self,
+            surface_id: u64,
+            release_point: Option<DrmSyncPoint>,
+        ) {
+            let previous = match release_point {
+                Some(release_point) => self
+                    .release_point_per_surface_id
+                    .insert(surface_id, release_point),
+                None => self.release_point_per_surface_id.remove(&surface_id),
+            };
+            self.pending_release_points.extend(previous);
+        }
+
+        /// Called once Flutter presented a frame, the buffers replaced before it aren't sampled anymore.
+        /// Buffers still on a plane are released by the page flip that replaces them.
+        pub fn signal_release_points(&mut self) {
+            for release_point in std::mem::take(&mut self.pending_release_points) {
+                if self.release_points_on_planes.contains(&release_point) {
+                    continue;
+                }
+                signal_release_point(release_point);
+            }
+        }
+
+        /// A plane scans out the buffer of `release_point` from the next page flip.
+        pub fn plane_acquired_release_point(&mut self, release_point: DrmSyncPoint) {
+            self.release_points_on_planes.push(release_point);
+        }
+
+        /// A page flip took the buffer of `release_point` off a plane.
+        /// It's released unless the surface still shows it, or Flutter may still sample it.
+        pub fn plane_released_release_point(&mut self, release_point: DrmSyncPoint) {
+            if let Some(index) = self
+                .release_points_on_planes
+                .iter()
+                .position(|other| *other == release_point)
+            {
+                self.release_points_on_planes.swap_remove(index);
+            }
+            let is_used = self.release_points_on_planes.contains(&release_point)
+                || self.pending_release_points.contains(&release_point)
+                || self
+                    .release_point_per_surface_id
+                    .values()
+                    .any(|other| *other == release_point);
+            if !is_used {
+                signal_release_point(release_point);
+            }
+        }
+    }
+
+    fn signal_release_point(release_point: DrmSyncPoint) {
+        if let Err(err) = release_point.signal() {
+            warn!("Failed to signal the release point: {}", err);
+        }
+    }
+
+    impl<BackendData: Backend + 'static> DrmSyncobjHandler for State<BackendData> {
+        fn drm_syncobj_state(&mut self) -> &mut DrmSyncobjState {
+            self.drm_syncobj_state.as_mut().unwrap()
+        }
+    }
+    delegate_drm_syncobj!(@<BackendData: Backend + 'static> State<BackendData>);
+}
@@ -0,0 +1,640 @@
+pub mod foreign_toplevel {
+    use serde_json::json;
+    use smithay::{
+        output::Output,
+        reexports::{
+            wayland_protocols::{
+                ext::foreign_toplevel_list::v1::server::{
+                    ext_foreign_toplevel_handle_v1::{self, ExtForeignToplevelHandleV1},
+                    ext_foreign_toplevel_list_v1::{self, ExtForeignToplevelListV1},
+                },
+                xdg::shell::server::xdg_toplevel,
+            },
+            wayland_protocols_wlr::foreign_toplevel::v1::server::{
+                zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+                zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+            },
+            wayland_server::{
+                backend::{ClientId, GlobalId},
+                Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+            },
+        },
+        wayland::{compositor::with_states, shell::xdg::XdgToplevelSurfaceData},
+    };
+
+    use crate::{
+        state::State,
+        wayland::{
+            security_context::security_context::{can_view_privileged_global, PrivilegedProtocol},
+            wayland::get_surface_id,
+        },
+        Backend,
+    };
+
+    /// A window as taskbars and window switchers see it.
+    /// X11 surfaces have ids of their own, which don't overlap with the Wayland surface ids.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ToplevelId {
+        Xdg(u64),
+        X11(u64),
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct ToplevelInfo {
+        title: Option<String>,
+        app_id: Option<String>,
+        activated: bool,
+        maximized: bool,
+        minimized: bool,
+        fullscreen: bool,
+        output: Option<Output>,
+    }
+
+    struct ForeignToplevel {
+        id: ToplevelId,
+        /// Identifies the window for ext-foreign-toplevel-list, never reused.
+        identifier: String,
+        info: ToplevelInfo,
+        /// Only the shell knows whether a window is minimized.
+        minimized: bool,
+        wlr_handles: Vec<ZwlrForeignToplevelHandleV1>,
+        ext_handles: Vec<ExtForeignToplevelHandleV1>,
+    }
+
+    pub struct ForeignToplevelManagerState {
+        wlr_global: GlobalId,
+        ext_global: GlobalId,
+        wlr_managers: Vec<ZwlrForeignToplevelManagerV1>,
+        ext_lists: Vec<ExtForeignToplevelListV1>,
+        toplevels: Vec<ForeignToplevel>,
+        next_identifier: u64,
+    }
+
+    impl ForeignToplevelManagerState {
+        pub fn new<BackendData: Backend + 'static>(display_handle: &DisplayHandle) -> Self {
+            let wlr_global = display_handle
+                .create_global::<State<BackendData>, ZwlrForeignToplevelManagerV1, _>(3, ());
+            let ext_global = display_handle
+                .create_global::<State<BackendData>, ExtForeignToplevelListV1, _>(1, ());
+            Self {
+                wlr_global,
+                ext_global,
+                wlr_managers: Vec::new(),
+                ext_lists: Vec::new(),
+                toplevels: Vec::new(),
+                next_identifier: 1,
+            }
+        }
+
+        pub fn wlr_global(&self) -> GlobalId {
+            self.wlr_global.clone()
+        }
+
+        pub fn ext_global(&self) -> GlobalId {
+            self.ext_global.clone()
+        }
+
+        fn toplevel_mut(&mut self, id: ToplevelId) -> Option<&mut ForeignToplevel> {
+            self.toplevels.iter_mut().find(|toplevel| toplevel.id == id)
+        }
+    }
+
+    impl ForeignToplevel {
+        fn announce_wlr<BackendData: Backend + 'static>(
+            &mut self,
+            display_handle: &DisplayHandle,
+            manager: &ZwlrForeignToplevelManagerV1,
+        ) {
+            let Some(client) = manager.client() else {
+                return;
+            };
+            let Ok(handle) = client
+                .create_resource::<ZwlrForeignToplevelHandleV1, _, State<BackendData>>(
+                    display_handle,
+                    manager.version(),
+                    self.id,
+                )
+            else {
+                return;
+            };
+            manager.toplevel(&handle);
+            send_wlr_info(&handle, &self.info, None);
+            self.wlr_handles.push(handle);
+        }
+
+        fn announce_ext<BackendData: Backend + 'static>(
+            &mut self,
+            display_handle: &DisplayHandle,
+            list: &ExtForeignToplevelListV1,
+        ) {
+            let Some(client) = list.client() else {
+                return;
+            };
+            let Ok(handle) = client
+                .create_resource::<ExtForeignToplevelHandleV1, _, State<BackendData>>(
+                    display_handle,
+                    list.version(),
+                    self.id,
+                )
+            else {
+                return;
+            };
+            list.toplevel(&handle);
+            handle.identifier(self.identifier.clone());
+            send_ext_info(&handle, &self.info, None);
+            self.ext_handles.push(handle);
+        }
+    }
+
+    /// Sends what changed since `previous`, or everything for a new handle.
+    fn send_wlr_info(
+        handle: &ZwlrForeignToplevelHandleV1,
+        info: &ToplevelInfo,
+        previous: Option<&ToplevelInfo>,
+    ) {
+        if let Some(ref title) = info.title {
+            if previous.map(|previous| &previous.title) != Some(&info.title) {
+                handle.title(title.clone());
+            }
+        }
+        if let Some(ref app_id) = info.app_id {
+            if previous.map(|previous| &previous.app_id) != Some(&info.app_id) {
+                handle.app_id(app_id.clone());
+            }
+        }
+        if previous.map(|previous| &previous.output) != Some(&info.output) {
+            if let Some(output) = previous.and_then(|previous| previous.output.as_ref()) {
+                send_wlr_output(handle, output, false);
+            }
+            if let Some(ref output) = info.output {
+                send_wlr_output(handle, output, true);
+            }
+        }
+        let state_changed = previous.map_or(true, |previous| {
+            (
+                previous.activated,
+                previous.maximized,
+                previous.minimized,
+                previous.fullscreen,
+            ) != (
+                info.activated,
+                info.maximized,
+                info.minimized,
+                info.fullscreen,
+            )
+        });
+        if state_changed {
+            let mut states = Vec::new();
+            if info.activated {
+                states.push(zwlr_foreign_toplevel_handle_v1::State::Activated);
+            }
+            if info.maximized {
+                states.push(zwlr_foreign_toplevel_handle_v1::State::Maximized);
+            }
+            if info.minimized {
+                states.push(zwlr_foreign_toplevel_handle_v1::State::Minimized);
+            }
+            // The fullscreen state came with version 2.
+            if info.fullscreen && handle.version() >= 2 {
+                states.push(zwlr_foreign_toplevel_handle_v1::State::Fullscreen);
+            }
+            handle.state(
+                states
+                    .into_iter()
+                    .flat_map(|state| (state as u32).to_ne_bytes())
+                    .collect(),
+            );
+        }
+        handle.done();
+    }
+
+    /// Tells the client on which of its wl_output objects the window entered or left.
+    fn send_wlr_output(handle: &ZwlrForeignToplevelHandleV1, output: &Output, enter: bool) {
+        let Some(client) = handle.client() else {
+            return;
+        };
+        for wl_output in output.client_outputs(&client) {
+            if enter {
+                handle.output_enter(&wl_output);
+            } else {
+                handle.output_leave(&wl_output);
+            }
+        }
+    }
+
+    /// The ext list only knows about titles and app ids.
+    fn send_ext_info(
+        handle: &ExtForeignToplevelHandleV1,
+        info: &ToplevelInfo,
+        previous: Option<&ToplevelInfo>,
+    ) {
+        let mut changed = previous.is_none();
+        if let Some(ref title) = info.title {
+            if previous.map(|previous| &previous.title) != Some(&info.title) {
+                handle.title(title.clone());
+                changed = true;
+            }
+        }
+        if let Some(ref app_id) = info.app_id {
+            if previous.map(|previous| &previous.app_id) != Some(&info.app_id) {
+                handle.app_id(app_id.clone());
+                changed = true;
+            }
+        }
+        if changed {
+            handle.done();
+        }
+    }
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// The window a surface belongs to, if it's a toplevel or a mapped X11 window.
+        pub fn toplevel_id_for_surface(&self, surface_id: u64) -> Option<ToplevelId> {
+            if self.xdg_toplevels.contains_key(&surface_id) {
+                return Some(ToplevelId::Xdg(surface_id));
+            }
+            let surface = self.surfaces.get(&surface_id)?;
+            self.x11_surface_per_wl_surface
+                .get(surface)
+                .map(|x11_surface| ToplevelId::X11(Self::get_x11_surface_id(x11_surface)))
+        }
+
+        fn foreign_toplevel_info(&self, id: ToplevelId, minimized: bool) -> Option<ToplevelInfo> {
+            match id {
+                ToplevelId::Xdg(surface_id) => {
+                    let toplevel = self.xdg_toplevels.get(&surface_id)?;
+                    let (title, app_id, states) =
+                        with_states(toplevel.wl_surface(), |surface_data| {
+                            let attributes = surface_data
+                                .data_map
+                                .get::<XdgToplevelSurfaceData>()
+                                .unwrap()
+                                .lock()
+                                .unwrap();
+                            (
+                                attributes.title.clone(),
+                                attributes.app_id.clone(),
+                                attributes.current.states.clone(),
+                            )
+                        });
+                    Some(ToplevelInfo {
+                        title,
+                        app_id,
+                        activated: states.contains(xdg_toplevel::State::Activated),
+                        maximized: states.contains(xdg_toplevel::State::Maximized),
+                        minimized,
+                        fullscreen: states.contains(xdg_toplevel::State::Fullscreen),
+                        output: self.output_for_surface_id(surface_id),
+                    })
+                }
+                ToplevelId::X11(x11_surface_id) => {
+                    let x11_surface =
+                        self.x11_surface_per_x11_window
+                            .values()
+                            .find(|x11_surface| {
+                                Self::get_x11_surface_id(x11_surface) == x11_surface_id
+                            })?;
+                    let title = x11_surface.title();
+                    let class = x11_surface.class();
+                    Some(ToplevelInfo {
+                        title: (!title.is_empty()).then_some(title),
+                        app_id: (!class.is_empty()).then_some(class),
+                        activated: x11_surface.is_activated(),
+                        maximized: x11_surface.is_maximized(),
+                        minimized,
+                        fullscreen: x11_surface.is_fullscreen(),
+                        // Not displayed anywhere until it's associated with a Wayland surface.
+                        output: x11_surface.wl_surface().and_then(|surface| {
+                            self.output_for_surface_id(get_surface_id(&surface))
+                        }),
+                    })
+                }
+            }
+        }
+
+        /// Lists a new window in the taskbars.
+        pub fn new_foreign_toplevel(&mut self, id: ToplevelId) {
+            let state = &mut self.foreign_toplevel_manager_state;
+            if state.toplevel_mut(id).is_some() {
+                return;
+            }
+
+            let mut toplevel = ForeignToplevel {
+                id,
+                identifier: format!("veshell-{}", state.next_identifier),
+                info: ToplevelInfo::default(),
+                minimized: false,
+                wlr_handles: Vec::new(),
+                ext_handles: Vec::new(),
+            };
+            state.next_identifier += 1;
+            if let Some(info) = self.foreign_toplevel_info(id, false) {
+                toplevel.info = info;
+            }
+
+            let state = &mut self.foreign_toplevel_manager_state;
+            for manager in &state.wlr_managers {
+                toplevel.announce_wlr::<BackendData>(&self.display_handle, manager);
+            }
+            for list in &state.ext_lists {
+                toplevel.announce_ext::<BackendData>(&self.display_handle, list);
+            }
+            state.toplevels.push(toplevel);
+        }
+
+        /// Sends the title, app id, state and output of the window again if they changed.
+        pub fn update_foreign_toplevel(&mut self, id: ToplevelId) {
+            let Some(minimized) = self
+                .foreign_toplevel_manager_state
+                .toplevel_mut(id)
+                .map(|toplevel| toplevel.minimized)
+            else {
+                return;
+            };
+            let Some(info) = self.foreign_toplevel_info(id, minimized) else {
+                return;
+            };
+
+            let toplevel = self
+                .foreign_toplevel_manager_state
+                .toplevel_mut(id)
+                .unwrap();
+            if info == toplevel.info {
+                return;
+            }
+            for handle in &toplevel.wlr_handles {
+                send_wlr_info(handle, &info, Some(&toplevel.info));
+            }
+            for handle in &toplevel.ext_handles {
+                send_ext_info(handle, &info, Some(&toplevel.info));
+            }
+            toplevel.info = info;
+        }
+
+        pub fn set_foreign_toplevel_minimized(&mut self, id: ToplevelId, minimized: bool) {
+            if let Some(toplevel) = self.foreign_toplevel_manager_state.toplevel_mut(id) {
+                toplevel.minimized = minimized;
+            }
+            self.update_foreign_toplevel(id);
+        }
+
+        /// Removes a destroyed or unmapped window from the taskbars.
+        pub fn foreign_toplevel_closed(&mut self, id: ToplevelId) {
+            let state = &mut self.foreign_toplevel_manager_state;
+            let Some(index) = state
+                .toplevels
+                .iter()
+                .position(|toplevel| toplevel.id == id)
+            else {
+                return;
+            };
+            let toplevel = state.toplevels.remove(index);
+            for handle in toplevel.wlr_handles {
+                handle.closed();
+            }
+            for handle in toplevel.ext_handles {
+                handle.closed();
+            }
+        }
+
+        /// The shell decides what to do with the requests of taskbars, like any other window management.
+        fn forward_foreign_toplevel_request(
+            &mut self,
+            id: ToplevelId,
+            request: &str,
+            monitor_name: Option<String>,
+        ) {
+            let mut message = json!({
+                "request": request,
+                "monitorName": monitor_name,
+            });
+            match id {
+                ToplevelId::Xdg(surface_id) => message["surfaceId"] = json!(surface_id),
+                ToplevelId::X11(x11_surface_id) => message["x11SurfaceId"] = json!(x11_surface_id),
+            }
+
+            self.flutter_engine_mut()
+                .platform_method_channel
+                .invoke_method("foreign_toplevel_request", Some(Box::new(message)), None);
+        }
+    }
+
+    impl<BackendData: Backend + 'static> GlobalDispatch<ZwlrForeignToplevelManagerV1, ()>
+        for State<BackendData>
+    {
+        fn bind(
+            state: &mut Self,
+            handle: &DisplayHandle,
+            _client: &Client,
+            resource: New<ZwlrForeignToplevelManagerV1>,
+            _global_data: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            let manager = data_init.init(resource, ());
+            let manager_state = &mut state.foreign_toplevel_manager_state;
+            for toplevel in &mut manager_state.toplevels {
+                toplevel.announce_wlr::<BackendData>(handle, &manager);
+            }
+            manager_state.wlr_managers.push(manager);
+        }
+
+        fn can_view(client: Client, _global_data: &()) -> bool {
+            can_view_privileged_global(&client, PrivilegedProtocol::ForeignToplevel)
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwlrForeignToplevelManagerV1, ()>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            manager: &ZwlrForeignToplevelManagerV1,
+            request: zwlr_foreign_toplevel_manager_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                zwlr_foreign_toplevel_manager_v1::Request::Stop => {
+                    state
+                        .foreign_toplevel_manager_state
+                        .wlr_managers
+                        .retain(|other| other != manager);
+                    manager.finished();
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        fn destroyed(
+            state: &mut Self,
+            _client: ClientId,
+            manager: &ZwlrForeignToplevelManagerV1,
+            _data: &(),
+        ) {
+            state
+                .foreign_toplevel_manager_state
+                .wlr_managers
+                .retain(|other| other != manager);
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwlrForeignToplevelHandleV1, ToplevelId>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            _handle: &ZwlrForeignToplevelHandleV1,
+            request: zwlr_foreign_toplevel_handle_v1::Request,
+            id: &ToplevelId,
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            // Requests for closed windows are ignored.
+            if state
+                .foreign_toplevel_manager_state
+                .toplevel_mut(*id)
+                .is_none()
+            {
+                return;
+            }
+
+            match request {
+                zwlr_foreign_toplevel_handle_v1::Request::SetMaximized => {
+                    state.forward_foreign_toplevel_request(*id, "maximize", None)
+                }
+                zwlr_foreign_toplevel_handle_v1::Request::UnsetMaximized => {
+                    state.forward_foreign_toplevel_request(*id, "unmaximize", None)
+                }
+                zwlr_foreign_toplevel_handle_v1::Request::SetMinimized => {
+                    state.forward_foreign_toplevel_request(*id, "minimize", None)
+                }
+                zwlr_foreign_toplevel_handle_v1::Request::UnsetMinimized => {
+                    state.forward_foreign_toplevel_request(*id, "unminimize", None)
+                }
+                zwlr_foreign_toplevel_handle_v1::Request::Activate { .. } => {
+                    state.forward_foreign_toplevel_request(*id, "activate", None)
+                }
+                zwlr_foreign_toplevel_handle_v1::Request::Close => {
+                    state.forward_foreign_toplevel_request(*id, "close", None)
+                }
+                zwlr_foreign_toplevel_handle_v1::Request::SetFullscreen { output } => {
+                    let monitor_name = output
+                        .as_ref()
+                        .and_then(Output::from_resource)
+                        .map(|output| output.name());
+                    state.forward_foreign_toplevel_request(*id, "fullscreen", monitor_name)
+                }
+                zwlr_foreign_toplevel_handle_v1::Request::UnsetFullscreen => {
+                    state.forward_foreign_toplevel_request(*id, "unfullscreen", None)
+                }
+                // Only a hint for minimize animations.
+                zwlr_foreign_toplevel_handle_v1::Request::SetRectangle { .. } => {}
+                zwlr_foreign_toplevel_handle_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+
+        fn destroyed(
+            state: &mut Self,
+            _client: ClientId,
+            handle: &ZwlrForeignToplevelHandleV1,
+            id: &ToplevelId,
+        ) {
+            if let Some(toplevel) = state.foreign_toplevel_manager_state.toplevel_mut(*id) {
+                toplevel.wlr_handles.retain(|other| other != handle);
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> GlobalDispatch<ExtForeignToplevelListV1, ()>
+        for State<BackendData>
+    {
+        fn bind(
+            state: &mut Self,
+            handle: &DisplayHandle,
+            _client: &Client,
+            resource: New<ExtForeignToplevelListV1>,
+            _global_data: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            let list = data_init.init(resource, ());
+            let manager_state = &mut state.foreign_toplevel_manager_state;
+            for toplevel in &mut manager_state.toplevels {
+                toplevel.announce_ext::<BackendData>(handle, &list);
+            }
+            manager_state.ext_lists.push(list);
+        }
+
+        fn can_view(client: Client, _global_data: &()) -> bool {
+            can_view_privileged_global(&client, PrivilegedProtocol::ForeignToplevel)
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ExtForeignToplevelListV1, ()> for State<BackendData> {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            list: &ExtForeignToplevelListV1,
+            request: ext_foreign_toplevel_list_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                ext_foreign_toplevel_list_v1::Request::Stop => {
+                    state
+                        .foreign_toplevel_manager_state
+                        .ext_lists
+                        .retain(|other| other != list);
+                    list.finished();
+                }
+                ext_foreign_toplevel_list_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+
+        fn destroyed(
+            state: &mut Self,
+            _client: ClientId,
+            list: &ExtForeignToplevelListV1,
+            _data: &(),
+        ) {
+            state
+                .foreign_toplevel_manager_state
+                .ext_lists
+                .retain(|other| other != list);
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ExtForeignToplevelHandleV1, ToplevelId>
+        for State<BackendData>
+    {
+        fn request(
+            _state: &mut Self,
+            _client: &Client,
+            _handle: &ExtForeignToplevelHandleV1,
+            request: ext_foreign_toplevel_handle_v1::Request,
+            _id: &ToplevelId,
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                ext_foreign_toplevel_handle_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+
+        fn destroyed(
+            state: &mut Self,
+            _client: ClientId,
+            handle: &ExtForeignToplevelHandleV1,
+            id: &ToplevelId,
+        ) {
+            if let Some(toplevel) = state.foreign_toplevel_manager_state.toplevel_mut(*id) {
+                toplevel.ext_handles.retain(|other| other != handle);
+            }
+        }
+    }
+}
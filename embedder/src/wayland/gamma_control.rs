@@ -0,0 +1,342 @@
+pub mod gamma_control {
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    use rustix::fs::OFlags;
+    use smithay::{
+        output::Output,
+        reexports::{
+            wayland_protocols_wlr::gamma_control::v1::server::{
+                zwlr_gamma_control_manager_v1::{self, ZwlrGammaControlManagerV1},
+                zwlr_gamma_control_v1::{self, ZwlrGammaControlV1},
+            },
+            wayland_server::{
+                backend::{ClientId, GlobalId},
+                Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+            },
+        },
+    };
+    use tracing::warn;
+
+    use crate::{
+        state::State,
+        wayland::security_context::security_context::{
+            can_view_privileged_global, PrivilegedProtocol,
+        },
+        Backend,
+    };
+
+    pub struct GammaControlManagerState {
+        global: GlobalId,
+        /// Only one client at a time can control the gamma of an output.
+        controls_per_output: HashMap<String, ZwlrGammaControlV1>,
+        /// Night light color temperature set by the shell, in Kelvin.
+        color_temperature: Option<u32>,
+    }
+
+    impl GammaControlManagerState {
+        pub fn new<BackendData: Backend + 'static>(display_handle: &DisplayHandle) -> Self {
+            let global = display_handle
+                .create_global::<State<BackendData>, ZwlrGammaControlManagerV1, _>(1, ());
+            Self {
+                global,
+                controls_per_output: HashMap::new(),
+                color_temperature: None,
+            }
+        }
+
+        pub fn global(&self) -> GlobalId {
+            self.global.clone()
+        }
+    }
+
+    /// Name of the output whose gamma ramps this object sets.
+    /// `None` if the output was gone, had no gamma ramps or was already controlled by another client,
+    /// and cleared once the output is unplugged.
+    pub struct GammaControlData {
+        output_name: Mutex<Option<String>>,
+    }
+
+    impl GammaControlData {
+        fn output_name(&self) -> Option<String> {
+            self.output_name.lock().unwrap().clone()
+        }
+    }
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// Applies the shell color temperature to every output that isn't controlled by a client.
+        pub fn set_color_temperature(&mut self, color_temperature: Option<u32>) {
+            self.gamma_control_manager_state.color_temperature = color_temperature;
+
+            let outputs = self.space.outputs().cloned().collect::<Vec<_>>();
+            for output in outputs {
+                self.restore_gamma(&output);
+            }
+        }
+
+        /// Gives the gamma of the output to the shell, unless a client controls it.
+        /// Also called when the output is plugged or powered on, so the night light follows.
+        pub fn restore_gamma(&mut self, output: &Output) {
+            if self
+                .gamma_control_manager_state
+                .controls_per_output
+                .contains_key(&output.name())
+            {
+                return;
+            }
+            let Some(size) = self.backend_data.gamma_size(output) else {
+                return;
+            };
+            let ramp = self
+                .gamma_control_manager_state
+                .color_temperature
+                .map(|temperature| color_temperature_ramp(temperature, size));
+
+            if let Err(err) = self.backend_data.set_gamma(output, ramp) {
+                warn!("Failed to restore the gamma of {}: {}", output.name(), err);
+            }
+        }
+    }
+
+    /// Builds red, green and blue gamma ramps that tint the screen like a black body at this temperature.
+    /// 6500K is neutral, lower values are warmer.
+    pub fn color_temperature_ramp(temperature: u32, size: usize) -> Vec<u16> {
+        let white_point = black_body_color(6500);
+        let color = black_body_color(temperature);
+
+        let mut ramp = vec![0u16; size * 3];
+        for (channel, ramp) in ramp.chunks_exact_mut(size).enumerate() {
+            let factor = (color[channel] / white_point[channel]).min(1.0);
+            for (i, value) in ramp.iter_mut().enumerate() {
+                let linear = i as f64 / (size - 1).max(1) as f64;
+                *value = (linear * factor * u16::MAX as f64).round() as u16;
+            }
+        }
+        ramp
+    }
+
+    /// Approximation of the color of a black body, by Tanner Helland.
+    fn black_body_color(temperature: u32) -> [f64; 3] {
+        let t = temperature.clamp(1000, 40000) as f64 / 100.0;
+
+        let red = if t <= 66.0 {
+            255.0
+        } else {
+            329.698727446 * (t - 60.0).powf(-0.1332047592)
+        };
+        let green = if t <= 66.0 {
+            99.4708025861 * t.ln() - 161.1195681661
+        } else {
+            288.1221695283 * (t - 60.0).powf(-0.0755148492)
+        };
+        let blue = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.5177312231 * (t - 10.0).ln() - 305.0447927307
+        };
+
+        [red, green, blue].map(|channel| channel.clamp(0.0, 255.0) / 255.0)
+    }
+
+    impl<BackendData: Backend + 'static> GlobalDispatch<ZwlrGammaControlManagerV1, ()>
+        for State<BackendData>
+    {
+        fn bind(
+            _state: &mut Self,
+            _handle: &DisplayHandle,
+            _client: &Client,
+            resource: New<ZwlrGammaControlManagerV1>,
+            _global_data: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            data_init.init(resource, ());
+        }
+
+        fn can_view(client: Client, _global_data: &()) -> bool {
+            can_view_privileged_global(&client, PrivilegedProtocol::OutputManagement)
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwlrGammaControlManagerV1, ()>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            _manager: &ZwlrGammaControlManagerV1,
+            request: zwlr_gamma_control_manager_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                zwlr_gamma_control_manager_v1::Request::GetGammaControl { id, output } => {
+                    let output = Output::from_resource(&output);
+                    let gamma_size = output
+                        .as_ref()
+                        .and_then(|output| state.backend_data.gamma_size(output));
+
+                    match (output, gamma_size) {
+                        (Some(output), Some(gamma_size))
+                            if !state
+                                .gamma_control_manager_state
+                                .controls_per_output
+                                .contains_key(&output.name()) =>
+                        {
+                            let gamma_control = data_init.init(
+                                id,
+                                GammaControlData {
+                                    output_name: Mutex::new(Some(output.name())),
+                                },
+                            );
+                            gamma_control.gamma_size(gamma_size as u32);
+                            state
+                                .gamma_control_manager_state
+                                .controls_per_output
+                                .insert(output.name(), gamma_control);
+                        }
+                        _ => {
+                            let gamma_control = data_init.init(
+                                id,
+                                GammaControlData {
+                                    output_name: Mutex::new(None),
+                                },
+                            );
+                            gamma_control.failed();
+                        }
+                    }
+                }
+                zwlr_gamma_control_manager_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwlrGammaControlV1, GammaControlData>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            gamma_control: &ZwlrGammaControlV1,
+            request: zwlr_gamma_control_v1::Request,
+            data: &GammaControlData,
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                zwlr_gamma_control_v1::Request::SetGamma { fd } => {
+                    let Some(ref output_name) = data.output_name() else {
+                        return;
+                    };
+                    // The control is inert once it failed.
+                    if state
+                        .gamma_control_manager_state
+                        .controls_per_output
+                        .get(output_name)
+                        != Some(gamma_control)
+                    {
+                        return;
+                    }
+                    let output = state
+                        .space
+                        .outputs()
+                        .find(|output| output.name() == *output_name)
+                        .cloned();
+
+                    let result = output
+                        .ok_or_else(|| {
+                            std::io::Error::new(std::io::ErrorKind::NotFound, "Output is gone")
+                        })
+                        .and_then(|output| {
+                            let gamma_size =
+                                state.backend_data.gamma_size(&output).ok_or_else(|| {
+                                    std::io::Error::from(std::io::ErrorKind::Unsupported)
+                                })?;
+                            let ramp = read_gamma_ramp(File::from(fd), gamma_size)?;
+                            state.backend_data.set_gamma(&output, Some(ramp))
+                        });
+
+                    if let Err(err) = result {
+                        warn!("Failed to set the gamma of {}: {}", output_name, err);
+                        gamma_control.failed();
+                        Self::gamma_control_gone(state, output_name);
+                    }
+                }
+                zwlr_gamma_control_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+
+        fn destroyed(
+            state: &mut Self,
+            _client: ClientId,
+            gamma_control: &ZwlrGammaControlV1,
+            data: &GammaControlData,
+        ) {
+            // Also called when the client exits without cleaning up.
+            let Some(ref output_name) = data.output_name() else {
+                return;
+            };
+            if state
+                .gamma_control_manager_state
+                .controls_per_output
+                .get(output_name)
+                == Some(gamma_control)
+            {
+                Self::gamma_control_gone(state, output_name);
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// Fails the client controlling the gamma of an unplugged output,
+        /// so that the shell gets the gamma back when the output returns.
+        pub fn gamma_output_gone(&mut self, output: &Output) {
+            let Some(gamma_control) = self
+                .gamma_control_manager_state
+                .controls_per_output
+                .remove(&output.name())
+            else {
+                return;
+            };
+            gamma_control.failed();
+            if let Some(data) = gamma_control.data::<GammaControlData>() {
+                *data.output_name.lock().unwrap() = None;
+            }
+        }
+
+        fn gamma_control_gone(&mut self, output_name: &str) {
+            self.gamma_control_manager_state
+                .controls_per_output
+                .remove(output_name);
+
+            let output = self
+                .space
+                .outputs()
+                .find(|output| output.name() == output_name)
+                .cloned();
+            if let Some(output) = output {
+                self.restore_gamma(&output);
+            }
+        }
+    }
+
+    /// The red, green and blue ramps are packed one after the other as native endian u16.
+    /// Fails instead of waiting if the client didn't write all of them, the fd may be a pipe.
+    fn read_gamma_ramp(mut file: File, gamma_size: usize) -> std::io::Result<Vec<u16>> {
+        let flags = rustix::fs::fcntl_getfl(&file)?;
+        rustix::fs::fcntl_setfl(&file, flags | OFlags::NONBLOCK)?;
+
+        let mut bytes = vec![0u8; gamma_size * 3 * std::mem::size_of::<u16>()];
+        file.read_exact(&mut bytes)?;
+        Ok(bytes
+            .chunks_exact(2)
+            .map(|value| u16::from_ne_bytes([value[0], value[1]]))
+            .collect())
+    }
+}
@@ -0,0 +1,168 @@
+pub mod idle {
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+    use smithay::{
+        delegate_idle_inhibit, delegate_idle_notify,
+        reexports::{
+            calloop::{
+                timer::{TimeoutAction, Timer},
+                RegistrationToken,
+            },
+            wayland_server::{protocol::wl_surface::WlSurface, Resource},
+        },
+        wayland::{
+            compositor::get_parent,
+            idle_inhibit::IdleInhibitHandler,
+            idle_notify::{IdleNotifierHandler, IdleNotifierState},
+        },
+    };
+    use tracing::info;
+
+    use crate::{state::State, wayland::wayland::get_surface_id, Backend};
+
+    /// Whether the user is away, as far as the shell is concerned.
+    pub struct IdleState {
+        pub last_input_time: Instant,
+        /// The shell is told the user is idle after this long without input.
+        idle_timeout: Option<Duration>,
+        idle_timer: Option<RegistrationToken>,
+        is_idle: bool,
+        /// Surfaces with an idle inhibitor, which only count while the shell displays them.
+        inhibiting_surfaces: Vec<WlSurface>,
+        is_inhibited: bool,
+    }
+
+    impl IdleState {
+        pub fn new() -> Self {
+            Self {
+                last_input_time: Instant::now(),
+                idle_timeout: None,
+                idle_timer: None,
+                is_idle: false,
+                inhibiting_surfaces: Vec::new(),
+                is_inhibited: false,
+            }
+        }
+
+        pub fn is_inhibited(&self) -> bool {
+            self.is_inhibited
+        }
+    }
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// Called on every input event.
+        pub fn notify_activity(&mut self) {
+            self.idle_state.last_input_time = Instant::now();
+            let seat = self.seat.clone();
+            self.idle_notifier_state.notify_activity(&seat);
+
+            if self.idle_state.is_idle {
+                self.set_idle(false);
+            }
+            self.wake_idle_blanked_outputs();
+        }
+
+        pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+            self.idle_state.idle_timeout = timeout;
+            if let Some(token) = self.idle_state.idle_timer.take() {
+                self.loop_handle.remove(token);
+            }
+            let Some(timeout) = timeout else {
+                return;
+            };
+
+            // Like idle blanking, the timer only checks how long ago the last input was.
+            self.idle_state.idle_timer = self
+                .loop_handle
+                .insert_source(Timer::from_duration(timeout), |_, _, data| {
+                    data.check_idle()
+                })
+                .ok();
+        }
+
+        fn check_idle(&mut self) -> TimeoutAction {
+            let Some(timeout) = self.idle_state.idle_timeout else {
+                return TimeoutAction::Drop;
+            };
+            let idle_time = self.idle_state.last_input_time.elapsed();
+            if idle_time < timeout {
+                return TimeoutAction::ToDuration(timeout - idle_time);
+            }
+
+            if !self.idle_state.is_idle && !self.idle_state.is_inhibited {
+                self.set_idle(true);
+            }
+            TimeoutAction::ToDuration(timeout)
+        }
+
+        fn set_idle(&mut self, is_idle: bool) {
+            self.idle_state.is_idle = is_idle;
+            self.flutter_engine_mut()
+                .platform_method_channel
+                .invoke_method(
+                    "idle_state_changed",
+                    Some(Box::new(json!({
+                        "idle": is_idle,
+                    }))),
+                    None,
+                );
+        }
+
+        /// Re-evaluates the idle inhibitors.
+        /// Called whenever an inhibitor comes or goes and whenever the shell moves a window.
+        pub fn update_idle_inhibition(&mut self) {
+            self.idle_state
+                .inhibiting_surfaces
+                .retain(|surface| surface.is_alive());
+
+            // A video playing on another workspace shouldn't keep the screen on.
+            let is_inhibited = self.idle_state.inhibiting_surfaces.iter().any(|surface| {
+                let mut root = surface.clone();
+                while let Some(parent) = get_parent(&root) {
+                    root = parent;
+                }
+                self.output_for_surface_id(get_surface_id(&root)).is_some()
+            });
+            if is_inhibited == self.idle_state.is_inhibited {
+                return;
+            }
+
+            info!("Idle inhibited: {}", is_inhibited);
+            self.idle_state.is_inhibited = is_inhibited;
+            self.idle_notifier_state.set_is_inhibited(is_inhibited);
+            if !is_inhibited {
+                // The inhibition shouldn't make the next timeout fire right away.
+                self.idle_state.last_input_time = Instant::now();
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> IdleNotifierHandler for State<BackendData> {
+        fn idle_notifier_state(&mut self) -> &mut IdleNotifierState<Self> {
+            &mut self.idle_notifier_state
+        }
+    }
+    delegate_idle_notify!(@<BackendData: Backend + 'static> State<BackendData>);
+
+    impl<BackendData: Backend + 'static> IdleInhibitHandler for State<BackendData> {
+        fn inhibit(&mut self, surface: WlSurface) {
+            self.idle_state.inhibiting_surfaces.push(surface);
+            self.update_idle_inhibition();
+        }
+
+        fn uninhibit(&mut self, surface: WlSurface) {
+            // A surface can have more than one inhibitor.
+            if let Some(index) = self
+                .idle_state
+                .inhibiting_surfaces
+                .iter()
+                .position(|other| *other == surface)
+            {
+                self.idle_state.inhibiting_surfaces.remove(index);
+            }
+            self.update_idle_inhibition();
+        }
+    }
+    delegate_idle_inhibit!(@<BackendData: Backend + 'static> State<BackendData>);
+}
@@ -0,0 +1,111 @@
+pub mod keyboard_shortcuts_inhibit {
+    use serde_json::json;
+    use smithay::{
+        delegate_keyboard_shortcuts_inhibit,
+        reexports::wayland_server::protocol::wl_surface::WlSurface,
+        wayland::{
+            keyboard_shortcuts_inhibit::{
+                KeyboardShortcutsInhibitHandler, KeyboardShortcutsInhibitState,
+                KeyboardShortcutsInhibitor, KeyboardShortcutsInhibitorSeat,
+            },
+            seat::WaylandFocus,
+        },
+    };
+    use tracing::info;
+
+    use crate::{state::State, wayland::wayland::get_surface_id, Backend};
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// Whether the focused surface gets every key, bypassing the shell and the compositor shortcuts.
+        pub fn are_shortcuts_inhibited(&self) -> bool {
+            self.shortcuts_inhibiting_surface_id.is_some()
+        }
+
+        /// Inhibitors are only honored while their surface has the keyboard focus.
+        /// Called with the new focus whenever it changes.
+        pub fn update_shortcuts_inhibitor(&mut self, focus: Option<&WlSurface>) {
+            let focus_id = focus.map(get_surface_id);
+            if focus_id == self.shortcuts_inhibiting_surface_id {
+                return;
+            }
+
+            if let Some(previous) = self
+                .shortcuts_inhibiting_surface_id
+                .and_then(|surface_id| self.surfaces.get(&surface_id))
+            {
+                if let Some(inhibitor) =
+                    self.seat.keyboard_shortcuts_inhibitor_for_surface(previous)
+                {
+                    inhibitor.inactivate();
+                }
+            }
+
+            let inhibiting_surface_id = focus.and_then(|surface| {
+                let inhibitor = self
+                    .seat
+                    .keyboard_shortcuts_inhibitor_for_surface(surface)?;
+                inhibitor.activate();
+                Some(get_surface_id(surface))
+            });
+            self.set_shortcuts_inhibiting_surface(inhibiting_surface_id);
+        }
+
+        /// The escape hatch that no client can block, Super+Escape,
+        /// the inhibitor stays inactive until its surface gets the focus again.
+        pub fn escape_shortcuts_inhibitor(&mut self) {
+            if let Some(surface) = self
+                .shortcuts_inhibiting_surface_id
+                .and_then(|surface_id| self.surfaces.get(&surface_id))
+            {
+                if let Some(inhibitor) = self.seat.keyboard_shortcuts_inhibitor_for_surface(surface)
+                {
+                    inhibitor.inactivate();
+                }
+            }
+            self.set_shortcuts_inhibiting_surface(None);
+        }
+
+        fn set_shortcuts_inhibiting_surface(&mut self, surface_id: Option<u64>) {
+            if surface_id == self.shortcuts_inhibiting_surface_id {
+                return;
+            }
+            info!("Keyboard shortcuts inhibited by {:?}", surface_id);
+            self.shortcuts_inhibiting_surface_id = surface_id;
+
+            // The shell shows that its shortcuts don't work, and how to get them back.
+            self.flutter_engine_mut()
+                .platform_method_channel
+                .invoke_method(
+                    "shortcuts_inhibitor_changed",
+                    Some(Box::new(json!({
+                        "surfaceId": surface_id,
+                    }))),
+                    None,
+                );
+        }
+    }
+
+    impl<BackendData: Backend + 'static> KeyboardShortcutsInhibitHandler for State<BackendData> {
+        fn keyboard_shortcuts_inhibit_state(&mut self) -> &mut KeyboardShortcutsInhibitState {
+            &mut self.keyboard_shortcuts_inhibit_state
+        }
+
+        fn new_inhibitor(&mut self, inhibitor: KeyboardShortcutsInhibitor) {
+            let focus = self
+                .keyboard
+                .current_focus()
+                .and_then(|focus| focus.wl_surface().map(|surface| surface.into_owned()));
+            if focus.as_ref() == Some(inhibitor.wl_surface()) {
+                self.update_shortcuts_inhibitor(focus.as_ref());
+            }
+        }
+
+        fn inhibitor_destroyed(&mut self, inhibitor: KeyboardShortcutsInhibitor) {
+            if self.shortcuts_inhibiting_surface_id == Some(get_surface_id(inhibitor.wl_surface()))
+            {
+                self.set_shortcuts_inhibiting_surface(None);
+            }
+        }
+    }
+    delegate_keyboard_shortcuts_inhibit!(@<BackendData: Backend + 'static> State<BackendData>);
+}
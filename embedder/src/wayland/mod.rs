@@ -1,21 +1,45 @@
+pub mod color_management;
+pub mod drm_syncobj;
+pub mod foreign_toplevel;
+pub mod gamma_control;
+pub mod idle;
+pub mod keyboard_shortcuts_inhibit;
+pub mod output_power_management;
+pub mod pointer_constraints;
+pub mod security_context;
+pub mod tearing_control;
+pub mod virtual_input;
 pub mod xdg;
+pub mod xdg_activation;
 
 pub mod wayland {
     use std::cell::RefCell;
 
     use serde_json::json;
     use smithay::{
-        backend::renderer::{ImportAll, Texture},
+        backend::renderer::{
+            buffer_dimensions, gles::ffi, utils::on_commit_buffer_handler, ImportAll, Texture,
+        },
         reexports::wayland_server::{protocol::wl_surface::WlSurface, Client},
-        utils::{Buffer as BufferCoords, Size},
+        utils::{Buffer as BufferCoords, Rectangle, Size},
         wayland::compositor::{
-            with_states, with_surface_tree_upward, BufferAssignment, CompositorClientState,
-            CompositorHandler, CompositorState, SurfaceAttributes, TraversalAction,
+            is_sync_subsurface, with_states, with_surface_tree_upward, BufferAssignment,
+            CompositorClientState, CompositorHandler, CompositorState, Damage, SurfaceAttributes,
+            TraversalAction,
         },
         xwayland::XWaylandClientData,
     };
 
-    use crate::{state::State, Backend, ClientState};
+    use crate::{
+        state::State,
+        texture_swap_chain::{wait_for_sync_file, TextureFence},
+        wayland::{
+            color_management::color_management::surface_image_description,
+            drm_syncobj::drm_syncobj::take_explicit_sync_points,
+            tearing_control::tearing_control::surface_allows_tearing,
+        },
+        Backend, ClientState,
+    };
 
     pub struct WlSurfaceVeshellState {
         pub surface_id: u64,
@@ -98,6 +122,7 @@ pub mod wayland {
                 })
             });
             self.surfaces.insert(surface_id, surface.clone());
+            self.add_acquire_point_hook(surface);
 
             let platform_method_channel = &mut self.flutter_engine_mut().platform_method_channel;
             platform_method_channel.invoke_method(
@@ -125,18 +150,64 @@ pub mod wayland {
         }
 
         fn commit(&mut self, surface: &WlSurface) {
+            // The state of synchronized subsurfaces is cached until their parent commits,
+            // it's sent to Flutter along with the state of the parent.
+            if is_sync_subsurface(surface) {
+                return;
+            }
+            self.apply_commit(surface);
+        }
+
+        fn destroyed(&mut self, _surface: &WlSurface) {
+            let surface_id = with_states(_surface, |surface_data| {
+                surface_data
+                    .data_map
+                    .get::<RefCell<WlSurfaceVeshellState>>()
+                    .unwrap()
+                    .borrow()
+                    .surface_id
+            });
+            self.surfaces.remove(&surface_id);
+            self.set_surface_release_point(surface_id, None);
+            self.release_surface_textures(surface_id);
+            self.scanout_surface_per_output
+                .retain(|_, scanout_surface_id| *scanout_surface_id != surface_id);
+            self.tearing_surface_ids_committed.remove(&surface_id);
+            self.surface_ids_sampled_by_flutter.remove(&surface_id);
+            self.monitor_per_surface_id.remove(&surface_id);
+
+            let platform_method_channel = &mut self.flutter_engine_mut().platform_method_channel;
+            platform_method_channel.invoke_method(
+                "destroy_surface",
+                Some(Box::new(json!({
+                    "surfaceId": surface_id,
+                }))),
+                None,
+            );
+        }
+    }
+
+    impl<BackendData: Backend> State<BackendData> {
+        /// Sends the new state of a surface to Flutter, after importing its buffer.
+        fn apply_commit(&mut self, surface: &WlSurface) {
             let (subsurfaces_below, subsurfaces_above) = get_direct_subsurfaces(surface);
 
-            // Make sure Flutter knows about subsurfaces
-            // because Wayland clients have the option to never commit them.
-            // In Wayland, when the parent surface is committed,
-            // subsurfaces are also committed recursively.
+            // Committing the parent applies the cached state of its synchronized subsurfaces,
+            // and the position of all of them.
+            // They go first because the buffer handler of the parent takes the buffers of the whole tree.
             for surface_id in subsurfaces_below.iter().chain(subsurfaces_above.iter()) {
-                let surface = self.surfaces.get(surface_id).unwrap().clone();
-                let _ = self.commit(&surface);
+                let subsurface = self.surfaces.get(surface_id).unwrap().clone();
+                if is_sync_subsurface(&subsurface) {
+                    self.apply_commit(&subsurface);
+                } else {
+                    self.send_surface_message(&subsurface, None);
+                }
             }
 
-            with_states(surface, |surface_data| {
+            // HDR and wide gamut buffers are converted to sRGB before Flutter samples them.
+            let image_description = surface_image_description(surface);
+
+            let buffer_damage = with_states(surface, |surface_data| {
                 let (surface_id, old_texture_size) = {
                     let my_state = surface_data
                         .data_map
@@ -149,124 +220,151 @@ pub mod wayland {
                 let mut binding = surface_data.cached_state.get::<SurfaceAttributes>();
                 let attributes = binding.current();
 
+                let (mut acquire_fence, release_point) = take_explicit_sync_points(surface_data);
+
                 let texture = attributes
                     .buffer
                     .as_ref()
                     .and_then(|assignment| match assignment {
-                        BufferAssignment::NewBuffer(buffer) => self
-                            .gles_renderer
-                            .as_mut()
-                            .unwrap()
-                            .import_buffer(buffer, Some(surface_data), &[])
-                            .and_then(|t| t.ok()),
+                        BufferAssignment::NewBuffer(buffer) => {
+                            // Shm buffers are uploaded to the texture the surface already has,
+                            // only where they're damaged. Dmabufs ignore the damage.
+                            let damage = buffer_dimensions(buffer)
+                                .map(|size| current_buffer_damage(attributes, size))
+                                .unwrap_or_default();
+                            self.gles_renderer
+                                .as_mut()
+                                .unwrap()
+                                .import_buffer(buffer, Some(surface_data), &damage)
+                                .and_then(|t| t.ok())
+                        }
                         _ => None,
                     });
 
-                let (texture_id, size) = if let Some(texture) = texture {
-                    unsafe {
-                        self.gl.as_ref().unwrap().Finish();
+                let Some(texture) = texture else {
+                    // The buffer is never going to be read.
+                    if let Some(release_point) = release_point {
+                        let _ = release_point.signal();
                     }
+                    return None;
+                };
 
-                    let size = texture.size();
+                let size = texture.size();
 
-                    let size_changed = match old_texture_size {
-                        Some(old_size) => old_size != size,
-                        None => true,
-                    };
+                let size_changed = match old_texture_size {
+                    Some(old_size) => old_size != size,
+                    None => true,
+                };
 
-                    surface_data
-                        .data_map
-                        .get::<RefCell<WlSurfaceVeshellState>>()
-                        .unwrap()
-                        .borrow_mut()
-                        .old_texture_size = Some(size);
-
-                    let texture_id = match size_changed {
-                        true => None,
-                        false => self
-                            .texture_ids_per_surface_id
-                            .get(&surface_id)
-                            .and_then(|v| v.last().cloned())
-                            .map(|(id, _)| id),
-                    };
-
-                    let texture_id = texture_id.unwrap_or_else(|| {
-                        let texture_id = self.get_new_texture_id();
-                        while self
-                            .texture_ids_per_surface_id
-                            .entry(surface_id)
-                            .or_default()
-                            .len()
-                            >= 2
-                        {
-                            self.texture_ids_per_surface_id
-                                .entry(surface_id)
-                                .or_default()
-                                .remove(0);
+                surface_data
+                    .data_map
+                    .get::<RefCell<WlSurfaceVeshellState>>()
+                    .unwrap()
+                    .borrow_mut()
+                    .old_texture_size = Some(size);
+
+                let texture_id = self.surface_texture_id(surface_id, size, size_changed);
+
+                let texture = match image_description {
+                    Some(ref description) => {
+                        // Tone mapping samples the buffer on the compositor context.
+                        if let Some(acquire_fence) = acquire_fence.take() {
+                            let gles_renderer = self.gles_renderer.as_ref().unwrap();
+                            unsafe {
+                                wait_for_sync_file(
+                                    gles_renderer.egl_context().display(),
+                                    acquire_fence,
+                                );
+                            }
                         }
+                        self.tone_map(texture_id, texture, description)
+                    }
+                    None => texture,
+                };
+                self.set_surface_release_point(surface_id, release_point);
+
+                // Flutter waits for the fences on its own context, the compositor doesn't stall.
+                let mut fences: Vec<_> = acquire_fence
+                    .map(TextureFence::SyncFile)
+                    .into_iter()
+                    .collect();
+                let gl = self.gl.as_ref().unwrap();
+                unsafe {
+                    let fence = gl.FenceSync(ffi::SYNC_GPU_COMMANDS_COMPLETE, 0);
+                    gl.Flush();
+                    if !fence.is_null() {
+                        fences.push(TextureFence::Gl(fence));
+                    }
+                }
 
-                        self.texture_ids_per_surface_id
-                            .entry(surface_id)
-                            .or_default()
-                            .push((texture_id, size));
-                        self.surface_id_per_texture_id
-                            .insert(texture_id, surface_id);
-                        self.flutter_engine_mut()
-                            .register_external_texture(texture_id)
-                            .unwrap();
-                        texture_id
-                    });
+                let swapchain = self.texture_swapchains.entry(texture_id).or_default();
+                swapchain.commit(self.gl.as_ref().unwrap(), texture, fences);
 
-                    let swapchain = self.texture_swapchains.entry(texture_id).or_default();
-                    swapchain.commit(texture.clone());
+                // A new texture has to be sampled entirely.
+                let buffer_damage = if size_changed {
+                    vec![Rectangle::from_loc_and_size((0, 0), size)]
+                } else {
+                    current_buffer_damage(attributes, size)
+                };
 
+                // Flutter keeps sampling the previous frame if nothing changed.
+                if !buffer_damage.is_empty() {
                     self.flutter_engine_mut()
                         .mark_external_texture_frame_available(texture_id)
                         .unwrap();
+                }
 
-                    (texture_id, Some(size))
-                } else {
-                    (-1, None)
-                };
-
-                (
-                    surface_id,
-                    texture_id,
-                    size,
-                    attributes.buffer_delta,
-                    attributes.buffer_scale,
-                    attributes.input_region.clone(),
-                )
+                Some(buffer_damage)
             });
-            let surface_message = self.construct_surface_message(surface);
+            // Keep track of the buffer so that the backend can scan it out directly
+            // when the shell reports this surface as covering an entire output.
+            on_commit_buffer_handler::<Self>(surface);
 
-            let platform_method_channel = &mut self.flutter_engine_mut().platform_method_channel;
-            platform_method_channel.invoke_method(
-                "commit_surface",
-                Some(Box::new(json!(surface_message))),
-                None,
-            );
+            let surface_id = get_surface_id(surface);
+            if surface_allows_tearing(surface) {
+                self.tearing_surface_ids_committed.insert(surface_id);
+            }
+
+            self.send_surface_message(surface, buffer_damage);
         }
 
-        fn destroyed(&mut self, _surface: &WlSurface) {
-            let surface_id = with_states(_surface, |surface_data| {
-                surface_data
-                    .data_map
-                    .get::<RefCell<WlSurfaceVeshellState>>()
-                    .unwrap()
-                    .borrow()
-                    .surface_id
-            });
-            self.surfaces.remove(&surface_id);
+        /// `buffer_damage` is `None` when the buffer didn't change.
+        fn send_surface_message(
+            &mut self,
+            surface: &WlSurface,
+            buffer_damage: Option<Vec<Rectangle<i32, BufferCoords>>>,
+        ) {
+            let mut surface_message = self.construct_surface_message(surface);
+            surface_message.buffer_damage =
+                buffer_damage.map(|damage| damage.into_iter().map(Into::into).collect());
 
             let platform_method_channel = &mut self.flutter_engine_mut().platform_method_channel;
             platform_method_channel.invoke_method(
-                "destroy_surface",
-                Some(Box::new(json!({
-                    "surfaceId": surface_id,
-                }))),
+                "commit_surface",
+                Some(Box::new(json!(surface_message))),
                 None,
             );
         }
     }
+
+    /// The damage of the current commit in buffer coordinates, clipped to the buffer.
+    fn current_buffer_damage(
+        attributes: &SurfaceAttributes,
+        buffer_size: Size<i32, BufferCoords>,
+    ) -> Vec<Rectangle<i32, BufferCoords>> {
+        let transform = attributes.buffer_transform.into();
+        let surface_size = buffer_size.to_logical(attributes.buffer_scale, transform);
+        let buffer_rect = Rectangle::from_loc_and_size((0, 0), buffer_size);
+        attributes
+            .damage
+            .iter()
+            .map(|damage| match damage {
+                Damage::Buffer(rect) => *rect,
+                Damage::Surface(rect) => {
+                    rect.to_buffer(attributes.buffer_scale, transform, &surface_size)
+                }
+            })
+            .filter_map(|rect| rect.intersection(buffer_rect))
+            .collect()
+    }
 }
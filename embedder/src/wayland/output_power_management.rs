@@ -0,0 +1,330 @@
+pub mod output_power_management {
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    use serde_json::json;
+    use smithay::{
+        output::Output,
+        reexports::{
+            calloop::{
+                timer::{TimeoutAction, Timer},
+                RegistrationToken,
+            },
+            wayland_protocols_wlr::output_power_management::v1::server::{
+                zwlr_output_power_manager_v1::{self, ZwlrOutputPowerManagerV1},
+                zwlr_output_power_v1::{self, Mode, ZwlrOutputPowerV1},
+            },
+            wayland_server::{
+                backend::{ClientId, GlobalId},
+                Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+            },
+        },
+    };
+    use tracing::{info, warn};
+
+    use crate::{
+        state::State,
+        wayland::security_context::security_context::{
+            can_view_privileged_global, PrivilegedProtocol,
+        },
+        Backend,
+    };
+
+    pub struct OutputPowerManagementState {
+        global: GlobalId,
+        /// Every client gets told when the power of the output it watches changes.
+        power_controls: Vec<(String, ZwlrOutputPowerV1)>,
+        powered_off_outputs: HashSet<String>,
+        /// Blank the outputs after this long without input, `None` never does.
+        idle_blank_timeout: Option<Duration>,
+        idle_blank_timer: Option<RegistrationToken>,
+        /// Outputs turned off by the idle timeout, which any input turns back on.
+        idle_blanked_outputs: Vec<String>,
+    }
+
+    impl OutputPowerManagementState {
+        pub fn new<BackendData: Backend + 'static>(display_handle: &DisplayHandle) -> Self {
+            let global = display_handle
+                .create_global::<State<BackendData>, ZwlrOutputPowerManagerV1, _>(1, ());
+            Self {
+                global,
+                power_controls: Vec::new(),
+                powered_off_outputs: HashSet::new(),
+                idle_blank_timeout: None,
+                idle_blank_timer: None,
+                idle_blanked_outputs: Vec::new(),
+            }
+        }
+
+        pub fn global(&self) -> GlobalId {
+            self.global.clone()
+        }
+
+        pub fn is_powered_on(&self, output: &Output) -> bool {
+            !self.powered_off_outputs.contains(&output.name())
+        }
+    }
+
+    /// Name of the output whose power mode this object sets.
+    /// `None` if the output was already gone when the client asked for the object.
+    pub struct OutputPowerData {
+        output_name: Option<String>,
+    }
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// Turns the display of `output` on or off, and tells the clients and the shell about it.
+        pub fn set_output_power(&mut self, output: &Output, on: bool) -> std::io::Result<()> {
+            let state = &mut self.output_power_management_state;
+            if state.is_powered_on(output) == on {
+                return Ok(());
+            }
+
+            self.backend_data.set_output_power(output, on)?;
+            info!(
+                "Powered {} {}",
+                output.name(),
+                if on { "on" } else { "off" }
+            );
+
+            if on {
+                self.restore_gamma(output);
+            }
+
+            let state = &mut self.output_power_management_state;
+            if on {
+                state.powered_off_outputs.remove(&output.name());
+            } else {
+                state.powered_off_outputs.insert(output.name());
+            }
+            let mode = if on { Mode::On } else { Mode::Off };
+            for (_, power_control) in state
+                .power_controls
+                .iter()
+                .filter(|(output_name, _)| *output_name == output.name())
+            {
+                power_control.mode(mode);
+            }
+
+            self.flutter_engine_mut()
+                .platform_method_channel
+                .invoke_method(
+                    "monitor_power_changed",
+                    Some(Box::new(json!({
+                        "monitorName": output.name(),
+                        "on": on,
+                    }))),
+                    None,
+                );
+            Ok(())
+        }
+
+        /// Forgets the power state of an unplugged output, and lets the clients watching it know.
+        pub fn output_power_gone(&mut self, output: &Output) {
+            let state = &mut self.output_power_management_state;
+            state.powered_off_outputs.remove(&output.name());
+            state
+                .idle_blanked_outputs
+                .retain(|output_name| *output_name != output.name());
+            state.power_controls.retain(|(output_name, power_control)| {
+                if *output_name == output.name() {
+                    power_control.failed();
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
+        pub fn set_idle_blank_timeout(&mut self, timeout: Option<Duration>) {
+            let state = &mut self.output_power_management_state;
+            state.idle_blank_timeout = timeout;
+            if let Some(token) = state.idle_blank_timer.take() {
+                self.loop_handle.remove(token);
+            }
+            let Some(timeout) = timeout else {
+                return;
+            };
+
+            // The timer only checks how long ago the last input was,
+            // so that input doesn't have to reschedule it every time.
+            self.output_power_management_state.idle_blank_timer = self
+                .loop_handle
+                .insert_source(Timer::from_duration(timeout), |_, _, data| {
+                    data.check_idle_blanking()
+                })
+                .ok();
+        }
+
+        fn check_idle_blanking(&mut self) -> TimeoutAction {
+            let state = &self.output_power_management_state;
+            let Some(timeout) = state.idle_blank_timeout else {
+                return TimeoutAction::Drop;
+            };
+            let idle_time = self.idle_state.last_input_time.elapsed();
+            if idle_time < timeout {
+                return TimeoutAction::ToDuration(timeout - idle_time);
+            }
+
+            if state.idle_blanked_outputs.is_empty() && !self.idle_state.is_inhibited() {
+                let outputs = self
+                    .all_outputs()
+                    .into_iter()
+                    .filter(|output| self.output_power_management_state.is_powered_on(output))
+                    .collect::<Vec<_>>();
+                for output in outputs {
+                    match self.set_output_power(&output, false) {
+                        Ok(()) => self
+                            .output_power_management_state
+                            .idle_blanked_outputs
+                            .push(output.name()),
+                        Err(err) => warn!("Failed to blank {}: {}", output.name(), err),
+                    }
+                }
+            }
+            TimeoutAction::ToDuration(timeout)
+        }
+
+        /// Any input wakes the outputs blanked by the idle timeout.
+        pub fn wake_idle_blanked_outputs(&mut self) {
+            let state = &mut self.output_power_management_state;
+            if state.idle_blanked_outputs.is_empty() {
+                return;
+            }
+
+            let output_names = std::mem::take(&mut state.idle_blanked_outputs);
+            let outputs = self
+                .all_outputs()
+                .into_iter()
+                .filter(|output| output_names.contains(&output.name()))
+                .collect::<Vec<_>>();
+            for output in outputs {
+                if let Err(err) = self.set_output_power(&output, true) {
+                    warn!("Failed to wake {}: {}", output.name(), err);
+                }
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> GlobalDispatch<ZwlrOutputPowerManagerV1, ()>
+        for State<BackendData>
+    {
+        fn bind(
+            _state: &mut Self,
+            _handle: &DisplayHandle,
+            _client: &Client,
+            resource: New<ZwlrOutputPowerManagerV1>,
+            _global_data: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            data_init.init(resource, ());
+        }
+
+        fn can_view(client: Client, _global_data: &()) -> bool {
+            can_view_privileged_global(&client, PrivilegedProtocol::OutputManagement)
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwlrOutputPowerManagerV1, ()> for State<BackendData> {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            _manager: &ZwlrOutputPowerManagerV1,
+            request: zwlr_output_power_manager_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                zwlr_output_power_manager_v1::Request::GetOutputPower { id, output } => {
+                    let output = Output::from_resource(&output);
+                    let Some(output) = output else {
+                        let power_control =
+                            data_init.init(id, OutputPowerData { output_name: None });
+                        power_control.failed();
+                        return;
+                    };
+
+                    let power_control = data_init.init(
+                        id,
+                        OutputPowerData {
+                            output_name: Some(output.name()),
+                        },
+                    );
+                    let on = state.output_power_management_state.is_powered_on(&output);
+                    power_control.mode(if on { Mode::On } else { Mode::Off });
+                    state
+                        .output_power_management_state
+                        .power_controls
+                        .push((output.name(), power_control));
+                }
+                zwlr_output_power_manager_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwlrOutputPowerV1, OutputPowerData>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            power_control: &ZwlrOutputPowerV1,
+            request: zwlr_output_power_v1::Request,
+            data: &OutputPowerData,
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                zwlr_output_power_v1::Request::SetMode { mode } => {
+                    let Some(ref output_name) = data.output_name else {
+                        return;
+                    };
+                    let on = match mode {
+                        WEnum::Value(Mode::On) => true,
+                        WEnum::Value(Mode::Off) => false,
+                        _ => {
+                            power_control.post_error(
+                                zwlr_output_power_v1::Error::InvalidMode,
+                                "Unknown power mode",
+                            );
+                            return;
+                        }
+                    };
+                    let output = state
+                        .all_outputs()
+                        .into_iter()
+                        .find(|output| output.name() == *output_name);
+                    let Some(output) = output else {
+                        power_control.failed();
+                        return;
+                    };
+
+                    // A client waking the outputs takes over from the idle timeout.
+                    state
+                        .output_power_management_state
+                        .idle_blanked_outputs
+                        .retain(|name| name != output_name);
+                    if let Err(err) = state.set_output_power(&output, on) {
+                        warn!("Failed to set the power of {}: {}", output_name, err);
+                        power_control.failed();
+                    }
+                }
+                zwlr_output_power_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+
+        fn destroyed(
+            state: &mut Self,
+            _client: ClientId,
+            power_control: &ZwlrOutputPowerV1,
+            _data: &OutputPowerData,
+        ) {
+            state
+                .output_power_management_state
+                .power_controls
+                .retain(|(_, other)| other != power_control);
+        }
+    }
+}
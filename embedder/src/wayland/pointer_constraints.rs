@@ -0,0 +1,217 @@
+pub mod pointer_constraints {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use smithay::{
+        delegate_pointer_constraints,
+        desktop::utils::bbox_from_surface_tree,
+        input::pointer::{MotionEvent, PointerHandle},
+        reexports::wayland_server::protocol::wl_surface::WlSurface,
+        utils::{Logical, Point, SERIAL_COUNTER},
+        wayland::{
+            pointer_constraints::{
+                with_pointer_constraint, PointerConstraint, PointerConstraintsHandler,
+            },
+            seat::WaylandFocus,
+        },
+    };
+    use tracing::info;
+
+    use crate::{state::State, wayland::wayland::get_surface_id, Backend};
+
+    /// The constraint of the surface under the pointer, while the pointer is in its region.
+    pub struct ActivePointerConstraint {
+        surface_id: u64,
+        locked: bool,
+        /// Where the client wants the pointer once it unlocks it, relative to the surface.
+        cursor_position_hint: Option<Point<f64, Logical>>,
+    }
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// The focused surface, where the shell displays it, and how many surface pixels make a logical pixel.
+        fn pointer_focus_surface(&self) -> Option<(WlSurface, Point<f64, Logical>, f64)> {
+            let (focus, origin) = self.pointer_focus.as_ref()?;
+            let surface = focus.wl_surface()?.into_owned();
+            let scale = self.surface_pixel_scale(&surface);
+            Some((surface, *origin, scale))
+        }
+
+        pub fn is_pointer_locked(&self) -> bool {
+            self.active_pointer_constraint
+                .as_ref()
+                .map(|constraint| constraint.locked)
+                .unwrap_or(false)
+        }
+
+        /// Activates the constraint of the focused surface once the pointer enters its region,
+        /// and lets go of the previous one when the focus moves or the client drops it.
+        /// Called after every pointer motion and focus change.
+        pub fn update_pointer_constraint(&mut self) {
+            let pointer = self.pointer.clone();
+            let location = pointer.current_location();
+            let focus = self.pointer_focus_surface();
+
+            if let Some(active) = self.active_pointer_constraint.as_ref() {
+                let still_active = focus.as_ref().is_some_and(|(surface, _, _)| {
+                    get_surface_id(surface) == active.surface_id
+                        && with_pointer_constraint(surface, &pointer, |constraint| {
+                            constraint.is_some_and(|constraint| constraint.is_active())
+                        })
+                });
+                if still_active {
+                    return;
+                }
+                self.deactivate_pointer_constraint();
+            }
+
+            let Some((surface, origin, scale)) = focus else {
+                return;
+            };
+            let local_location = (location - origin).upscale(scale).to_i32_round();
+            let locked = with_pointer_constraint(&surface, &pointer, |constraint| {
+                let constraint = constraint?;
+                if !constraint
+                    .region()
+                    .map_or(true, |region| region.contains(local_location))
+                {
+                    return None;
+                }
+                constraint.activate();
+                Some(matches!(&*constraint, PointerConstraint::Locked(_)))
+            });
+            let Some(locked) = locked else {
+                return;
+            };
+
+            let surface_id = get_surface_id(&surface);
+            info!(
+                "Pointer {} to surface {}",
+                if locked { "locked" } else { "confined" },
+                surface_id
+            );
+            self.active_pointer_constraint = Some(ActivePointerConstraint {
+                surface_id,
+                locked,
+                cursor_position_hint: None,
+            });
+            self.pointer_constraint_changed(Some(surface_id), locked);
+        }
+
+        fn deactivate_pointer_constraint(&mut self) {
+            let Some(active) = self.active_pointer_constraint.take() else {
+                return;
+            };
+            let pointer = self.pointer.clone();
+            if let Some(surface) = self.surfaces.get(&active.surface_id).cloned() {
+                with_pointer_constraint(&surface, &pointer, |constraint| {
+                    if let Some(constraint) = constraint {
+                        constraint.deactivate();
+                    }
+                });
+            }
+            self.pointer_constraint_changed(None, false);
+
+            // Games usually leave the pointer where their own cursor was.
+            let hint = active.cursor_position_hint.zip(
+                self.pointer_focus_surface()
+                    .filter(|(surface, _, _)| get_surface_id(surface) == active.surface_id),
+            );
+            if let Some((hint, (_, origin, scale))) = hint {
+                let location = self.clamp_coords(origin + hint.downscale(scale));
+                pointer.motion(
+                    self,
+                    self.pointer_focus_at(location),
+                    &MotionEvent {
+                        location,
+                        serial: SERIAL_COUNTER.next_serial(),
+                        time: Duration::from(self.clock.now()).as_millis() as u32,
+                    },
+                );
+                pointer.frame(self);
+                self.send_motion_event(location);
+            }
+        }
+
+        /// Keeps a confined pointer in the region of its surface, sliding along the edges.
+        /// Returns the current location if there's no way to get closer.
+        pub fn confine_pointer(&self, location: Point<f64, Logical>) -> Point<f64, Logical> {
+            let Some(active) = self.active_pointer_constraint.as_ref() else {
+                return location;
+            };
+            if active.locked {
+                return location;
+            }
+            let Some((surface, origin, scale)) = self.pointer_focus_surface() else {
+                return location;
+            };
+
+            let bounds = bbox_from_surface_tree(&surface, (0, 0));
+            if bounds.is_empty() {
+                return location;
+            }
+            let pointer = self.pointer.clone();
+            let is_inside = |location: Point<f64, Logical>| {
+                let local_location = (location - origin).upscale(scale).to_i32_round();
+                bounds.contains(local_location)
+                    && with_pointer_constraint(&surface, &pointer, |constraint| {
+                        constraint
+                            .and_then(|constraint| constraint.region().cloned())
+                            .map_or(true, |region| region.contains(local_location))
+                    })
+            };
+
+            if is_inside(location) {
+                return location;
+            }
+            let clamped = Point::<f64, Logical>::from((
+                location.x.clamp(
+                    origin.x + bounds.loc.x as f64 / scale,
+                    origin.x + (bounds.loc.x + bounds.size.w - 1) as f64 / scale,
+                ),
+                location.y.clamp(
+                    origin.y + bounds.loc.y as f64 / scale,
+                    origin.y + (bounds.loc.y + bounds.size.h - 1) as f64 / scale,
+                ),
+            ));
+            if is_inside(clamped) {
+                clamped
+            } else {
+                pointer.current_location()
+            }
+        }
+
+        /// The shell ignores screen edges and hot corners while the pointer is constrained.
+        fn pointer_constraint_changed(&mut self, surface_id: Option<u64>, locked: bool) {
+            self.flutter_engine_mut()
+                .platform_method_channel
+                .invoke_method(
+                    "pointer_constraint_changed",
+                    Some(Box::new(json!({
+                        "surfaceId": surface_id,
+                        "locked": locked,
+                    }))),
+                    None,
+                );
+        }
+    }
+
+    impl<BackendData: Backend + 'static> PointerConstraintsHandler for State<BackendData> {
+        fn new_constraint(&mut self, _surface: &WlSurface, _pointer: &PointerHandle<Self>) {
+            self.update_pointer_constraint();
+        }
+
+        fn cursor_position_hint(
+            &mut self,
+            surface: &WlSurface,
+            _pointer: &PointerHandle<Self>,
+            location: Point<f64, Logical>,
+        ) {
+            if let Some(active) = self.active_pointer_constraint.as_mut() {
+                if active.locked && active.surface_id == get_surface_id(surface) {
+                    active.cursor_position_hint = Some(location);
+                }
+            }
+        }
+    }
+    delegate_pointer_constraints!(@<BackendData: Backend + 'static> State<BackendData>);
+}
@@ -0,0 +1,191 @@
+pub mod security_context {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+
+    use smithay::{
+        delegate_security_context,
+        reexports::wayland_server::Client,
+        wayland::security_context::{
+            SecurityContext, SecurityContextHandler, SecurityContextListenerSource,
+        },
+    };
+    use tracing::{info, warn};
+
+    use crate::{state::State, Backend, ClientState};
+
+    /// Protocols that let a client snoop on or drive the whole session.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum PrivilegedProtocol {
+        DataControl,
+        OutputManagement,
+        VirtualInput,
+        /// Lists the windows of every app, and lets taskbars activate or close them.
+        ForeignToplevel,
+        DrmLease,
+    }
+
+    impl PrivilegedProtocol {
+        const ALL: [PrivilegedProtocol; 5] = [
+            PrivilegedProtocol::DataControl,
+            PrivilegedProtocol::OutputManagement,
+            PrivilegedProtocol::VirtualInput,
+            PrivilegedProtocol::ForeignToplevel,
+            PrivilegedProtocol::DrmLease,
+        ];
+    }
+
+    /// Clients connecting to the main socket are trusted with every privileged protocol.
+    /// Sandboxed clients only get the ones the shell allowed for their app id.
+    #[derive(Default)]
+    pub struct ClientPolicy {
+        allowed_protocols_per_app_id: HashMap<String, HashSet<PrivilegedProtocol>>,
+    }
+
+    impl ClientPolicy {
+        pub fn allowed_protocols(
+            &self,
+            security_context: Option<&SecurityContext>,
+        ) -> HashSet<PrivilegedProtocol> {
+            let Some(security_context) = security_context else {
+                return PrivilegedProtocol::ALL.into_iter().collect();
+            };
+            security_context
+                .app_id
+                .as_ref()
+                .and_then(|app_id| self.allowed_protocols_per_app_id.get(app_id))
+                .cloned()
+                .unwrap_or_default()
+        }
+    }
+
+    /// The filter of privileged globals. XWayland is trusted like the clients of the main socket.
+    pub fn can_view_privileged_global(client: &Client, protocol: PrivilegedProtocol) -> bool {
+        client
+            .get_data::<ClientState>()
+            .map_or(true, |client_state| {
+                client_state.privileged_protocols.contains(&protocol)
+            })
+    }
+
+    /// Sandboxed clients can't create security contexts of their own to escape their restrictions.
+    pub fn can_view_security_context_global(client: &Client) -> bool {
+        client
+            .get_data::<ClientState>()
+            .map_or(true, |client_state| client_state.security_context.is_none())
+    }
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// Only applies to clients connecting afterwards,
+        /// the globals a client sees can't be taken back once it bound them.
+        pub fn set_client_policy(&mut self, app_id: String, allowed: HashSet<PrivilegedProtocol>) {
+            info!("Privileged protocols allowed for {}: {:?}", app_id, allowed);
+            self.client_policy
+                .allowed_protocols_per_app_id
+                .insert(app_id, allowed);
+        }
+    }
+
+    impl<BackendData: Backend + 'static> SecurityContextHandler for State<BackendData> {
+        fn context_created(
+            &mut self,
+            source: SecurityContextListenerSource,
+            security_context: SecurityContext,
+        ) {
+            info!(
+                "Security context created for {:?} by {:?}",
+                security_context.app_id, security_context.sandbox_engine
+            );
+            let result = self
+                .loop_handle
+                .insert_source(source, move |client_stream, _, data| {
+                    let client_state =
+                        ClientState::new(Some(security_context.clone()), &data.client_policy);
+                    if let Err(err) = data
+                        .display_handle
+                        .insert_client(client_stream, Arc::new(client_state))
+                    {
+                        warn!("Error adding sandboxed wayland client: {}", err);
+                    };
+                });
+            if let Err(err) = result {
+                warn!("Failed to listen on the security context socket: {}", err);
+            }
+        }
+    }
+    delegate_security_context!(@<BackendData: Backend + 'static> State<BackendData>);
+
+    #[cfg(test)]
+    mod tests {
+        use std::os::unix::net::UnixStream;
+        use std::sync::Arc;
+
+        use smithay::reexports::wayland_server::Display;
+        use smithay::wayland::security_context::SecurityContext;
+
+        use super::*;
+
+        fn insert_client(
+            security_context: Option<SecurityContext>,
+            policy: &ClientPolicy,
+        ) -> Client {
+            let display = Display::<()>::new().unwrap();
+            let (stream, _other_end) = UnixStream::pair().unwrap();
+            display
+                .handle()
+                .insert_client(stream, Arc::new(ClientState::new(security_context, policy)))
+                .unwrap()
+        }
+
+        fn sandbox(app_id: &str) -> SecurityContext {
+            SecurityContext {
+                sandbox_engine: Some("org.flatpak".to_string()),
+                app_id: Some(app_id.to_string()),
+                instance_id: None,
+            }
+        }
+
+        #[test]
+        fn sandboxed_clients_cant_bind_privileged_globals() {
+            let policy = ClientPolicy::default();
+            let client = insert_client(Some(sandbox("org.example.App")), &policy);
+            for protocol in PrivilegedProtocol::ALL {
+                assert!(
+                    !can_view_privileged_global(&client, protocol),
+                    "{:?}",
+                    protocol
+                );
+            }
+        }
+
+        #[test]
+        fn sandboxed_clients_bind_what_the_shell_allowed() {
+            let mut policy = ClientPolicy::default();
+            policy.allowed_protocols_per_app_id.insert(
+                "org.example.Taskbar".to_string(),
+                HashSet::from([PrivilegedProtocol::ForeignToplevel]),
+            );
+            let client = insert_client(Some(sandbox("org.example.Taskbar")), &policy);
+            assert!(can_view_privileged_global(
+                &client,
+                PrivilegedProtocol::ForeignToplevel
+            ));
+            assert!(!can_view_privileged_global(
+                &client,
+                PrivilegedProtocol::DrmLease
+            ));
+        }
+
+        #[test]
+        fn main_socket_clients_bind_every_privileged_global() {
+            let client = insert_client(None, &ClientPolicy::default());
+            for protocol in PrivilegedProtocol::ALL {
+                assert!(
+                    can_view_privileged_global(&client, protocol),
+                    "{:?}",
+                    protocol
+                );
+            }
+        }
+    }
+}
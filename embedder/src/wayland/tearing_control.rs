@@ -0,0 +1,177 @@
+pub mod tearing_control {
+    use std::cell::RefCell;
+
+    use smithay::{
+        reexports::{
+            wayland_protocols::wp::tearing_control::v1::server::{
+                wp_tearing_control_manager_v1::{self, WpTearingControlManagerV1},
+                wp_tearing_control_v1::{self, WpTearingControlV1},
+            },
+            wayland_server::{
+                backend::GlobalId, protocol::wl_surface::WlSurface, Client, DataInit, Dispatch,
+                DisplayHandle, GlobalDispatch, New, Resource, WEnum, Weak,
+            },
+        },
+        wayland::compositor::{with_states, Cacheable},
+    };
+
+    use crate::{state::State, Backend};
+
+    /// Double-buffered presentation hint of a surface, applied on commit.
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct TearingControlCachedState {
+        pub is_async: bool,
+    }
+
+    impl Cacheable for TearingControlCachedState {
+        fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+            *self
+        }
+
+        fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+            *into = self;
+        }
+    }
+
+    #[derive(Default)]
+    struct TearingControlSurfaceData {
+        is_bound: bool,
+    }
+
+    pub struct TearingControlManagerState {
+        global: GlobalId,
+    }
+
+    impl TearingControlManagerState {
+        pub fn new<BackendData: Backend + 'static>(display_handle: &DisplayHandle) -> Self {
+            let global = display_handle
+                .create_global::<State<BackendData>, WpTearingControlManagerV1, _>(1, ());
+            Self { global }
+        }
+
+        pub fn global(&self) -> GlobalId {
+            self.global.clone()
+        }
+    }
+
+    /// Whether the client asked for this surface to be presented as soon as possible,
+    /// even if it means tearing.
+    pub fn surface_allows_tearing(surface: &WlSurface) -> bool {
+        with_states(surface, |surface_data| {
+            surface_data
+                .cached_state
+                .get::<TearingControlCachedState>()
+                .current()
+                .is_async
+        })
+    }
+
+    impl<BackendData: Backend + 'static> GlobalDispatch<WpTearingControlManagerV1, ()>
+        for State<BackendData>
+    {
+        fn bind(
+            _state: &mut Self,
+            _handle: &DisplayHandle,
+            _client: &Client,
+            resource: New<WpTearingControlManagerV1>,
+            _global_data: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            data_init.init(resource, ());
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<WpTearingControlManagerV1, ()>
+        for State<BackendData>
+    {
+        fn request(
+            _state: &mut Self,
+            _client: &Client,
+            manager: &WpTearingControlManagerV1,
+            request: wp_tearing_control_manager_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                wp_tearing_control_manager_v1::Request::GetTearingControl { id, surface } => {
+                    let already_bound = with_states(&surface, |surface_data| {
+                        surface_data.data_map.insert_if_missing(|| {
+                            RefCell::new(TearingControlSurfaceData::default())
+                        });
+                        let mut tearing_control_data = surface_data
+                            .data_map
+                            .get::<RefCell<TearingControlSurfaceData>>()
+                            .unwrap()
+                            .borrow_mut();
+                        std::mem::replace(&mut tearing_control_data.is_bound, true)
+                    });
+
+                    if already_bound {
+                        manager.post_error(
+                            wp_tearing_control_manager_v1::Error::TearingControlExists,
+                            "The surface already has a tearing control object",
+                        );
+                        return;
+                    }
+
+                    data_init.init(id, surface.downgrade());
+                }
+                wp_tearing_control_manager_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<WpTearingControlV1, Weak<WlSurface>>
+        for State<BackendData>
+    {
+        fn request(
+            _state: &mut Self,
+            _client: &Client,
+            _resource: &WpTearingControlV1,
+            request: wp_tearing_control_v1::Request,
+            surface: &Weak<WlSurface>,
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            // The object becomes inert once its surface is destroyed.
+            let Ok(surface) = surface.upgrade() else {
+                return;
+            };
+
+            match request {
+                wp_tearing_control_v1::Request::SetPresentationHint { hint } => {
+                    let is_async = matches!(
+                        hint,
+                        WEnum::Value(wp_tearing_control_v1::PresentationHint::Async)
+                    );
+                    with_states(&surface, |surface_data| {
+                        surface_data
+                            .cached_state
+                            .get::<TearingControlCachedState>()
+                            .pending()
+                            .is_async = is_async;
+                    });
+                }
+                wp_tearing_control_v1::Request::Destroy => {
+                    // Destroying the object reverts the surface to vsync presentation.
+                    with_states(&surface, |surface_data| {
+                        surface_data
+                            .cached_state
+                            .get::<TearingControlCachedState>()
+                            .pending()
+                            .is_async = false;
+                        if let Some(tearing_control_data) = surface_data
+                            .data_map
+                            .get::<RefCell<TearingControlSurfaceData>>()
+                        {
+                            tearing_control_data.borrow_mut().is_bound = false;
+                        }
+                    });
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
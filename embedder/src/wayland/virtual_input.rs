@@ -0,0 +1,762 @@
+pub mod virtual_input {
+    use std::collections::{HashMap, HashSet};
+    use std::fs::File;
+    use std::os::unix::fs::FileExt;
+
+    use input_linux::sys::{KEY_LEFTALT, KEY_LEFTCTRL, KEY_LEFTMETA, KEY_LEFTSHIFT, KEY_RIGHTALT};
+    use serde_json::json;
+    use smithay::{
+        backend::input::{Axis, AxisSource, ButtonState, KeyState},
+        input::{
+            keyboard::{xkb, Keysym},
+            pointer::AxisFrame,
+        },
+        reexports::{
+            wayland_protocols_misc::zwp_virtual_keyboard_v1::server::{
+                zwp_virtual_keyboard_manager_v1::{self, ZwpVirtualKeyboardManagerV1},
+                zwp_virtual_keyboard_v1::{self, ZwpVirtualKeyboardV1},
+            },
+            wayland_protocols_wlr::virtual_pointer::v1::server::{
+                zwlr_virtual_pointer_manager_v1::{self, ZwlrVirtualPointerManagerV1},
+                zwlr_virtual_pointer_v1::{self, ZwlrVirtualPointerV1},
+            },
+            wayland_server::{
+                backend::{ClientId, GlobalId, ObjectId},
+                protocol::wl_pointer,
+                Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+            },
+        },
+        wayland::security_context::SecurityContext,
+    };
+    use tracing::{info, warn};
+
+    use crate::{
+        state::{State, KEYBOARD_LAYOUT},
+        wayland::security_context::security_context::{
+            can_view_privileged_global, PrivilegedProtocol,
+        },
+        Backend, ClientState,
+    };
+
+    /// Modifiers of virtual keyboards and the keys holding them on the seat.
+    /// `handle_key_event` swaps the left Meta and Alt keys, so Alt is held with Meta and the other way around.
+    const SEAT_MODIFIER_KEYS: [(&str, u32); 4] = [
+        (xkb::MOD_NAME_SHIFT, KEY_LEFTSHIFT as u32),
+        (xkb::MOD_NAME_CTRL, KEY_LEFTCTRL as u32),
+        (xkb::MOD_NAME_ALT, KEY_LEFTMETA as u32),
+        (xkb::MOD_NAME_LOGO, KEY_LEFTALT as u32),
+    ];
+
+    /// The modifiers picking the shift levels of the seat keymap, from the first to the fourth.
+    /// AltGr is the right Alt key in `KEYBOARD_LAYOUT`.
+    const LEVEL_MODIFIER_KEYS: [&[u32]; 4] = [
+        &[],
+        &[KEY_LEFTSHIFT as u32],
+        &[KEY_RIGHTALT as u32],
+        &[KEY_LEFTSHIFT as u32, KEY_RIGHTALT as u32],
+    ];
+
+    /// Compiled keymaps are a few tens of KiB, anything bigger than this is refused.
+    const MAX_KEYMAP_SIZE: u64 = 1024 * 1024;
+
+    struct VirtualKeyboard {
+        /// The keymap of the client with the modifiers it set, `None` until it sends a keymap.
+        xkb_state: Option<xkb::State>,
+        /// The seat key codes the pressed keys were translated to,
+        /// so that they're released even if the modifiers changed in between.
+        pressed_keys: HashMap<u32, u32>,
+        /// The seat modifier keys held for the modifiers the client set.
+        held_modifier_keys: HashSet<u32>,
+    }
+
+    /// How to type a keysym with the seat keymap.
+    #[derive(Debug, Clone, Copy)]
+    struct SeatKey {
+        /// Evdev key code, before `handle_key_event` swaps the left Meta and Alt keys.
+        key_code: u32,
+        level: usize,
+    }
+
+    pub struct VirtualInputState {
+        keyboard_global: GlobalId,
+        pointer_global: GlobalId,
+        /// Every keysym the seat keymap can type, to find the key giving the keysym a virtual keyboard asks for.
+        seat_keys: HashMap<Keysym, SeatKey>,
+        /// The same keys by the character they type, for the Unicode keysyms of tools like wtype.
+        seat_keys_per_char: HashMap<u32, SeatKey>,
+        keyboards: HashMap<ObjectId, VirtualKeyboard>,
+        /// Axis events are grouped until the frame event.
+        pending_axis_frames: HashMap<ObjectId, AxisFrame>,
+        /// Which sandboxed apps may send input, as decided by the shell.
+        permissions: HashMap<String, bool>,
+        /// The shell is asked only once per app.
+        requested_permissions: HashSet<String>,
+    }
+
+    impl VirtualInputState {
+        pub fn new<BackendData: Backend + 'static>(display_handle: &DisplayHandle) -> Self {
+            let keyboard_global = display_handle
+                .create_global::<State<BackendData>, ZwpVirtualKeyboardManagerV1, _>(1, ());
+            let pointer_global = display_handle
+                .create_global::<State<BackendData>, ZwlrVirtualPointerManagerV1, _>(2, ());
+
+            let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+            let seat_keys = xkb::Keymap::new_from_names(
+                &context,
+                "",
+                "",
+                KEYBOARD_LAYOUT,
+                "",
+                None,
+                xkb::KEYMAP_COMPILE_NO_FLAGS,
+            )
+            .map(|seat_keymap| typeable_keys(&seat_keymap))
+            .unwrap_or_default();
+            let mut seat_keys_per_char = HashMap::<u32, SeatKey>::new();
+            for (&keysym, &seat_key) in &seat_keys {
+                let character = xkb::keysym_to_utf32(keysym);
+                if character == 0 {
+                    continue;
+                }
+                let entry = seat_keys_per_char.entry(character).or_insert(seat_key);
+                if seat_key.level < entry.level {
+                    *entry = seat_key;
+                }
+            }
+
+            Self {
+                keyboard_global,
+                pointer_global,
+                seat_keys,
+                seat_keys_per_char,
+                keyboards: HashMap::new(),
+                pending_axis_frames: HashMap::new(),
+                permissions: HashMap::new(),
+                requested_permissions: HashSet::new(),
+            }
+        }
+
+        pub fn keyboard_global(&self) -> GlobalId {
+            self.keyboard_global.clone()
+        }
+
+        pub fn pointer_global(&self) -> GlobalId {
+            self.pointer_global.clone()
+        }
+
+        /// Finds the key of the seat keymap giving the same keysym as `key` on the virtual keyboard.
+        /// `None` when the seat keymap can't type it, sending the key code as is would type something else.
+        fn translate_key(&self, xkb_state: &xkb::State, key: u32) -> Option<SeatKey> {
+            let keysym = xkb_state.key_get_one_sym(xkb::Keycode::new(key + 8));
+            if keysym == Keysym::NoSymbol {
+                return None;
+            }
+            self.seat_keys.get(&keysym).copied().or_else(|| {
+                let character = xkb::keysym_to_utf32(keysym);
+                (character != 0)
+                    .then(|| self.seat_keys_per_char.get(&character).copied())
+                    .flatten()
+            })
+        }
+    }
+
+    /// Finds the key and shift level of every keysym of the first layout,
+    /// preferring the ones that need the fewest modifiers.
+    fn typeable_keys(seat_keymap: &xkb::Keymap) -> HashMap<Keysym, SeatKey> {
+        let mut xkb_state = xkb::State::new(seat_keymap);
+        let mut seat_keys = HashMap::new();
+        for (level, modifier_keys) in LEVEL_MODIFIER_KEYS.iter().enumerate() {
+            let mut modifiers = 0;
+            for &modifier_key in *modifier_keys {
+                let name = if modifier_key == KEY_LEFTSHIFT as u32 {
+                    xkb::MOD_NAME_SHIFT
+                } else {
+                    // ISO_Level3_Shift
+                    "Mod5"
+                };
+                let index = seat_keymap.mod_get_index(name);
+                if index != xkb::MOD_INVALID {
+                    modifiers |= 1 << index;
+                }
+            }
+            xkb_state.update_mask(modifiers, 0, 0, 0, 0, 0);
+            seat_keymap.key_for_each(|_, keycode| {
+                let keysym = xkb_state.key_get_one_sym(keycode);
+                if keysym != Keysym::NoSymbol {
+                    seat_keys.entry(keysym).or_insert(SeatKey {
+                        key_code: keycode.raw() - 8,
+                        level,
+                    });
+                }
+            });
+        }
+        seat_keys
+    }
+
+    /// Sandboxed clients are identified to the shell by their app id, which decides whether they may send input.
+    /// Clients of the main socket are trusted, like with every other privileged protocol.
+    pub struct VirtualInputData {
+        security_context: Option<SecurityContext>,
+    }
+
+    impl VirtualInputData {
+        fn new(client: &Client) -> Self {
+            Self {
+                security_context: client
+                    .get_data::<ClientState>()
+                    .and_then(|client_state| client_state.security_context.clone()),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        pub fn set_virtual_input_permission(&mut self, app_id: String, allowed: bool) {
+            info!(
+                "Virtual input {} for {}",
+                if allowed { "allowed" } else { "denied" },
+                app_id
+            );
+            self.virtual_input_state.permissions.insert(app_id, allowed);
+        }
+
+        /// Devices of sandboxed clients the shell hasn't decided about yet are created,
+        /// but their events are dropped until the shell allows them.
+        fn is_virtual_input_allowed(&mut self, data: &VirtualInputData, device: &str) -> bool {
+            let Some(ref security_context) = data.security_context else {
+                return true;
+            };
+            // The shell couldn't tell which app it is.
+            let Some(ref app_id) = security_context.app_id else {
+                return false;
+            };
+            let state = &mut self.virtual_input_state;
+            if let Some(&allowed) = state.permissions.get(app_id) {
+                return allowed;
+            }
+            if state.requested_permissions.insert(app_id.clone()) {
+                self.flutter_engine_mut()
+                    .platform_method_channel
+                    .invoke_method(
+                        "virtual_input_requested",
+                        Some(Box::new(json!({
+                            "appId": app_id,
+                            "device": device,
+                        }))),
+                        None,
+                    );
+            }
+            false
+        }
+
+        /// Virtual keys take the same path as the physical ones, through Flutter first.
+        fn virtual_key(&mut self, keyboard_id: ObjectId, key: u32, state: KeyState, time: u32) {
+            let virtual_input_state = &self.virtual_input_state;
+            let Some(keyboard) = virtual_input_state.keyboards.get(&keyboard_id) else {
+                return;
+            };
+            let Some(ref xkb_state) = keyboard.xkb_state else {
+                return;
+            };
+
+            match state {
+                KeyState::Pressed => {
+                    let Some(seat_key) = virtual_input_state.translate_key(xkb_state, key) else {
+                        warn!(
+                            "The keymap can't type {:?}",
+                            xkb_state.key_get_one_sym(xkb::Keycode::new(key + 8))
+                        );
+                        return;
+                    };
+                    // `handle_key_event` swaps the left Meta and Alt keys of physical keyboards,
+                    // but the keysym was already the one the client wanted.
+                    let key_code = swap_meta_and_alt(seat_key.key_code);
+                    self.virtual_input_state
+                        .keyboards
+                        .get_mut(&keyboard_id)
+                        .unwrap()
+                        .pressed_keys
+                        .insert(key, key_code);
+
+                    // Shift and AltGr are only held for this key, when they aren't already.
+                    let modifier_state = self.keyboard.modifier_state();
+                    let modifier_keys = LEVEL_MODIFIER_KEYS[seat_key.level]
+                        .iter()
+                        .copied()
+                        .filter(|&modifier_key| {
+                            if modifier_key == KEY_LEFTSHIFT as u32 {
+                                !modifier_state.shift
+                            } else {
+                                !modifier_state.iso_level3_shift
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    for &modifier_key in &modifier_keys {
+                        self.handle_key_event(modifier_key, KeyState::Pressed, time);
+                    }
+                    self.handle_key_event(key_code, KeyState::Pressed, time);
+                    for &modifier_key in modifier_keys.iter().rev() {
+                        self.handle_key_event(modifier_key, KeyState::Released, time);
+                    }
+                }
+                KeyState::Released => {
+                    let Some(key_code) = self
+                        .virtual_input_state
+                        .keyboards
+                        .get_mut(&keyboard_id)
+                        .unwrap()
+                        .pressed_keys
+                        .remove(&key)
+                    else {
+                        return;
+                    };
+                    self.handle_key_event(key_code, KeyState::Released, time);
+                }
+            }
+        }
+
+        /// Holds the seat modifier keys matching the modifiers a virtual keyboard set,
+        /// so that shortcuts like Ctrl+C reach Flutter and the focused client.
+        /// Only releases them when `allowed` is false.
+        fn virtual_modifiers(&mut self, keyboard_id: &ObjectId, allowed: bool) {
+            let Some(keyboard) = self.virtual_input_state.keyboards.get(keyboard_id) else {
+                return;
+            };
+            let Some(ref xkb_state) = keyboard.xkb_state else {
+                return;
+            };
+
+            let mut to_press = Vec::new();
+            let mut to_release = Vec::new();
+            for (name, key_code) in SEAT_MODIFIER_KEYS {
+                let is_active =
+                    allowed && xkb_state.mod_name_is_active(name, xkb::STATE_MODS_DEPRESSED);
+                let is_held = keyboard.held_modifier_keys.contains(&key_code);
+                if is_active && !is_held {
+                    to_press.push(key_code);
+                } else if !is_active && is_held {
+                    to_release.push(key_code);
+                }
+            }
+
+            let keyboard = self
+                .virtual_input_state
+                .keyboards
+                .get_mut(keyboard_id)
+                .unwrap();
+            keyboard.held_modifier_keys.extend(&to_press);
+            for key_code in &to_release {
+                keyboard.held_modifier_keys.remove(key_code);
+            }
+
+            for key_code in to_release {
+                self.handle_key_event(key_code, KeyState::Released, 0);
+            }
+            for key_code in to_press {
+                self.handle_key_event(key_code, KeyState::Pressed, 0);
+            }
+        }
+
+        /// Releases the keys a virtual keyboard still holds when it goes away.
+        fn release_virtual_keys(&mut self, keyboard_id: &ObjectId) {
+            let Some(keyboard) = self.virtual_input_state.keyboards.remove(keyboard_id) else {
+                return;
+            };
+            for key_code in keyboard
+                .pressed_keys
+                .into_values()
+                .chain(keyboard.held_modifier_keys)
+            {
+                self.handle_key_event(key_code, KeyState::Released, 0);
+            }
+        }
+    }
+
+    fn swap_meta_and_alt(key_code: u32) -> u32 {
+        if key_code == KEY_LEFTMETA as u32 {
+            KEY_LEFTALT as u32
+        } else if key_code == KEY_LEFTALT as u32 {
+            KEY_LEFTMETA as u32
+        } else {
+            key_code
+        }
+    }
+
+    fn axis_from_wl(axis: wl_pointer::Axis) -> Axis {
+        match axis {
+            wl_pointer::Axis::HorizontalScroll => Axis::Horizontal,
+            _ => Axis::Vertical,
+        }
+    }
+
+    impl<BackendData: Backend + 'static> GlobalDispatch<ZwpVirtualKeyboardManagerV1, ()>
+        for State<BackendData>
+    {
+        fn bind(
+            _state: &mut Self,
+            _handle: &DisplayHandle,
+            _client: &Client,
+            resource: New<ZwpVirtualKeyboardManagerV1>,
+            _global_data: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            data_init.init(resource, ());
+        }
+
+        fn can_view(client: Client, _global_data: &()) -> bool {
+            can_view_privileged_global(&client, PrivilegedProtocol::VirtualInput)
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwpVirtualKeyboardManagerV1, ()>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            client: &Client,
+            _manager: &ZwpVirtualKeyboardManagerV1,
+            request: zwp_virtual_keyboard_manager_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                zwp_virtual_keyboard_manager_v1::Request::CreateVirtualKeyboard { id, .. } => {
+                    let data = VirtualInputData::new(client);
+                    // Asks the shell right away, so that it can decide before the first key.
+                    state.is_virtual_input_allowed(&data, "keyboard");
+                    let keyboard = data_init.init(id, data);
+                    state.virtual_input_state.keyboards.insert(
+                        keyboard.id(),
+                        VirtualKeyboard {
+                            xkb_state: None,
+                            pressed_keys: HashMap::new(),
+                            held_modifier_keys: HashSet::new(),
+                        },
+                    );
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwpVirtualKeyboardV1, VirtualInputData>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            keyboard: &ZwpVirtualKeyboardV1,
+            request: zwp_virtual_keyboard_v1::Request,
+            data: &VirtualInputData,
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                zwp_virtual_keyboard_v1::Request::Keymap { format, fd, size } => {
+                    // Only XKB_V1 keymaps exist.
+                    if format != 1 {
+                        warn!("Unsupported virtual keyboard keymap format {}", format);
+                        return;
+                    }
+                    let file = File::from(fd);
+                    // The size comes from the client, the file has to back it before anything is allocated.
+                    let file_size = match file.metadata() {
+                        Ok(metadata) => metadata.len(),
+                        Err(err) => {
+                            warn!("Failed to stat the virtual keyboard keymap: {}", err);
+                            return;
+                        }
+                    };
+                    if size as u64 > file_size.min(MAX_KEYMAP_SIZE) {
+                        warn!(
+                            "Rejecting a virtual keyboard keymap of {} bytes in a file of {} bytes",
+                            size, file_size
+                        );
+                        return;
+                    }
+                    let mut keymap = vec![0; size as usize];
+                    if let Err(err) = file.read_exact_at(&mut keymap, 0) {
+                        warn!("Failed to read the virtual keyboard keymap: {}", err);
+                        return;
+                    }
+                    let keymap = String::from_utf8_lossy(&keymap);
+                    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+                    let Some(keymap) = xkb::Keymap::new_from_string(
+                        &context,
+                        keymap.trim_end_matches('\0').to_string(),
+                        xkb::KEYMAP_FORMAT_TEXT_V1,
+                        xkb::KEYMAP_COMPILE_NO_FLAGS,
+                    ) else {
+                        warn!("Failed to compile the virtual keyboard keymap");
+                        return;
+                    };
+                    if let Some(virtual_keyboard) =
+                        state.virtual_input_state.keyboards.get_mut(&keyboard.id())
+                    {
+                        virtual_keyboard.xkb_state = Some(xkb::State::new(&keymap));
+                    }
+                }
+                zwp_virtual_keyboard_v1::Request::Key {
+                    time,
+                    key,
+                    state: key_state,
+                } => {
+                    let has_keymap = state
+                        .virtual_input_state
+                        .keyboards
+                        .get(&keyboard.id())
+                        .is_some_and(|virtual_keyboard| virtual_keyboard.xkb_state.is_some());
+                    if !has_keymap {
+                        keyboard.post_error(
+                            zwp_virtual_keyboard_v1::Error::NoKeymap,
+                            "No keymap was set",
+                        );
+                        return;
+                    }
+                    let key_state = if key_state == 1 {
+                        KeyState::Pressed
+                    } else {
+                        KeyState::Released
+                    };
+                    // Releases still go through, so that no key stays stuck.
+                    if key_state == KeyState::Pressed
+                        && !state.is_virtual_input_allowed(data, "keyboard")
+                    {
+                        return;
+                    }
+                    state.virtual_key(keyboard.id(), key, key_state, time);
+                }
+                zwp_virtual_keyboard_v1::Request::Modifiers {
+                    mods_depressed,
+                    mods_latched,
+                    mods_locked,
+                    group,
+                } => {
+                    // Tells which keysyms the client means, and which seat modifiers to hold.
+                    if let Some(xkb_state) = state
+                        .virtual_input_state
+                        .keyboards
+                        .get_mut(&keyboard.id())
+                        .and_then(|virtual_keyboard| virtual_keyboard.xkb_state.as_mut())
+                    {
+                        xkb_state.update_mask(
+                            mods_depressed,
+                            mods_latched,
+                            mods_locked,
+                            0,
+                            0,
+                            group,
+                        );
+                    }
+                    // Modifiers are still released, so that none stays stuck.
+                    let allowed = state.is_virtual_input_allowed(data, "keyboard");
+                    state.virtual_modifiers(&keyboard.id(), allowed);
+                }
+                zwp_virtual_keyboard_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+
+        fn destroyed(
+            state: &mut Self,
+            _client: ClientId,
+            keyboard: &ZwpVirtualKeyboardV1,
+            _data: &VirtualInputData,
+        ) {
+            state.release_virtual_keys(&keyboard.id());
+        }
+    }
+
+    impl<BackendData: Backend + 'static> GlobalDispatch<ZwlrVirtualPointerManagerV1, ()>
+        for State<BackendData>
+    {
+        fn bind(
+            _state: &mut Self,
+            _handle: &DisplayHandle,
+            _client: &Client,
+            resource: New<ZwlrVirtualPointerManagerV1>,
+            _global_data: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            data_init.init(resource, ());
+        }
+
+        fn can_view(client: Client, _global_data: &()) -> bool {
+            can_view_privileged_global(&client, PrivilegedProtocol::VirtualInput)
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwlrVirtualPointerManagerV1, ()>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            client: &Client,
+            _manager: &ZwlrVirtualPointerManagerV1,
+            request: zwlr_virtual_pointer_manager_v1::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            let id = match request {
+                zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointer { id, .. } => id,
+                // Absolute motion is always mapped to the whole canvas.
+                zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointerWithOutput {
+                    id,
+                    ..
+                } => id,
+                zwlr_virtual_pointer_manager_v1::Request::Destroy => return,
+                _ => unreachable!(),
+            };
+            let data = VirtualInputData::new(client);
+            state.is_virtual_input_allowed(&data, "pointer");
+            data_init.init(id, data);
+        }
+    }
+
+    impl<BackendData: Backend + 'static> Dispatch<ZwlrVirtualPointerV1, VirtualInputData>
+        for State<BackendData>
+    {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            pointer: &ZwlrVirtualPointerV1,
+            request: zwlr_virtual_pointer_v1::Request,
+            data: &VirtualInputData,
+            _dhandle: &DisplayHandle,
+            _data_init: &mut DataInit<'_, Self>,
+        ) {
+            if matches!(request, zwlr_virtual_pointer_v1::Request::Destroy) {
+                return;
+            }
+            if !state.is_virtual_input_allowed(data, "pointer") {
+                return;
+            }
+
+            match request {
+                zwlr_virtual_pointer_v1::Request::Motion { time, dx, dy } => {
+                    let delta = (dx, dy).into();
+                    state.pointer_motion(delta, delta, time as u64 * 1000, time);
+                }
+                zwlr_virtual_pointer_v1::Request::MotionAbsolute {
+                    time,
+                    x,
+                    y,
+                    x_extent,
+                    y_extent,
+                } => {
+                    if x_extent == 0 || y_extent == 0 {
+                        return;
+                    }
+                    let Some(size) = state.pointer_canvas_size() else {
+                        return;
+                    };
+                    let location = (
+                        x as f64 / x_extent as f64 * size.w as f64,
+                        y as f64 / y_extent as f64 * size.h as f64,
+                    );
+                    state.pointer_motion_absolute(location.into(), time);
+                }
+                zwlr_virtual_pointer_v1::Request::Button {
+                    button,
+                    state: WEnum::Value(button_state),
+                    ..
+                } => {
+                    let button_state = match button_state {
+                        wl_pointer::ButtonState::Pressed => ButtonState::Pressed,
+                        _ => ButtonState::Released,
+                    };
+                    state.pointer_button(button, button_state);
+                }
+                zwlr_virtual_pointer_v1::Request::Axis {
+                    time,
+                    axis: WEnum::Value(axis),
+                    value,
+                } => {
+                    let frame = state
+                        .virtual_input_state
+                        .pending_axis_frames
+                        .remove(&pointer.id())
+                        .unwrap_or_else(|| AxisFrame::new(time));
+                    state
+                        .virtual_input_state
+                        .pending_axis_frames
+                        .insert(pointer.id(), frame.value(axis_from_wl(axis), value));
+                }
+                zwlr_virtual_pointer_v1::Request::AxisSource {
+                    axis_source: WEnum::Value(axis_source),
+                } => {
+                    let source = match axis_source {
+                        wl_pointer::AxisSource::Finger => AxisSource::Finger,
+                        wl_pointer::AxisSource::Continuous => AxisSource::Continuous,
+                        wl_pointer::AxisSource::WheelTilt => AxisSource::WheelTilt,
+                        _ => AxisSource::Wheel,
+                    };
+                    let frame = state
+                        .virtual_input_state
+                        .pending_axis_frames
+                        .remove(&pointer.id())
+                        .unwrap_or_else(|| AxisFrame::new(0));
+                    state
+                        .virtual_input_state
+                        .pending_axis_frames
+                        .insert(pointer.id(), frame.source(source));
+                }
+                zwlr_virtual_pointer_v1::Request::AxisStop {
+                    time,
+                    axis: WEnum::Value(axis),
+                } => {
+                    let frame = state
+                        .virtual_input_state
+                        .pending_axis_frames
+                        .remove(&pointer.id())
+                        .unwrap_or_else(|| AxisFrame::new(time));
+                    state
+                        .virtual_input_state
+                        .pending_axis_frames
+                        .insert(pointer.id(), frame.stop(axis_from_wl(axis)));
+                }
+                zwlr_virtual_pointer_v1::Request::AxisDiscrete {
+                    time,
+                    axis: WEnum::Value(axis),
+                    value,
+                    discrete,
+                } => {
+                    let axis = axis_from_wl(axis);
+                    let frame = state
+                        .virtual_input_state
+                        .pending_axis_frames
+                        .remove(&pointer.id())
+                        .unwrap_or_else(|| AxisFrame::new(time));
+                    state.virtual_input_state.pending_axis_frames.insert(
+                        pointer.id(),
+                        frame.value(axis, value).v120(axis, discrete * 120),
+                    );
+                }
+                zwlr_virtual_pointer_v1::Request::Frame => {
+                    if let Some(frame) = state
+                        .virtual_input_state
+                        .pending_axis_frames
+                        .remove(&pointer.id())
+                    {
+                        state.pointer_axis(frame);
+                    }
+                }
+                // Unknown enum values are ignored.
+                _ => {}
+            }
+        }
+
+        fn destroyed(
+            state: &mut Self,
+            _client: ClientId,
+            pointer: &ZwlrVirtualPointerV1,
+            _data: &VirtualInputData,
+        ) {
+            state
+                .virtual_input_state
+                .pending_axis_frames
+                .remove(&pointer.id());
+        }
+    }
+}
@@ -5,13 +5,16 @@ pub mod xdg {
     use smithay::{
         reexports::{
             wayland_protocols::xdg::shell::server::xdg_toplevel,
-            wayland_server::{protocol::wl_seat::WlSeat, Resource},
+            wayland_server::{
+                protocol::{wl_seat::WlSeat, wl_surface::WlSurface},
+                Resource,
+            },
         },
         utils::{Logical, Serial},
         wayland::{
             compositor::with_states,
             shell::xdg::{
-                PopupSurface, PositionerState, ToplevelSurface, XdgPopupSurfaceData,
+                Configure, PopupSurface, PositionerState, ToplevelSurface, XdgPopupSurfaceData,
                 XdgShellHandler, XdgShellState, XdgToplevelSurfaceData,
             },
         },
@@ -20,7 +23,10 @@ pub mod xdg {
     use crate::{
         flutter_engine::wayland_messages::MyPoint,
         state::State,
-        wayland::wayland::{get_surface_id, WlSurfaceVeshellState},
+        wayland::{
+            foreign_toplevel::foreign_toplevel::ToplevelId,
+            wayland::{get_surface_id, WlSurfaceVeshellState},
+        },
         Backend,
     };
 
@@ -54,6 +60,8 @@ pub mod xdg {
                 }))),
                 None,
             );
+
+            self.new_foreign_toplevel(ToplevelId::Xdg(surface_id));
         }
 
         fn new_popup(&mut self, surface: PopupSurface, positioner: PositionerState) {
@@ -145,9 +153,17 @@ pub mod xdg {
             surface.send_repositioned(token);
         }
 
+        fn ack_configure(&mut self, surface: WlSurface, configure: Configure) {
+            // Taskbars show the states the client has acknowledged.
+            if let Configure::Toplevel(_) = configure {
+                self.update_foreign_toplevel(ToplevelId::Xdg(get_surface_id(&surface)));
+            }
+        }
+
         fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
             let surface_id = get_surface_id(surface.wl_surface());
             self.xdg_toplevels.remove(&surface_id);
+            self.foreign_toplevel_closed(ToplevelId::Xdg(surface_id));
 
             let platform_method_channel = &mut self.flutter_engine_mut().platform_method_channel;
             platform_method_channel.invoke_method(
@@ -196,6 +212,7 @@ pub mod xdg {
                 }))),
                 None,
             );
+            self.update_foreign_toplevel(ToplevelId::Xdg(surface_id));
         }
 
         fn title_changed(&mut self, surface: ToplevelSurface) {
@@ -221,6 +238,7 @@ pub mod xdg {
                 }))),
                 None,
             );
+            self.update_foreign_toplevel(ToplevelId::Xdg(surface_id));
         }
     }
 }
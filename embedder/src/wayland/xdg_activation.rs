@@ -0,0 +1,126 @@
+pub mod xdg_activation {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use smithay::{
+        delegate_xdg_activation,
+        input::Seat,
+        reexports::wayland_server::{protocol::wl_surface::WlSurface, Resource},
+        wayland::{
+            seat::WaylandFocus,
+            xdg_activation::{
+                XdgActivationHandler, XdgActivationState, XdgActivationToken,
+                XdgActivationTokenData,
+            },
+        },
+        xwayland::X11Surface,
+    };
+    use tracing::info;
+
+    use crate::{state::State, wayland::wayland::get_surface_id, Backend};
+
+    /// Launching an app can take a while, but a token older than this is from a stale launch.
+    const ACTIVATION_TOKEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+    impl<BackendData: Backend + 'static> State<BackendData> {
+        /// Issues a token for an app the shell launches,
+        /// passed to it in XDG_ACTIVATION_TOKEN and DESKTOP_STARTUP_ID.
+        pub fn create_activation_token(&mut self) -> String {
+            let (token, _) = self.xdg_activation_state.create_external_token(None);
+            token.to_string()
+        }
+
+        /// Tokens issued to the shell are always valid. Tokens created by a client are only valid
+        /// if the client had the keyboard focus when the user triggered the input event it passed,
+        /// so that an app can't steal the focus without the user asking for it.
+        fn is_activation_token_valid(&self, token_data: &XdgActivationTokenData) -> bool {
+            if token_data.timestamp.elapsed() > ACTIVATION_TOKEN_TIMEOUT {
+                return false;
+            }
+            let Some(ref client_id) = token_data.client_id else {
+                return true;
+            };
+            let Some((serial, ref seat)) = token_data.serial else {
+                return false;
+            };
+            if Seat::<Self>::from_resource(seat).as_ref() != Some(&self.seat) {
+                return false;
+            }
+
+            let focused_client = self
+                .keyboard
+                .current_focus()
+                .and_then(|focus| focus.wl_surface().map(|surface| surface.into_owned()))
+                .and_then(|surface| surface.client())
+                .map(|client| client.id());
+            focused_client.as_ref() == Some(client_id)
+                && self
+                    .keyboard
+                    .last_enter()
+                    .map(|last_enter| serial.is_no_older_than(&last_enter))
+                    .unwrap_or(false)
+        }
+
+        /// X11 apps launched with a token set it as their `_NET_STARTUP_ID`.
+        pub fn x11_startup_notification(&mut self, x11_surface: &X11Surface) {
+            let Some(startup_id) = x11_surface.startup_id() else {
+                return;
+            };
+            // Startup ids that weren't issued as activation tokens are left alone.
+            let Some(is_valid) = self
+                .xdg_activation_state
+                .data_for_token(&startup_id)
+                .map(|token_data| self.is_activation_token_valid(token_data))
+            else {
+                return;
+            };
+            self.xdg_activation_state.remove_token(&startup_id);
+
+            self.activation_requested(json!({
+                "x11SurfaceId": Self::get_x11_surface_id(x11_surface),
+                "token": startup_id,
+                "valid": is_valid,
+            }));
+        }
+
+        /// The shell decides between focusing the window and marking it as urgent.
+        fn activation_requested(&mut self, message: serde_json::Value) {
+            info!("Activation requested: {}", message);
+            self.flutter_engine_mut()
+                .platform_method_channel
+                .invoke_method("activation_requested", Some(Box::new(message)), None);
+        }
+    }
+
+    impl<BackendData: Backend + 'static> XdgActivationHandler for State<BackendData> {
+        fn activation_state(&mut self) -> &mut XdgActivationState {
+            &mut self.xdg_activation_state
+        }
+
+        fn token_created(
+            &mut self,
+            _token: XdgActivationToken,
+            _data: XdgActivationTokenData,
+        ) -> bool {
+            // Every token is kept, invalid ones still let the shell mark the window as urgent.
+            true
+        }
+
+        fn request_activation(
+            &mut self,
+            token: XdgActivationToken,
+            token_data: XdgActivationTokenData,
+            surface: WlSurface,
+        ) {
+            let is_valid = self.is_activation_token_valid(&token_data);
+            self.xdg_activation_state.remove_token(&token);
+
+            self.activation_requested(json!({
+                "surfaceId": get_surface_id(&surface),
+                "token": token.to_string(),
+                "valid": is_valid,
+            }));
+        }
+    }
+    delegate_xdg_activation!(@<BackendData: Backend + 'static> State<BackendData>);
+}
@@ -4,11 +4,16 @@ pub mod xwayland {
     use crate::flutter_engine::wayland_messages::{MapX11Surface, NewX11Surface};
     use crate::focus::KeyboardFocusTarget;
     use crate::state::State;
+    use crate::wayland::foreign_toplevel::foreign_toplevel::ToplevelId;
     use crate::wayland::wayland::get_surface_id;
     use serde_json::json;
 
     use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+    use smithay::reexports::x11rb::connection::Connection as _;
     use smithay::reexports::x11rb::protocol::xproto::Window;
+    use smithay::reexports::x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _, PropMode};
+    use smithay::reexports::x11rb::rust_connection::RustConnection;
+    use smithay::reexports::x11rb::wrapper::ConnectionExt as _;
     use smithay::utils::{Logical, Point, Rectangle, Size};
     use smithay::wayland::seat::WaylandFocus;
     use smithay::wayland::selection::data_device::{
@@ -27,7 +32,7 @@ pub mod xwayland {
     use std::cell::RefCell;
     use std::os::fd::OwnedFd;
     use std::time::Duration;
-    use tracing::{error, trace};
+    use tracing::{error, trace, warn};
     pub struct MyX11SurfaceState {
         pub x11_surface_id: u64,
     }
@@ -72,6 +77,8 @@ pub mod xwayland {
 
                         data.x11_wm = Some(wm);
                         data.xwayland_display = Some(display_number);
+                        data.xwayland_scale = data.largest_output_scale();
+                        set_xft_dpi(display_number, data.xwayland_scale);
 
                         if let Some(flutter_engine) = data.flutter_engine.as_mut() {
                             flutter_engine.set_environment_variable(
@@ -97,6 +104,48 @@ pub mod xwayland {
             }
         }
 
+        /// X11 clients have no per-window scale, so XWayland runs at the largest output scale:
+        /// clients are told its DPI through `Xft.dpi`, which GTK, Qt and Xft size text and widgets from,
+        /// and their windows are shown at one X11 pixel per physical pixel of that output.
+        /// Clients started before a change keep the DPI they read.
+        pub fn update_xwayland_scale(&mut self) {
+            let scale = self.largest_output_scale();
+            if scale == self.xwayland_scale {
+                return;
+            }
+            self.xwayland_scale = scale;
+            if let Some(display_number) = self.xwayland_display {
+                set_xft_dpi(display_number, scale);
+            }
+        }
+
+        fn largest_output_scale(&self) -> f64 {
+            self.space
+                .outputs()
+                .map(|output| output.current_scale().fractional_scale())
+                .fold(1.0, f64::max)
+        }
+
+        /// How many surface pixels make a logical pixel, more than 1 for X11 windows on HiDPI outputs.
+        pub fn surface_pixel_scale(&self, surface: &WlSurface) -> f64 {
+            if self.x11_surface_per_wl_surface.contains_key(surface) {
+                self.xwayland_scale
+            } else {
+                1.0
+            }
+        }
+
+        pub fn x11_to_logical(&self, geometry: Rectangle<i32, Logical>) -> Rectangle<i32, Logical> {
+            geometry
+                .to_f64()
+                .downscale(self.xwayland_scale)
+                .to_i32_round()
+        }
+
+        pub fn logical_to_x11(&self, size: Size<i32, Logical>) -> Size<i32, Logical> {
+            size.to_f64().upscale(self.xwayland_scale).to_i32_round()
+        }
+
         pub fn get_x11_surface_id(x11_surface: &X11Surface) -> u64 {
             x11_surface
                 .user_data()
@@ -154,11 +203,17 @@ pub mod xwayland {
                 "map_x11_surface",
                 Some(Box::new(json!(MapX11Surface {
                     x11_surface_id: Self::get_x11_surface_id(&surface),
-                    geometry: surface.geometry().into(),
+                    geometry: self.x11_to_logical(surface.geometry()).into(),
                     parent,
                 }))),
                 None,
             );
+
+            // Menus and tooltips aren't windows as far as taskbars are concerned.
+            if !surface.is_override_redirect() {
+                self.new_foreign_toplevel(ToplevelId::X11(Self::get_x11_surface_id(&surface)));
+                self.x11_startup_notification(&surface);
+            }
         }
     }
 
@@ -194,6 +249,8 @@ pub mod xwayland {
         }
 
         fn unmapped_window(&mut self, xwm: XwmId, surface: X11Surface) {
+            self.foreign_toplevel_closed(ToplevelId::X11(Self::get_x11_surface_id(&surface)));
+
             let Some(wl_surface) = surface.wl_surface() else {
                 return;
             };
@@ -218,6 +275,7 @@ pub mod xwayland {
 
         fn destroyed_window(&mut self, xwm: XwmId, surface: X11Surface) {
             let x11_surface_id = Self::get_x11_surface_id(&surface);
+            self.foreign_toplevel_closed(ToplevelId::X11(x11_surface_id));
 
             let platform_method_channel = &mut self.flutter_engine_mut().platform_method_channel;
             platform_method_channel.invoke_method(
@@ -290,6 +348,7 @@ pub mod xwayland {
             }))),
             None,
         );
+            self.update_foreign_toplevel(ToplevelId::X11(Self::get_x11_surface_id(&x11_surface)));
 
             /* match property {
                 WmWindowProperty::Title => {
@@ -437,6 +496,51 @@ pub mod xwayland {
                 }))),
                 None,
             );
+            // The window now has an output.
+            self.update_foreign_toplevel(ToplevelId::X11(x11_surface_id));
         }
     }
+
+    /// Writes `Xft.dpi` in the resource database of XWayland, keeping the other resources.
+    /// Done on another thread, the compositor must not wait for the X server.
+    fn set_xft_dpi(display_number: u32, scale: f64) {
+        let dpi = (96.0 * scale).round() as u32;
+        std::thread::spawn(move || {
+            if let Err(err) = write_xft_dpi(display_number, dpi) {
+                warn!("Failed to set Xft.dpi to {}: {}", dpi, err);
+            }
+        });
+    }
+
+    fn write_xft_dpi(display_number: u32, dpi: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let (connection, screen) =
+            RustConnection::connect(Some(format!(":{}", display_number).as_str()))?;
+        let root = connection.setup().roots[screen].root;
+        let reply = connection
+            .get_property(
+                false,
+                root,
+                AtomEnum::RESOURCE_MANAGER,
+                AtomEnum::STRING,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+        let mut resources: String = String::from_utf8_lossy(&reply.value)
+            .lines()
+            .filter(|line| !line.starts_with("Xft.dpi:"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        resources.push_str(&format!("Xft.dpi:\t{}\n", dpi));
+        connection
+            .change_property8(
+                PropMode::REPLACE,
+                root,
+                AtomEnum::RESOURCE_MANAGER,
+                AtomEnum::STRING,
+                resources.as_bytes(),
+            )?
+            .check()?;
+        Ok(())
+    }
 }
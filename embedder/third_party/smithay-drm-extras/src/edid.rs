@@ -26,6 +26,63 @@ pub struct EdidInfo {
     pub model: String,
     /// Name of manufacturer of this monitor
     pub manufacturer: String,
+    /// Serial number, from the serial number descriptor or the numeric serial
+    pub serial: Option<String>,
+    /// Physical size in millimeters
+    pub physical_size: Option<(u32, u32)>,
+    /// The first detailed timing, which is the native mode of the monitor
+    pub preferred_timing: Option<DetailedTiming>,
+    /// Refresh rate range in Hz, when the monitor supports continuous frequencies
+    pub vrr_range: Option<(u32, u32)>,
+    /// HDR capabilities, from the CTA-861 HDR static metadata data block
+    pub hdr_static_metadata: Option<HdrStaticMetadata>,
+    /// Additional colorimetry, from the CTA-861 colorimetry data block
+    pub colorimetry: Colorimetry,
+}
+
+/// Detailed timing descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetailedTiming {
+    /// Pixel clock in kHz
+    pub pixel_clock: u32,
+    /// Horizontal active pixels
+    pub width: u32,
+    /// Vertical active lines
+    pub height: u32,
+    /// Refresh rate in mHz
+    pub refresh_rate: u32,
+    /// Whether the timing is interlaced
+    pub interlaced: bool,
+}
+
+/// Transfer functions and luminance range the monitor accepts
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HdrStaticMetadata {
+    /// Traditional gamma, SDR luminance range
+    pub eotf_sdr: bool,
+    /// Traditional gamma, HDR luminance range
+    pub eotf_hdr: bool,
+    /// SMPTE ST 2084 (PQ)
+    pub eotf_pq: bool,
+    /// Hybrid log-gamma
+    pub eotf_hlg: bool,
+    /// Desired content max luminance, in cd/m²
+    pub max_luminance: Option<f64>,
+    /// Desired content max frame-average luminance, in cd/m²
+    pub max_frame_average_luminance: Option<f64>,
+    /// Desired content min luminance, in cd/m²
+    pub min_luminance: Option<f64>,
+}
+
+/// Wide color gamuts the monitor accepts besides sRGB
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Colorimetry {
+    /// ITU-R BT.2020 RGB
+    pub bt2020_rgb: bool,
+    /// ITU-R BT.2020 YCbCr
+    pub bt2020_ycc: bool,
+    /// DCI-P3
+    pub dci_p3: bool,
 }
 
 impl EdidInfo {
@@ -38,14 +95,211 @@ impl EdidInfo {
             .get_properties(connector)
             .ok()
             .and_then(|props| get_edid(device, &props))
-            .map(|edid| EdidInfo {
-                model: get_monitor_name(&edid),
-                manufacturer: get_manufacturer_name(&edid),
-            })
+            .and_then(|data| EdidInfo::parse(&data))
+    }
+
+    /// Parse a raw EDID blob, including its CTA-861 extension blocks
+    pub fn parse(data: &[u8]) -> Option<EdidInfo> {
+        let base = data.get(..EDID_BLOCK_SIZE)?;
+        let mut reader = std::io::Cursor::new(data);
+        let edid = edid_rs::parse(&mut reader).ok()?;
+
+        let mut hdr_static_metadata = None;
+        let mut colorimetry = Colorimetry::default();
+        for (tag, payload) in cta_data_blocks(data) {
+            match tag {
+                CTA_EXTENDED_TAG_COLORIMETRY => colorimetry = parse_colorimetry(payload),
+                CTA_EXTENDED_TAG_HDR_STATIC_METADATA => {
+                    hdr_static_metadata = parse_hdr_static_metadata(payload)
+                }
+                _ => {}
+            }
+        }
+
+        let preferred_timing = base_descriptors(base)
+            .next()
+            .filter(|descriptor| !is_display_descriptor(descriptor))
+            .map(parse_detailed_timing);
+
+        Some(EdidInfo {
+            model: get_monitor_name(&edid),
+            manufacturer: get_manufacturer_name(&edid),
+            serial: get_serial(base),
+            physical_size: get_physical_size(base),
+            preferred_timing,
+            vrr_range: get_vrr_range(base),
+            hdr_static_metadata,
+            colorimetry,
+        })
+    }
+}
+
+const EDID_BLOCK_SIZE: usize = 128;
+const DESCRIPTOR_SIZE: usize = 18;
+const DESCRIPTOR_SERIAL_NUMBER: u8 = 0xff;
+const DESCRIPTOR_RANGE_LIMITS: u8 = 0xfd;
+const CTA_EXTENSION_TAG: u8 = 0x02;
+const CTA_DATA_BLOCK_EXTENDED: u8 = 7;
+const CTA_EXTENDED_TAG_COLORIMETRY: u8 = 0x05;
+const CTA_EXTENDED_TAG_HDR_STATIC_METADATA: u8 = 0x06;
+
+/// The four 18-byte descriptors of the base block
+fn base_descriptors(base: &[u8]) -> impl Iterator<Item = &[u8]> {
+    base[54..126].chunks_exact(DESCRIPTOR_SIZE)
+}
+
+/// Display descriptors start with a zero pixel clock, detailed timings don't
+fn is_display_descriptor(descriptor: &[u8]) -> bool {
+    descriptor[0] == 0 && descriptor[1] == 0
+}
+
+fn display_descriptor(base: &[u8], tag: u8) -> Option<&[u8]> {
+    base_descriptors(base)
+        .find(|descriptor| is_display_descriptor(descriptor) && descriptor[3] == tag)
+}
+
+fn parse_detailed_timing(descriptor: &[u8]) -> DetailedTiming {
+    let pixel_clock = u16::from_le_bytes([descriptor[0], descriptor[1]]) as u32 * 10;
+    let width = descriptor[2] as u32 | ((descriptor[4] as u32 >> 4) << 8);
+    let horizontal_blank = descriptor[3] as u32 | ((descriptor[4] as u32 & 0xf) << 8);
+    let height = descriptor[5] as u32 | ((descriptor[7] as u32 >> 4) << 8);
+    let vertical_blank = descriptor[6] as u32 | ((descriptor[7] as u32 & 0xf) << 8);
+
+    let total = (width + horizontal_blank) as u64 * (height + vertical_blank) as u64;
+    let refresh_rate = (pixel_clock as u64 * 1_000_000)
+        .checked_div(total)
+        .unwrap_or(0) as u32;
+
+    DetailedTiming {
+        pixel_clock,
+        width,
+        height,
+        refresh_rate,
+        interlaced: descriptor[17] & 0x80 != 0,
+    }
+}
+
+/// Text descriptors are up to 13 bytes, terminated by a line feed and padded with spaces
+fn descriptor_text(descriptor: &[u8]) -> String {
+    let text = &descriptor[5..DESCRIPTOR_SIZE];
+    let end = text.iter().position(|&c| c == b'\n').unwrap_or(text.len());
+    String::from_utf8_lossy(&text[..end]).trim().to_string()
+}
+
+fn get_serial(base: &[u8]) -> Option<String> {
+    display_descriptor(base, DESCRIPTOR_SERIAL_NUMBER)
+        .map(descriptor_text)
+        .filter(|serial| !serial.is_empty())
+        .or_else(|| {
+            let serial = u32::from_le_bytes([base[12], base[13], base[14], base[15]]);
+            (serial != 0).then(|| serial.to_string())
+        })
+}
+
+fn get_physical_size(base: &[u8]) -> Option<(u32, u32)> {
+    // The detailed timing is in millimeters, the basic display parameters only in centimeters.
+    base_descriptors(base)
+        .find(|descriptor| !is_display_descriptor(descriptor))
+        .map(|descriptor| {
+            (
+                descriptor[12] as u32 | ((descriptor[14] as u32 >> 4) << 8),
+                descriptor[13] as u32 | ((descriptor[14] as u32 & 0xf) << 8),
+            )
+        })
+        .filter(|&(width, height)| width != 0 && height != 0)
+        .or_else(|| {
+            let (width, height) = (base[21] as u32 * 10, base[22] as u32 * 10);
+            (width != 0 && height != 0).then_some((width, height))
+        })
+}
+
+/// Same rules as the kernel: EDID 1.4 monitors with continuous frequency support
+/// accept any vertical rate within their range limits.
+fn get_vrr_range(base: &[u8]) -> Option<(u32, u32)> {
+    let (version, revision) = (base[18], base[19]);
+    let continuous_frequency = base[24] & 0x01 != 0;
+    if version != 1 || revision < 4 || !continuous_frequency {
+        return None;
+    }
+
+    let descriptor = display_descriptor(base, DESCRIPTOR_RANGE_LIMITS)?;
+    let offsets = descriptor[4] & 0x03;
+    let mut min = descriptor[5] as u32;
+    let mut max = descriptor[6] as u32;
+    if offsets == 0x03 {
+        min += 255;
+    }
+    if offsets & 0x02 != 0 {
+        max += 255;
+    }
+
+    (min > 0 && min < max).then_some((min, max))
+}
+
+/// Extended tag and payload of every extended data block in the CTA-861 extensions
+fn cta_data_blocks(data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut blocks = vec![];
+
+    for extension in data.chunks_exact(EDID_BLOCK_SIZE).skip(1) {
+        if extension[0] != CTA_EXTENSION_TAG {
+            continue;
+        }
+        // Data blocks sit between the header and the detailed timing descriptors.
+        let end = (extension[2] as usize).clamp(4, EDID_BLOCK_SIZE);
+        let mut offset = 4;
+        while offset < end {
+            let header = extension[offset];
+            let tag = header >> 5;
+            let length = (header & 0x1f) as usize;
+            let Some(payload) = extension.get(offset + 1..offset + 1 + length) else {
+                break;
+            };
+            if tag == CTA_DATA_BLOCK_EXTENDED && !payload.is_empty() {
+                blocks.push((payload[0], &payload[1..]));
+            }
+            offset += 1 + length;
+        }
+    }
+    blocks
+}
+
+fn parse_colorimetry(payload: &[u8]) -> Colorimetry {
+    let flags = payload.first().copied().unwrap_or(0);
+    let metadata_flags = payload.get(1).copied().unwrap_or(0);
+    Colorimetry {
+        bt2020_ycc: flags & (1 << 6) != 0,
+        bt2020_rgb: flags & (1 << 7) != 0,
+        dci_p3: metadata_flags & (1 << 7) != 0,
     }
 }
 
-fn get_edid(device: &impl ControlDevice, props: &PropertyValueSet) -> Option<edid_rs::EDID> {
+fn parse_hdr_static_metadata(payload: &[u8]) -> Option<HdrStaticMetadata> {
+    let eotfs = *payload.first()?;
+    // Luminances are coded values, see CTA-861-G 7.5.13.
+    let max_luminance = payload
+        .get(2)
+        .filter(|&&value| value != 0)
+        .map(|&value| 50.0 * 2f64.powf(value as f64 / 32.0));
+    let max_frame_average_luminance = payload
+        .get(3)
+        .filter(|&&value| value != 0)
+        .map(|&value| 50.0 * 2f64.powf(value as f64 / 32.0));
+    let min_luminance = max_luminance
+        .zip(payload.get(4))
+        .map(|(max, &value)| max * (value as f64 / 255.0).powi(2) / 100.0);
+
+    Some(HdrStaticMetadata {
+        eotf_sdr: eotfs & (1 << 0) != 0,
+        eotf_hdr: eotfs & (1 << 1) != 0,
+        eotf_pq: eotfs & (1 << 2) != 0,
+        eotf_hlg: eotfs & (1 << 3) != 0,
+        max_luminance,
+        max_frame_average_luminance,
+        min_luminance,
+    })
+}
+
+fn get_edid(device: &impl ControlDevice, props: &PropertyValueSet) -> Option<Vec<u8>> {
     let (info, value) = props
         .into_iter()
         .filter_map(|(handle, value)| {
@@ -56,11 +310,7 @@ fn get_edid(device: &impl ControlDevice, props: &PropertyValueSet) -> Option<edi
         .find(|(info, _)| info.name().to_str() == Ok("EDID"))?;
 
     let blob = info.value_type().convert_value(*value).as_blob()?;
-    let data = device.get_property_blob(blob).ok()?;
-
-    let mut reader = std::io::Cursor::new(data);
-
-    edid_rs::parse(&mut reader).ok()
+    device.get_property_blob(blob).ok()
 }
 
 fn get_manufacturer_name(edid: &edid_rs::EDID) -> String {
@@ -90,3 +340,188 @@ fn get_monitor_name(edid: &edid_rs::EDID) -> String {
         })
         .unwrap_or_else(|| edid.product.product_code.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELL_P2419H: &[u8] = include_bytes!("../tests/edid/dell-p2419h.bin");
+    const LG_27GN950: &[u8] = include_bytes!("../tests/edid/lg-27gn950.bin");
+    const BOE_NV156FHM: &[u8] = include_bytes!("../tests/edid/boe-nv156fhm.bin");
+    const DELL_UP3218K: &[u8] = include_bytes!("../tests/edid/dell-up3218k.bin");
+    const ASUS_PG27UQ: &[u8] = include_bytes!("../tests/edid/asus-pg27uq.bin");
+
+    fn assert_luminance(value: Option<f64>, expected: f64) {
+        let value = value.expect("missing luminance");
+        assert!((value - expected).abs() < 0.01, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn sdr_monitor() {
+        let info = EdidInfo::parse(DELL_P2419H).unwrap();
+
+        assert_eq!(info.manufacturer, "Dell Inc.");
+        assert_eq!(info.model, "DELL P2419H");
+        // The serial number descriptor takes precedence over the numeric serial.
+        assert_eq!(info.serial.as_deref(), Some("7G2CR93"));
+        assert_eq!(info.physical_size, Some((527, 296)));
+        assert_eq!(
+            info.preferred_timing,
+            Some(DetailedTiming {
+                pixel_clock: 148500,
+                width: 1920,
+                height: 1080,
+                refresh_rate: 60000,
+                interlaced: false,
+            })
+        );
+        // Range limits without continuous frequency support don't mean VRR.
+        assert_eq!(info.vrr_range, None);
+        assert_eq!(info.hdr_static_metadata, None);
+        assert_eq!(info.colorimetry, Colorimetry::default());
+    }
+
+    #[test]
+    fn hdr_vrr_monitor() {
+        let info = EdidInfo::parse(LG_27GN950).unwrap();
+
+        assert_eq!(info.manufacturer, "LG Electronics");
+        assert_eq!(info.model, "LG ULTRAGEAR");
+        assert_eq!(info.serial.as_deref(), Some("369841"));
+        assert_eq!(info.physical_size, Some((597, 336)));
+        assert_eq!(
+            info.preferred_timing,
+            Some(DetailedTiming {
+                pixel_clock: 533250,
+                width: 3840,
+                height: 2160,
+                refresh_rate: 59996,
+                interlaced: false,
+            })
+        );
+        assert_eq!(info.vrr_range, Some((48, 144)));
+        assert_eq!(
+            info.colorimetry,
+            Colorimetry {
+                bt2020_rgb: true,
+                bt2020_ycc: true,
+                dci_p3: false,
+            }
+        );
+
+        let hdr = info.hdr_static_metadata.unwrap();
+        assert!(hdr.eotf_sdr);
+        assert!(!hdr.eotf_hdr);
+        assert!(hdr.eotf_pq);
+        assert!(!hdr.eotf_hlg);
+        assert_luminance(hdr.max_luminance, 603.67);
+        assert_luminance(hdr.max_frame_average_luminance, 436.20);
+        assert_luminance(hdr.min_luminance, 0.38);
+    }
+
+    #[test]
+    fn laptop_panel() {
+        let info = EdidInfo::parse(BOE_NV156FHM).unwrap();
+
+        assert_eq!(info.manufacturer, "BOE");
+        // Panels usually only have unspecified text descriptors, so the product code is used.
+        assert_eq!(info.model, "1736");
+        assert_eq!(info.serial, None);
+        // Falls back to the centimeter size of the basic display parameters.
+        assert_eq!(info.physical_size, Some((340, 190)));
+        assert_eq!(
+            info.preferred_timing.map(|timing| timing.refresh_rate),
+            Some(60020)
+        );
+        assert_eq!(info.vrr_range, None);
+        assert_eq!(info.hdr_static_metadata, None);
+    }
+
+    #[test]
+    fn displayid_extension() {
+        let info = EdidInfo::parse(DELL_UP3218K).unwrap();
+
+        assert_eq!(info.manufacturer, "Dell Inc.");
+        assert_eq!(info.model, "DELL UP3218K");
+        assert_eq!(info.serial.as_deref(), Some("C1T9K83"));
+        assert_eq!(info.physical_size, Some((697, 392)));
+        // The tiles are only described by the DisplayID extension.
+        assert_eq!(
+            info.preferred_timing,
+            Some(DetailedTiming {
+                pixel_clock: 297000,
+                width: 3840,
+                height: 2160,
+                refresh_rate: 30000,
+                interlaced: false,
+            })
+        );
+        assert_eq!(info.vrr_range, None);
+        // The CTA-861 extension is found after the DisplayID one.
+        assert_eq!(
+            info.colorimetry,
+            Colorimetry {
+                bt2020_rgb: false,
+                bt2020_ycc: false,
+                dci_p3: true,
+            }
+        );
+        assert_eq!(info.hdr_static_metadata, None);
+    }
+
+    #[test]
+    fn missing_extension_block() {
+        // The base block announces two extensions, only the DisplayID one is there.
+        let info = EdidInfo::parse(&DELL_UP3218K[..2 * EDID_BLOCK_SIZE]).unwrap();
+
+        assert_eq!(info.model, "DELL UP3218K");
+        assert_eq!(info.colorimetry, Colorimetry::default());
+    }
+
+    #[test]
+    fn extension_with_bad_checksum() {
+        let info = EdidInfo::parse(ASUS_PG27UQ).unwrap();
+
+        assert_eq!(info.manufacturer, "ASUSTek COMPUTER INC");
+        assert_eq!(info.model, "ROG PG27UQ");
+        assert_eq!(info.serial.as_deref(), Some("J9LMQS012345"));
+        assert_eq!(info.physical_size, Some((597, 336)));
+        assert_eq!(
+            info.preferred_timing.map(|timing| timing.refresh_rate),
+            Some(59996)
+        );
+        assert_eq!(info.vrr_range, Some((24, 144)));
+        assert_eq!(
+            info.colorimetry,
+            Colorimetry {
+                bt2020_rgb: true,
+                bt2020_ycc: true,
+                dci_p3: false,
+            }
+        );
+
+        let hdr = info.hdr_static_metadata.unwrap();
+        assert!(hdr.eotf_sdr);
+        assert!(!hdr.eotf_hdr);
+        assert!(hdr.eotf_pq);
+        assert!(!hdr.eotf_hlg);
+        assert_luminance(hdr.max_luminance, 1131.37);
+        assert_luminance(hdr.max_frame_average_luminance, 749.67);
+        assert_luminance(hdr.min_luminance, 0.71);
+    }
+
+    #[test]
+    fn truncated_blob() {
+        assert!(EdidInfo::parse(&DELL_P2419H[..100]).is_none());
+    }
+
+    /// Prints what a capture decodes to, for the expected values of the test it goes into:
+    /// `EDID_CAPTURE=/sys/class/drm/card0-DP-1/edid cargo test decode_capture -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn decode_capture() {
+        let path = std::env::var("EDID_CAPTURE").expect("EDID_CAPTURE is not set");
+        let data = std::fs::read(&path).unwrap();
+        println!("{:#?}", EdidInfo::parse(&data));
+    }
+}
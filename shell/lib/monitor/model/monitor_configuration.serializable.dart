@@ -12,7 +12,6 @@ class MonitorConfiguration
     implements PersistableModel {
   /// Factory
   factory MonitorConfiguration({
-    required int selectedMode,
     required ScreenConfiguration screenConfiguration,
   }) = _MonitorConfiguration;
   MonitorConfiguration._();
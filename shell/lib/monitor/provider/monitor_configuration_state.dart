@@ -3,7 +3,6 @@ import 'package:riverpod_annotation/riverpod_annotation.dart';
 import 'package:shell/monitor/model/monitor.serializable.dart';
 import 'package:shell/monitor/model/monitor_configuration.serializable.dart';
 import 'package:shell/monitor/model/screen_configuration.serializable.dart';
-import 'package:shell/monitor/provider/monitor_list.dart';
 import 'package:shell/screen/model/screen.serializable.dart';
 import 'package:shell/shared/persistence/persistable_provider.mixin.dart';
 
@@ -19,13 +18,9 @@ class MonitorConfigurationState extends _$MonitorConfigurationState
   MonitorConfiguration build(MonitorId monitorId) {
     persistChanges(clearOnDispose: false);
 
-    final monitor = ref.watch(monitorListProvider).firstWhere(
-          (element) => element.name == monitorId,
-        );
-
+    // The mode of the monitor is remembered by the embedder, in its monitor profiles.
     return getPersisted(MonitorConfiguration.fromJson) ??
         MonitorConfiguration(
-          selectedMode: monitor.modes.indexOf(monitor.currentMode!),
           screenConfiguration: ScreenConfiguration(
             screenList: IList(),
             displayMode: ScreenDisplayMode.splitHorizontal,